@@ -0,0 +1,652 @@
+//! A 6502 assembler, encoding instructions with [`OPCODES`]: the reverse of the
+//! [disassembler](crate::disasm), reading the syntax it writes.
+//!
+//! Each line has an optional label, then an optional instruction or directive, then an optional
+//! `;` comment:
+//! ```text
+//! start:  LDX #0          ; labels end with `:`
+//! loop:   INX
+//!         CPX #count      ; constants are set with `=`, before they're used
+//!         BNE loop
+//!         JMP (vector)
+//! vector: .word start     ; `.word` and `.byte` write lists of values
+//!         .byte <vector, >vector, $01, %10
+//! ```
+//! Numbers are in hex after `$`, in binary after `%`, and in decimal otherwise. `<` and `>`
+//! take the low and high byte of a value. Mnemonics and registers can be in any case, but
+//! labels are case-sensitive.
+//!
+//! Addresses below `$100` use zero page addressing if the instruction has it, unless they're
+//! written with an `a:` prefix, or are labels that aren't defined until a later line.
+//!
+//! # Examples
+//! ```
+//! # use pretty_assertions::assert_eq;
+//! # use fete::{bus::Bus, rom::Rom, testing::test_rom};
+//! use fete::{asm, cpu::Cpu};
+//!
+//! # let rom = test_rom();
+//! # let bus = Bus::new(Rom::new(&rom).unwrap());
+//! let mut cpu = Cpu::new(bus);
+//! let program = asm!(
+//!     "
+//!         LDA #$05
+//!         JSR double
+//!         BRK
+//!     double:
+//!         ASL A
+//!         RTS
+//!     "
+//! );
+//! assert_eq!(*program, [0xA9, 0x05, 0x20, 0x06, 0x06, 0x00, 0x0A, 0x60]);
+//!
+//! cpu.load_and_run(&program).unwrap();
+//! assert_eq!(cpu.reg_a, 0x0A);
+//! ```
+
+use crate::{
+    cpu::AddressingMode,
+    opcode::{OpCode, OPCODES},
+};
+use core::ops::Deref;
+use snafu::prelude::*;
+
+/// The most labels and constants a program can have.
+pub const MAX_LABELS: usize = 64;
+/// The most bytes [`asm!`](crate::asm!) can assemble.
+pub const MAX_PROGRAM: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Snafu)]
+pub enum Error {
+    #[snafu(display("expected {expected} on line {line}"))]
+    Syntax { line: usize, expected: &'static str },
+    #[snafu(display(
+        "line {line} isn't an instruction, or the instruction can't take its operand"
+    ))]
+    UnknownInstruction { line: usize },
+    #[snafu(display("line {line} uses a label that isn't defined"))]
+    UnknownLabel { line: usize },
+    #[snafu(display("line {line} defines a label that's already defined"))]
+    DuplicateLabel { line: usize },
+    #[snafu(display("the value on line {line} is out of range"))]
+    OutOfRange { line: usize },
+    #[snafu(display("the program has more than {MAX_LABELS} labels"))]
+    TooManyLabels,
+    #[snafu(display("the program doesn't fit in the buffer"))]
+    Full,
+}
+
+/// Assembles a program to run at `origin`, writing it to `out` and returning its length.
+///
+/// # Errors
+/// Errors if the program is malformed, or doesn't fit in `out`.
+pub fn assemble(origin: u16, src: &str, out: &mut [u8]) -> Result<usize, Error> {
+    let mut asm = Assembler {
+        labels: [None; MAX_LABELS],
+        line: 0,
+    };
+    // the first pass finds where the labels are, and the second writes the program
+    asm.pass(origin, src, &mut Output { buf: None, len: 0 })?;
+    let mut out = Output {
+        buf: Some(out),
+        len: 0,
+    };
+    asm.pass(origin, src, &mut out)?;
+    Ok(out.len)
+}
+
+/// An assembled program, in a fixed-size buffer; usually made with [`asm!`](crate::asm!).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Program<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Program<N> {
+    /// Assembles a program to run at `origin`; see [`assemble`].
+    ///
+    /// # Errors
+    /// Errors if the program is malformed, or is more than `N` bytes.
+    pub fn assemble(origin: u16, src: &str) -> Result<Self, Error> {
+        let mut bytes = [0; N];
+        let len = assemble(origin, src, &mut bytes)?;
+        Ok(Self { bytes, len })
+    }
+}
+
+impl<const N: usize> Deref for Program<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Assembles a program, by default to run at `$0600` where
+/// [`Cpu::load`](crate::cpu::Cpu::load) puts it, into a [`Program`] of up to [`MAX_PROGRAM`]
+/// bytes.
+///
+/// # Panics
+/// Panics if the program doesn't assemble.
+///
+/// # Examples
+/// ```
+/// # use pretty_assertions::assert_eq;
+/// use fete::asm;
+///
+/// assert_eq!(*asm!("LDA #1"), [0xA9, 0x01]);
+/// assert_eq!(*asm!(0xC000, "loop: JMP loop"), [0x4C, 0x00, 0xC0]);
+/// ```
+#[macro_export]
+macro_rules! asm {
+    ($src:expr) => {
+        $crate::asm!(0x0600, $src)
+    };
+    ($origin:expr, $src:expr) => {
+        $crate::asm::Program::<{ $crate::asm::MAX_PROGRAM }>::assemble($origin, $src).unwrap()
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Label<'a> {
+    name: &'a str,
+    val: u16,
+    /// The line it's defined on.
+    line: usize,
+}
+
+/// A value in an operand.
+#[derive(Debug, Clone, Copy)]
+struct Value {
+    val: u16,
+    /// Whether it was known when the line was first assembled, so zero page addressing can be
+    /// picked for it: it's a number, or a label defined on an earlier line.
+    early: bool,
+}
+
+/// The operand of an instruction, before an addressing mode is picked for it.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    None,
+    Immediate(Value),
+    Direct { val: Value, absolute: bool },
+    X { val: Value, absolute: bool },
+    Y { val: Value, absolute: bool },
+    Indirect(Value),
+    IndirectX(Value),
+    IndirectY(Value),
+}
+
+/// Where a pass writes the program: nowhere in the first pass, which only counts the bytes.
+struct Output<'o> {
+    buf: Option<&'o mut [u8]>,
+    len: usize,
+}
+
+impl Output<'_> {
+    const fn writing(&self) -> bool {
+        self.buf.is_some()
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), Error> {
+        if let Some(buf) = &mut self.buf {
+            *buf.get_mut(self.len).context(FullSnafu)? = byte;
+        }
+        self.len += 1;
+        Ok(())
+    }
+}
+
+struct Assembler<'a> {
+    labels: [Option<Label<'a>>; MAX_LABELS],
+    /// The line being assembled.
+    line: usize,
+}
+
+impl<'a> Assembler<'a> {
+    fn pass(&mut self, origin: u16, src: &'a str, out: &mut Output) -> Result<(), Error> {
+        for (i, text) in src.lines().enumerate() {
+            self.line = i + 1;
+            let text = text.split_once(';').map_or(text, |(code, _)| code);
+            let mut parser = Parser {
+                s: text,
+                pos: 0,
+                line: self.line,
+            };
+            #[allow(clippy::cast_possible_truncation)] // addresses wrap around
+            let pc = origin.wrapping_add(out.len as u16);
+
+            let start = parser.pos;
+            if let Some(name) = parser.ident() {
+                if parser.rest().starts_with(':') {
+                    parser.pos += 1;
+                    if !out.writing() {
+                        self.define(name, pc)?;
+                    }
+                } else if parser.eat("=") {
+                    let val = self.value(&mut parser, out)?;
+                    parser.end()?;
+                    if !out.writing() {
+                        ensure!(val.early, UnknownLabelSnafu { line: self.line });
+                        self.define(name, val.val)?;
+                    }
+                    continue;
+                } else {
+                    parser.pos = start;
+                }
+            }
+
+            parser.skip_space();
+            if parser.eat(".") {
+                self.directive(&mut parser, out)?;
+            } else if let Some(mnemonic) = parser.ident() {
+                self.instruction(&mut parser, mnemonic, pc, out)?;
+            }
+            parser.end()?;
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &'a str, val: u16) -> Result<(), Error> {
+        let line = self.line;
+        ensure!(self.label(name).is_none(), DuplicateLabelSnafu { line });
+        let slot = self
+            .labels
+            .iter_mut()
+            .find(|l| l.is_none())
+            .context(TooManyLabelsSnafu)?;
+        *slot = Some(Label { name, val, line });
+        Ok(())
+    }
+
+    fn label(&self, name: &str) -> Option<Label<'a>> {
+        self.labels
+            .iter()
+            .flatten()
+            .find(|l| l.name == name)
+            .copied()
+    }
+
+    fn value(&self, parser: &mut Parser, out: &Output) -> Result<Value, Error> {
+        let (low, high) = (parser.eat("<"), parser.eat(">"));
+        parser.skip_space();
+        let mut val = if let Some(name) = parser.ident() {
+            match self.label(name) {
+                Some(label) => Value {
+                    val: label.val,
+                    early: label.line <= self.line,
+                },
+                // it might be defined later, which the second pass will know
+                None if !out.writing() => Value {
+                    val: 0,
+                    early: false,
+                },
+                None => return UnknownLabelSnafu { line: self.line }.fail(),
+            }
+        } else {
+            Value {
+                val: parser.number()?,
+                early: true,
+            }
+        };
+
+        if low {
+            val.val &= 0xFF;
+        } else if high {
+            val.val >>= 8;
+        }
+        Ok(val)
+    }
+
+    /// The next value, which must fit in a byte.
+    fn byte(&self, val: Value, out: &Output) -> Result<u8, Error> {
+        // unknown in the first pass, but only the size matters then
+        match u8::try_from(val.val) {
+            Ok(byte) => Ok(byte),
+            Err(_) if !out.writing() => Ok(0),
+            Err(_) => OutOfRangeSnafu { line: self.line }.fail(),
+        }
+    }
+
+    fn directive(&self, parser: &mut Parser, out: &mut Output) -> Result<(), Error> {
+        let directive = parser
+            .ident()
+            .ok_or_else(|| parser.expected("a directive"))?;
+        let word = if directive.eq_ignore_ascii_case("word") {
+            true
+        } else if directive.eq_ignore_ascii_case("byte") {
+            false
+        } else {
+            return Err(parser.expected("`.byte` or `.word`"));
+        };
+
+        loop {
+            let val = self.value(parser, out)?;
+            if word {
+                let [lo, hi] = val.val.to_le_bytes();
+                out.push(lo)?;
+                out.push(hi)?;
+            } else {
+                out.push(self.byte(val, out)?)?;
+            }
+            if !parser.eat(",") {
+                return Ok(());
+            }
+        }
+    }
+
+    fn instruction(
+        &self,
+        parser: &mut Parser,
+        mnemonic: &str,
+        pc: u16,
+        out: &mut Output,
+    ) -> Result<(), Error> {
+        use AddressingMode as Mode;
+
+        let operand = self.operand(parser, out)?;
+        let zero_page = |val: Value, absolute: bool| !absolute && val.early && val.val <= 0xFF;
+        let (modes, val): (&[Mode], _) = match operand {
+            Operand::None => (&[Mode::NoneAddressing], None),
+            Operand::Immediate(val) => (&[Mode::Immediate], Some(val)),
+            Operand::Direct { val, absolute } if zero_page(val, absolute) => {
+                (&[Mode::Relative, Mode::ZeroPage, Mode::Absolute], Some(val))
+            }
+            Operand::Direct { val, .. } => (&[Mode::Relative, Mode::Absolute], Some(val)),
+            Operand::X { val, absolute } if zero_page(val, absolute) => {
+                (&[Mode::ZeroPageX, Mode::AbsoluteX], Some(val))
+            }
+            Operand::X { val, .. } => (&[Mode::AbsoluteX], Some(val)),
+            Operand::Y { val, absolute } if zero_page(val, absolute) => {
+                (&[Mode::ZeroPageY, Mode::AbsoluteY], Some(val))
+            }
+            Operand::Y { val, .. } => (&[Mode::AbsoluteY], Some(val)),
+            Operand::Indirect(val) => (&[Mode::Indirect], Some(val)),
+            Operand::IndirectX(val) => (&[Mode::IndirectX], Some(val)),
+            Operand::IndirectY(val) => (&[Mode::IndirectY], Some(val)),
+        };
+        let opcode = modes
+            .iter()
+            .find_map(|&mode| opcode(mnemonic, mode))
+            .context(UnknownInstructionSnafu { line: self.line })?;
+
+        out.push(opcode.code)?;
+        let Some(val) = val else {
+            return Ok(());
+        };
+        match opcode.mode {
+            Mode::Relative => {
+                let next = pc.wrapping_add(2);
+                #[allow(clippy::cast_possible_wrap)] // backward branches wrap to negative
+                let offset = i8::try_from(val.val.wrapping_sub(next) as i16);
+                match offset {
+                    Ok(offset) => out.push(offset.to_le_bytes()[0]),
+                    Err(_) if !out.writing() => out.push(0),
+                    Err(_) => OutOfRangeSnafu { line: self.line }.fail(),
+                }
+            }
+            mode if mode.size() == 1 => out.push(self.byte(val, out)?),
+            _ => {
+                let [lo, hi] = val.val.to_le_bytes();
+                out.push(lo)?;
+                out.push(hi)
+            }
+        }
+    }
+
+    fn operand(&self, parser: &mut Parser, out: &Output) -> Result<Operand, Error> {
+        parser.skip_space();
+        if parser.rest().is_empty() {
+            return Ok(Operand::None);
+        }
+        if parser.eat("#") {
+            return Ok(Operand::Immediate(self.value(parser, out)?));
+        }
+        if parser.eat("(") {
+            let val = self.value(parser, out)?;
+            if parser.eat(",") {
+                parser.expect("X")?;
+                parser.expect(")")?;
+                return Ok(Operand::IndirectX(val));
+            }
+            parser.expect(")")?;
+            if parser.eat(",") {
+                parser.expect("Y")?;
+                return Ok(Operand::IndirectY(val));
+            }
+            return Ok(Operand::Indirect(val));
+        }
+
+        // the accumulator, unless it's the start of a label
+        let start = parser.pos;
+        if parser.eat("A") && parser.rest().trim().is_empty() {
+            return Ok(Operand::None);
+        }
+        parser.pos = start;
+
+        let absolute = parser.eat("a:");
+        let val = self.value(parser, out)?;
+        if !parser.eat(",") {
+            Ok(Operand::Direct { val, absolute })
+        } else if parser.eat("X") {
+            Ok(Operand::X { val, absolute })
+        } else if parser.eat("Y") {
+            Ok(Operand::Y { val, absolute })
+        } else {
+            Err(parser.expected("`X` or `Y`"))
+        }
+    }
+}
+
+/// The opcode for a mnemonic, in any case, with an addressing mode.
+fn opcode(mnemonic: &str, mode: AddressingMode) -> Option<&'static OpCode> {
+    OPCODES
+        .values()
+        .find(|op| op.mode == mode && op.name.eq_ignore_ascii_case(mnemonic))
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips `token`, if it's next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let next = self.rest().get(..token.len());
+        if next.is_some_and(|next| next.eq_ignore_ascii_case(token)) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), Error> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.expected(token))
+        }
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        self.skip_space();
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(self.expected("the end of the line"))
+        }
+    }
+
+    const fn expected(&self, expected: &'static str) -> Error {
+        Error::Syntax {
+            line: self.line,
+            expected,
+        }
+    }
+
+    /// A label, mnemonic or directive name.
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_space();
+        let rest = self.rest();
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '@') {
+            return None;
+        }
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '@'))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn number(&mut self) -> Result<u16, Error> {
+        let radix = if self.eat("$") {
+            16
+        } else if self.eat("%") {
+            2
+        } else {
+            10
+        };
+
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(rest.len());
+        let num = u16::from_str_radix(&rest[..len], radix)
+            .map_err(|_| self.expected("a number or label"))?;
+        self.pos += len;
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm::disassemble;
+    use pretty_assertions::assert_eq;
+    use std::{string::ToString, vec::Vec};
+
+    fn asm(src: &str) -> Result<Vec<u8>, Error> {
+        let mut buf = [0; 64];
+        let len = assemble(0x0600, src, &mut buf)?;
+        Ok(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn addr_modes() {
+        let src = "
+            ZP = $10
+            nop
+            asl
+            ROL a
+            lda #ZP
+            lda ZP
+            lda ZP,x
+            ldx ZP,Y
+            lda a:ZP
+            lda $1234
+            lda $1234,X
+            lda ZP,Y
+            jmp ($1234)
+            lda ($20,X)
+            lda ($20),Y
+            bne *
+        "
+        .replace('*', "$0600");
+        let lines: Vec<_> = disassemble(0x0600, &asm(&src).unwrap())
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "nop",
+                "asl a",
+                "rol a",
+                "lda #$10",
+                "lda $10",
+                "lda $10,x",
+                "ldx $10,y",
+                "lda a:$0010",
+                "lda $1234",
+                "lda $1234,x",
+                "lda a:$0010,y",
+                "jmp ($1234)",
+                "lda ($20,x)",
+                "lda ($20),y",
+                "bne $0600",
+            ]
+        );
+    }
+
+    #[test]
+    fn labels() {
+        let src = "
+        start:  ldx #0          ; comment
+        loop:   inx
+                stx later
+                bne loop
+                jmp (vector)
+        vector: .word start, $1234
+                .byte <vector, >vector, %11
+        later:
+        ";
+        assert_eq!(
+            asm(src).unwrap(),
+            [
+                0xA2, 0x00, // ldx #0
+                0xE8, // inx
+                0x8E, 0x12, 0x06, // stx later
+                0xD0, 0xFA, // bne loop
+                0x6C, 0x0B, 0x06, // jmp (vector)
+                0x00, 0x06, 0x34, 0x12, // .word
+                0x0B, 0x06, 0x03, // .byte
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            asm("lda #1\nfoo $10"),
+            Err(Error::UnknownInstruction { line: 2 })
+        );
+        assert_eq!(
+            asm("jmp ($10,X)"),
+            Err(Error::UnknownInstruction { line: 1 })
+        );
+        assert_eq!(asm("jmp nowhere"), Err(Error::UnknownLabel { line: 1 }));
+        assert_eq!(
+            asm("a: nop\na: nop"),
+            Err(Error::DuplicateLabel { line: 2 })
+        );
+        assert_eq!(asm("lda #$100"), Err(Error::OutOfRange { line: 1 }));
+        assert_eq!(asm("bne $0700"), Err(Error::OutOfRange { line: 1 }));
+        assert_eq!(
+            asm("lda $10,Z"),
+            Err(Error::Syntax {
+                line: 1,
+                expected: "`X` or `Y`"
+            })
+        );
+        assert_eq!(
+            asm("lda #1 2"),
+            Err(Error::Syntax {
+                line: 1,
+                expected: "the end of the line"
+            })
+        );
+        assert_eq!(asm(".byte 1, 2, 3\n".repeat(22).as_str()), Err(Error::Full));
+    }
+}
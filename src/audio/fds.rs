@@ -0,0 +1,386 @@
+use super::Expansion;
+use crate::state::{fields, State};
+
+/// The Famicom Disk System's wavetable channel: a 64-step, 6-bit waveform with a volume
+/// envelope, and a frequency modulator with its own 32-step table and envelope.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the control registers
+pub struct Fds {
+    wave: [u8; 64],
+    /// `$4089` bit 7: the waveform is writable, and the output is held.
+    wave_writable: bool,
+    /// `$4089` bits 0-1.
+    master_volume: u8,
+
+    /// 12-bit frequency.
+    pitch: u16,
+    /// `$4083` bit 7.
+    wave_halted: bool,
+    /// `$4083` bit 6.
+    envelopes_halted: bool,
+    /// Fixed point; the top 6 bits are the position in the waveform.
+    wave_acc: u32,
+    /// The last output level, held while the waveform is written.
+    level: u16,
+
+    volume: Envelope,
+    mod_envelope: Envelope,
+    /// `$408A`: multiplies the period of both envelopes; `0` disables them.
+    envelope_speed: u8,
+
+    mod_table: [u8; 32],
+    /// Position in the modulation table, in half-steps since each entry is written twice.
+    mod_pos: u8,
+    /// 7-bit signed sweep bias.
+    mod_counter: i8,
+    /// 12-bit frequency.
+    mod_pitch: u16,
+    /// `$4087` bit 7; also makes the table writable.
+    mod_halted: bool,
+    mod_acc: u16,
+}
+
+/// The volume and modulation envelopes: `$4080` and `$4084`.
+#[derive(Debug, Clone)]
+struct Envelope {
+    /// `MDSS SSSS`: bit 7 disables the envelope and sets the gain directly; bit 6 increases it.
+    control: u8,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    const fn new() -> Self {
+        Self {
+            control: 0x80,
+            gain: 0,
+            timer: 0,
+        }
+    }
+
+    const fn write(&mut self, val: u8, master_speed: u8) {
+        self.control = val;
+        if val & 0x80 != 0 {
+            self.gain = val & 0x3F;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    const fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * ((self.control & 0x3F) as u32 + 1) * master_speed as u32;
+    }
+
+    const fn clock(&mut self, master_speed: u8) {
+        if self.control & 0x80 != 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.reset_timer(master_speed);
+
+        if self.control & 0x40 != 0 {
+            if self.gain < 32 {
+                self.gain += 1;
+            }
+        } else if self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+impl Fds {
+    /// A full-volume waveform is about as loud as a full-volume APU pulse.
+    const SCALE: f32 = 0.149_4 / (63.0 * 32.0);
+    /// `$4089`'s master volume, as fractions of 30.
+    const MASTER_VOLUME: [u16; 4] = [30, 20, 15, 12];
+    /// How each modulation table entry changes the counter; `None` resets it.
+    const MOD_STEPS: [Option<i8>; 8] = [
+        Some(0),
+        Some(1),
+        Some(2),
+        Some(4),
+        None,
+        Some(-4),
+        Some(-2),
+        Some(-1),
+    ];
+
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_writable: false,
+            master_volume: 0,
+            pitch: 0,
+            wave_halted: true,
+            envelopes_halted: true,
+            wave_acc: 0,
+            level: 0,
+            volume: Envelope::new(),
+            mod_envelope: Envelope::new(),
+            envelope_speed: 0xE8,
+            mod_table: [0; 32],
+            mod_pos: 0,
+            mod_counter: 0,
+            mod_pitch: 0,
+            mod_halted: true,
+            mod_acc: 0,
+        }
+    }
+
+    /// Reads a register in `$4040..=$4097`; [`None`] for open bus.
+    #[must_use]
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[usize::from(addr - 0x4040)]),
+            0x4090 => Some(self.volume.gain),
+            0x4092 => Some(self.mod_envelope.gain),
+            _ => None,
+        }
+    }
+
+    /// Writes a register in `$4040..=$4097`.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_writable => {
+                self.wave[usize::from(addr - 0x4040)] = val & 0x3F;
+            }
+            0x4080 => self.volume.write(val, self.envelope_speed),
+            0x4082 => self.pitch = (self.pitch & 0x0F00) | u16::from(val),
+            0x4083 => {
+                self.pitch = (self.pitch & 0x00FF) | (u16::from(val & 0x0F) << 8);
+                self.wave_halted = val & 0x80 != 0;
+                self.envelopes_halted = val & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_acc = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.mod_envelope.reset_timer(self.envelope_speed);
+                }
+            }
+            0x4084 => self.mod_envelope.write(val, self.envelope_speed),
+            0x4085 => {
+                // sign-extend the 7-bit value
+                self.mod_counter = i8::from_ne_bytes([val << 1]) >> 1;
+            }
+            0x4086 => self.mod_pitch = (self.mod_pitch & 0x0F00) | u16::from(val),
+            0x4087 => {
+                self.mod_pitch = (self.mod_pitch & 0x00FF) | (u16::from(val & 0x0F) << 8);
+                self.mod_halted = val & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_acc = 0;
+                }
+            }
+            0x4088 if self.mod_halted => {
+                self.mod_table[usize::from(self.mod_pos >> 1)] = val & 0b111;
+                self.mod_pos = (self.mod_pos + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_writable = val & 0x80 != 0;
+                self.master_volume = val & 0b11;
+            }
+            0x408A => self.envelope_speed = val,
+            _ => {}
+        }
+    }
+
+    /// The wave frequency after modulation.
+    fn modulated_pitch(&self) -> u32 {
+        // from the FDS audio page on the NESdev wiki
+        let mut temp = i32::from(self.mod_counter) * i32::from(self.mod_envelope.gain);
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        let pitch = i32::from(self.pitch);
+        let mut temp = pitch * temp;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        u32::try_from(pitch + temp).unwrap_or(0)
+    }
+
+    fn clock_mod(&mut self) {
+        if self.mod_halted || self.mod_pitch == 0 {
+            return;
+        }
+
+        let (acc, overflow) = self.mod_acc.overflowing_add(self.mod_pitch);
+        self.mod_acc = acc;
+        if overflow {
+            match Self::MOD_STEPS[usize::from(self.mod_table[usize::from(self.mod_pos >> 1)])] {
+                Some(step) => {
+                    // the counter is 7 bits, so wrap it to -64..=63
+                    self.mod_counter = (self.mod_counter.wrapping_add(step) << 1) >> 1;
+                }
+                None => self.mod_counter = 0,
+            }
+            self.mod_pos = (self.mod_pos + 1) & 0x3F;
+        }
+    }
+}
+
+impl Default for Fds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expansion for Fds {
+    fn clock(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted && self.envelope_speed != 0 {
+            self.volume.clock(self.envelope_speed);
+            self.mod_envelope.clock(self.envelope_speed);
+        }
+
+        self.clock_mod();
+
+        if !self.wave_halted && !self.wave_writable {
+            self.wave_acc = (self.wave_acc + self.modulated_pitch()) & 0x3F_FFFF;
+            let sample = u16::from(self.wave[(self.wave_acc >> 16) as usize]);
+            let gain = u16::from(self.volume.gain.min(32));
+            self.level = sample * gain * Self::MASTER_VOLUME[usize::from(self.master_volume)] / 30;
+        }
+    }
+
+    fn output(&self) -> f32 {
+        f32::from(self.level) * Self::SCALE
+    }
+}
+
+impl State for Envelope {
+    fields!(control, gain, timer);
+}
+
+impl State for Fds {
+    fields!(
+        wave,
+        wave_writable,
+        master_volume,
+        pitch,
+        wave_halted,
+        envelopes_halted,
+        wave_acc,
+        level,
+        volume,
+        mod_envelope,
+        envelope_speed,
+        mod_table,
+        mod_pos,
+        mod_counter,
+        mod_pitch,
+        mod_halted,
+        mod_acc;
+        valid |m| m.mod_pos < 64
+            && usize::from(m.master_volume) < Self::MASTER_VOLUME.len()
+            && m.mod_table.iter().all(|&step| step < 8)
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn chip_with_square() -> Fds {
+        let mut chip = Fds::new();
+        chip.write(0x4089, 0x80);
+        for i in 0..64 {
+            chip.write(0x4040 + i, if i < 32 { 63 } else { 0 });
+        }
+        chip.write(0x4089, 0x00);
+        chip.write(0x4080, 0x80 | 32); // gain 32
+        chip
+    }
+
+    #[test]
+    fn wave_writes_need_enable() {
+        let mut chip = Fds::new();
+        chip.write(0x4040, 0x3F);
+        assert_eq!(chip.read(0x4040), Some(0));
+        chip.write(0x4089, 0x80);
+        chip.write(0x4040, 0xFF);
+        assert_eq!(chip.read(0x4040), Some(0x3F));
+    }
+
+    #[test]
+    fn plays_wave() {
+        let mut chip = chip_with_square();
+        chip.write(0x4082, 0x00);
+        chip.write(0x4083, 0x01); // a step every 256 cycles
+
+        chip.clock();
+        assert_eq!(chip.level, 63 * 32);
+        for _ in 0..32 * 256 - 1 {
+            chip.clock();
+        }
+        assert_eq!(chip.level, 0);
+
+        chip.write(0x4083, 0x81); // halted
+        chip.clock();
+        assert_eq!(chip.wave_acc, 0);
+    }
+
+    #[test]
+    fn master_volume() {
+        let mut chip = chip_with_square();
+        chip.write(0x4089, 0x03);
+        chip.write(0x4083, 0x01);
+        chip.clock();
+        assert_eq!(chip.level, 63 * 32 * 12 / 30);
+    }
+
+    #[test]
+    fn volume_envelope() {
+        let mut chip = Fds::new();
+        chip.write(0x408A, 1);
+        chip.write(0x4080, 0x40); // increase every 8 cycles
+        chip.write(0x4083, 0x01);
+        for _ in 0..9 {
+            chip.clock();
+        }
+        assert_eq!(chip.read(0x4090), Some(1));
+    }
+
+    #[test]
+    fn modulation() {
+        let mut chip = Fds::new();
+        chip.write(0x4087, 0x80);
+        for _ in 0..32 {
+            chip.write(0x4088, 1); // +1 per step
+        }
+        chip.write(0x4086, 0x00);
+        chip.write(0x4087, 0x08); // overflows every 32 cycles
+        for _ in 0..64 {
+            chip.clock();
+        }
+        assert_eq!(chip.mod_counter, 2);
+
+        chip.write(0x4085, 0x7F);
+        assert_eq!(chip.mod_counter, -1);
+    }
+
+    #[test]
+    fn modulated_pitch() {
+        let mut chip = Fds::new();
+        chip.write(0x4082, 0x00);
+        chip.write(0x4083, 0x01); // 256
+        chip.write(0x4084, 0x80 | 32);
+        chip.write(0x4085, 8);
+        // 8 * 32 / 16 = 16; 256 * 16 / 64 = 64
+        assert_eq!(chip.modulated_pitch(), 256 + 64);
+    }
+}
@@ -0,0 +1,25 @@
+//! Expansion audio chips found on cartridges.
+//!
+//! The 2A03's own APU isn't emulated yet, so these are mixed on their own by
+//! [`Bus::audio_sample`](crate::bus::Bus::audio_sample).
+
+pub mod fds;
+pub mod n163;
+pub mod sunsoft5b;
+pub mod vrc6;
+pub mod vrc7;
+
+pub use fds::Fds;
+pub use n163::N163;
+pub use sunsoft5b::Sunsoft5b;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+/// A sound generator clocked by the CPU.
+pub trait Expansion {
+    /// Advances the chip by a single CPU cycle.
+    fn clock(&mut self);
+
+    /// The current output level, on the same scale as the APU's mixer output (`0.0..=1.0`).
+    fn output(&self) -> f32;
+}
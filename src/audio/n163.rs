@@ -0,0 +1,204 @@
+use super::Expansion;
+use crate::state::{fields, State};
+use core::cell::Cell;
+
+/// The Namco 163's wavetable synthesizer: up to 8 channels playing 4-bit samples out of the
+/// 128 bytes of RAM they share with their own registers.
+///
+/// The chip only updates one channel every 15 CPU cycles, so enabling more channels lowers the
+/// rate each is updated at. The hardware outputs the channels one after another; this averages
+/// them instead, which is what that multiplexing sounds like after filtering.
+#[derive(Debug, Clone)]
+pub struct N163 {
+    ram: [u8; 128],
+    /// `$F800`: `IAAA AAAA`. A [`Cell`] since reading through the data port also increments it.
+    addr: Cell<u8>,
+    disabled: bool,
+    /// CPU cycles until the next channel update.
+    divider: u8,
+    /// The channel updated next. Channels are updated from 7 downwards.
+    current: usize,
+    outputs: [i16; 8],
+}
+
+impl N163 {
+    /// A full-volume channel is about as loud as a full-volume APU pulse.
+    const SCALE: f32 = 0.149_4 / 120.0;
+    const CYCLES_PER_UPDATE: u8 = 15;
+
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            ram: [0; 128],
+            addr: Cell::new(0),
+            disabled: false,
+            divider: Self::CYCLES_PER_UPDATE,
+            current: 7,
+            outputs: [0; 8],
+        }
+    }
+
+    /// Sets the RAM address accessed through the data port (`$F800`). Bit 7 enables auto-increment.
+    pub fn set_addr(&mut self, val: u8) {
+        self.addr.set(val);
+    }
+
+    /// Silences the chip (bit 6 of `$E000`).
+    pub const fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// Reads RAM through the data port (`$4800`).
+    #[must_use]
+    pub fn read(&self) -> u8 {
+        let val = self.peek();
+        self.increment();
+        val
+    }
+
+    /// Reads RAM through the data port without incrementing the address.
+    #[must_use]
+    pub fn peek(&self) -> u8 {
+        self.ram[usize::from(self.addr.get() & 0x7F)]
+    }
+
+    /// Writes RAM through the data port (`$4800`).
+    pub fn write(&mut self, val: u8) {
+        self.ram[usize::from(self.addr.get() & 0x7F)] = val;
+        self.increment();
+    }
+
+    fn increment(&self) {
+        let addr = self.addr.get();
+        if addr & 0x80 != 0 {
+            self.addr.set(0x80 | (addr.wrapping_add(1) & 0x7F));
+        }
+    }
+
+    /// The number of enabled channels, from the top 3 bits of `$7F`.
+    const fn channels(&self) -> usize {
+        ((self.ram[0x7F] >> 4) & 0b111) as usize + 1
+    }
+
+    fn update(&mut self, ch: usize) {
+        let regs = 0x40 + ch * 8;
+        let reg = |i: usize| u32::from(self.ram[regs + i]);
+
+        let freq = reg(0) | (reg(2) << 8) | ((reg(4) & 0b11) << 16);
+        let length = (256 - (reg(4) & 0xFC)) << 16;
+        let phase = (reg(1) | (reg(3) << 8) | (reg(5) << 16)) + freq;
+        let phase = phase % length;
+
+        let [lo, mid, hi, _] = phase.to_le_bytes();
+        self.ram[regs + 1] = lo;
+        self.ram[regs + 3] = mid;
+        self.ram[regs + 5] = hi;
+
+        let sample = usize::from(hi.wrapping_add(self.ram[regs + 6]));
+        let nibble = (self.ram[sample >> 1] >> ((sample & 1) * 4)) & 0x0F;
+        let volume = self.ram[regs + 7] & 0x0F;
+
+        self.outputs[ch] = (i16::from(nibble) - 8) * i16::from(volume);
+    }
+}
+
+impl Default for N163 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expansion for N163 {
+    fn clock(&mut self) {
+        if self.disabled {
+            return;
+        }
+
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = Self::CYCLES_PER_UPDATE;
+
+            let lowest = 8 - self.channels();
+            if self.current < lowest {
+                self.current = 7;
+            }
+            self.update(self.current);
+            self.current = if self.current == lowest {
+                7
+            } else {
+                self.current - 1
+            };
+        }
+    }
+
+    fn output(&self) -> f32 {
+        if self.disabled {
+            return 0.0;
+        }
+
+        let active = &self.outputs[8 - self.channels()..];
+        let sum: i16 = active.iter().sum();
+        f32::from(sum) / f32::from(u8::try_from(active.len()).unwrap_or(8)) * Self::SCALE
+    }
+}
+
+impl State for N163 {
+    fields!(ram, addr, disabled, divider, current, outputs; valid |m| m.current < 8);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn auto_increment() {
+        let mut chip = N163::new();
+        chip.set_addr(0x80 | 0x7E);
+        chip.write(0x12);
+        chip.write(0x34);
+        chip.write(0x56); // wraps to $00
+
+        chip.set_addr(0x7E);
+        assert_eq!(chip.read(), 0x12);
+        assert_eq!(chip.read(), 0x12);
+
+        chip.set_addr(0x80 | 0x7F);
+        assert_eq!(chip.read(), 0x34);
+        assert_eq!(chip.read(), 0x56);
+    }
+
+    #[test]
+    fn channel_7() {
+        let mut chip = N163::new();
+        // a 4-sample wave: F, 0, 0, 0
+        chip.set_addr(0x00);
+        chip.write(0x0F);
+
+        chip.set_addr(0x80 | 0x78);
+        for val in [
+            0x00,        // freq lo
+            0x00,        // phase lo
+            0x00,        // freq mid
+            0x00,        // phase mid
+            0xFC | 0x01, // length 4, freq hi: one sample per update
+            0x00,        // phase hi
+            0x00,        // wave address
+            0x0F,        // volume; 1 channel
+        ] {
+            chip.write(val);
+        }
+
+        // samples 1-3 are 0
+        for _ in 0..3 {
+            for _ in 0..15 {
+                chip.clock();
+            }
+            assert_eq!(chip.outputs[7], -8 * 15);
+        }
+        for _ in 0..15 {
+            chip.clock();
+        }
+        assert_eq!(chip.outputs[7], 7 * 15);
+    }
+}
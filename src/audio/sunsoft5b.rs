@@ -0,0 +1,331 @@
+use super::Expansion;
+use crate::state::{fields, State};
+
+/// The Sunsoft 5B's PSG, a licensed YM2149 (itself an AY-3-8910 clone): three square waves
+/// that can each be mixed with a shared noise generator and driven by a shared envelope.
+#[derive(Debug, Clone)]
+pub struct Sunsoft5b {
+    /// Selected by `$C000`.
+    selected: u8,
+    regs: [u8; 16],
+
+    /// CPU cycles until the next tone/noise/envelope step; tones step every 16 cycles.
+    divider: u8,
+    tone_counters: [u16; 3],
+    tone_levels: [bool; 3],
+
+    noise_counter: u8,
+    /// The noise generator only steps every other tone step.
+    noise_half: bool,
+    /// 17-bit LFSR.
+    noise: u32,
+
+    envelope_counter: u16,
+    /// `0..32`, counting up when attacking.
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5b {
+    /// A full-volume channel is about as loud as a full-volume APU pulse.
+    const SCALE: f32 = 0.149_4 / 65535.0;
+    const CYCLES_PER_STEP: u8 = 16;
+
+    /// Logarithmic volume levels, 1.5dB apart; 4-bit channel volumes use every other one.
+    const VOLUME: [u16; 32] = [
+        0, 369, 438, 521, 619, 735, 874, 1039, 1234, 1467, 1744, 2072, 2463, 2927, 3479, 4135,
+        4914, 5841, 6942, 8250, 9806, 11654, 13851, 16462, 19565, 23253, 27636, 32845, 39037,
+        46395, 55141, 65535,
+    ];
+
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            selected: 0,
+            regs: [0; 16],
+            divider: Self::CYCLES_PER_STEP,
+            tone_counters: [0; 3],
+            tone_levels: [false; 3],
+            noise_counter: 0,
+            noise_half: false,
+            noise: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false,
+        }
+    }
+
+    /// Selects the register written by [`Self::write`] (`$C000`).
+    pub const fn select(&mut self, val: u8) {
+        self.selected = val;
+    }
+
+    /// Writes the selected register (`$E000`). The upper four bits of the select value must be
+    /// clear, or the write is ignored.
+    pub const fn write(&mut self, val: u8) {
+        if self.selected > 0x0F {
+            return;
+        }
+        self.regs[self.selected as usize] = val;
+        if self.selected == 0x0D {
+            self.restart_envelope();
+        }
+    }
+
+    const fn restart_envelope(&mut self) {
+        self.envelope_counter = 0;
+        self.envelope_attack = self.regs[0x0D] & 0b0100 != 0;
+        self.envelope_step = 0;
+        self.envelope_holding = false;
+    }
+
+    /// The 12-bit period of a tone channel; a period of 0 acts like 1.
+    const fn tone_period(&self, ch: usize) -> u16 {
+        let period = (self.regs[ch * 2] as u16) | ((self.regs[ch * 2 + 1] as u16 & 0x0F) << 8);
+        if period == 0 {
+            1
+        } else {
+            period
+        }
+    }
+
+    const fn noise_period(&self) -> u8 {
+        let period = self.regs[6] & 0x1F;
+        if period == 0 {
+            1
+        } else {
+            period
+        }
+    }
+
+    const fn envelope_period(&self) -> u16 {
+        let period = (self.regs[0x0B] as u16) | ((self.regs[0x0C] as u16) << 8);
+        if period == 0 {
+            1
+        } else {
+            period
+        }
+    }
+
+    fn step_tones(&mut self) {
+        for ch in 0..3 {
+            self.tone_counters[ch] += 1;
+            if self.tone_counters[ch] >= self.tone_period(ch) {
+                self.tone_counters[ch] = 0;
+                self.tone_levels[ch] = !self.tone_levels[ch];
+            }
+        }
+    }
+
+    const fn step_noise(&mut self) {
+        self.noise_half = !self.noise_half;
+        if self.noise_half {
+            return;
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period() {
+            self.noise_counter = 0;
+            let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+            self.noise = (self.noise >> 1) | (feedback << 16);
+        }
+    }
+
+    /// Steps the envelope; called twice per tone step, since it has 32 levels rather than 16.
+    const fn step_envelope(&mut self) {
+        self.envelope_counter += 1;
+        if self.envelope_counter < self.envelope_period() {
+            return;
+        }
+        self.envelope_counter = 0;
+
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        // `CAAH`: continue, attack, alternate, hold
+        let shape = self.regs[0x0D];
+        if shape & 0b1000 == 0 {
+            // without continue, the envelope always ends at 0
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+            return;
+        }
+
+        if shape & 0b0010 != 0 {
+            self.envelope_attack = !self.envelope_attack;
+        }
+        if shape & 0b0001 != 0 {
+            self.envelope_holding = true;
+        } else {
+            self.envelope_step = 0;
+        }
+    }
+
+    /// The envelope's current position in [`Self::VOLUME`].
+    const fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn channel_output(&self, ch: usize) -> u16 {
+        let mixer = self.regs[7];
+        let tone_off = mixer & (1 << ch) != 0;
+        let noise_off = mixer & (0b1000 << ch) != 0;
+        let high = (tone_off || self.tone_levels[ch]) && (noise_off || self.noise & 1 != 0);
+        if !high {
+            return 0;
+        }
+
+        let amplitude = self.regs[8 + ch];
+        let level = if amplitude & 0x10 != 0 {
+            self.envelope_level()
+        } else {
+            match amplitude & 0x0F {
+                0 => 0,
+                v => v * 2 + 1,
+            }
+        };
+        Self::VOLUME[usize::from(level)]
+    }
+}
+
+impl Default for Sunsoft5b {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expansion for Sunsoft5b {
+    fn clock(&mut self) {
+        self.divider -= 1;
+        if self.divider == Self::CYCLES_PER_STEP / 2 {
+            self.step_envelope();
+        } else if self.divider == 0 {
+            self.divider = Self::CYCLES_PER_STEP;
+            self.step_tones();
+            self.step_noise();
+            self.step_envelope();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        let sum: f32 = (0..3).map(|ch| f32::from(self.channel_output(ch))).sum();
+        sum * Self::SCALE
+    }
+}
+
+impl State for Sunsoft5b {
+    fields!(
+        selected,
+        regs,
+        divider,
+        tone_counters,
+        tone_levels,
+        noise_counter,
+        noise_half,
+        noise,
+        envelope_counter,
+        envelope_step,
+        envelope_attack,
+        envelope_holding,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write(chip: &mut Sunsoft5b, reg: u8, val: u8) {
+        chip.select(reg);
+        chip.write(val);
+    }
+
+    #[test]
+    fn tone() {
+        let mut chip = Sunsoft5b::new();
+        write(&mut chip, 0, 2); // period 2
+        write(&mut chip, 7, 0b11_1110); // only tone A
+        write(&mut chip, 8, 0x0F);
+
+        assert_eq!(chip.channel_output(0), 0);
+        for _ in 0..2 * 16 {
+            chip.clock();
+        }
+        assert_eq!(chip.channel_output(0), 65535);
+        for _ in 0..2 * 16 {
+            chip.clock();
+        }
+        assert_eq!(chip.channel_output(0), 0);
+    }
+
+    #[test]
+    fn disabled_channel_uses_volume() {
+        let mut chip = Sunsoft5b::new();
+        write(&mut chip, 7, 0b11_1111);
+        write(&mut chip, 9, 0x07);
+        assert_eq!(chip.channel_output(1), Sunsoft5b::VOLUME[15]);
+    }
+
+    #[test]
+    fn envelope_hold() {
+        let mut chip = Sunsoft5b::new();
+        write(&mut chip, 7, 0b11_1111);
+        write(&mut chip, 10, 0x10);
+        write(&mut chip, 11, 1);
+        write(&mut chip, 13, 0b1101); // attack, then hold at the top
+
+        assert_eq!(chip.envelope_level(), 0);
+        for _ in 0..31 * 8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 31);
+        for _ in 0..64 * 8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 31);
+        assert_eq!(chip.channel_output(2), 65535);
+    }
+
+    #[test]
+    fn envelope_triangle() {
+        let mut chip = Sunsoft5b::new();
+        write(&mut chip, 11, 1);
+        write(&mut chip, 13, 0b1110); // up, down, up...
+
+        for _ in 0..31 * 8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 31);
+        for _ in 0..8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 31);
+        for _ in 0..31 * 8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 0);
+    }
+
+    #[test]
+    fn envelope_without_continue() {
+        let mut chip = Sunsoft5b::new();
+        write(&mut chip, 11, 1);
+        write(&mut chip, 13, 0b0100); // attack once, then silence
+
+        for _ in 0..32 * 8 {
+            chip.clock();
+        }
+        assert_eq!(chip.envelope_level(), 0);
+    }
+}
@@ -0,0 +1,234 @@
+use super::Expansion;
+use crate::state::{fields, State};
+
+/// The VRC6's two pulse channels and sawtooth channel.
+#[derive(Debug, Clone, Default)]
+pub struct Vrc6 {
+    pulse: [Pulse; 2],
+    saw: Saw,
+    halt: bool,
+    /// Right shift applied to every channel's period; set by the frequency scaling bits in `$9003`.
+    shift: u8,
+}
+
+impl Vrc6 {
+    /// A full-volume VRC6 pulse is about as loud as a full-volume APU pulse.
+    const SCALE: f32 = 0.149_4 / 15.0;
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes an audio register. `reg` must already have the board's address line swap applied.
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0x9000..=0x9002 => self.pulse[0].write(reg & 0b11, val),
+            0x9003 => {
+                self.halt = val & 0b001 != 0;
+                self.shift = if val & 0b100 != 0 {
+                    8
+                } else if val & 0b010 != 0 {
+                    4
+                } else {
+                    0
+                };
+            }
+            0xA000..=0xA002 => self.pulse[1].write(reg & 0b11, val),
+            0xB000..=0xB002 => self.saw.write(reg & 0b11, val),
+            _ => {}
+        }
+    }
+}
+
+impl Expansion for Vrc6 {
+    fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+        self.pulse[0].clock(self.shift);
+        self.pulse[1].clock(self.shift);
+        self.saw.clock(self.shift);
+    }
+
+    fn output(&self) -> f32 {
+        let sum = self.pulse[0].output() + self.pulse[1].output() + self.saw.output();
+        f32::from(sum) * Self::SCALE
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignores the duty cycle, outputting the volume constantly.
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    divider: u16,
+    step: u8,
+}
+
+impl Default for Pulse {
+    fn default() -> Self {
+        Self {
+            volume: 0,
+            duty: 0,
+            constant: false,
+            enabled: false,
+            period: 0,
+            divider: 0,
+            step: 15,
+        }
+    }
+}
+
+impl Pulse {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.constant = val & 0x80 != 0;
+                self.duty = (val >> 4) & 0b111;
+                self.volume = val & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | u16::from(val),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(val & 0x0F) << 8);
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    const fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    const fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Saw {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    divider: u16,
+    /// Counts divider clocks; the accumulator is bumped on every other clock and reset on the 14th.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Saw {
+    fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => self.rate = val & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | u16::from(val),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(val & 0x0F) << 8);
+                self.enabled = val & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    const fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    const fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl State for Pulse {
+    fields!(volume, duty, constant, enabled, period, divider, step);
+}
+
+impl State for Saw {
+    fields!(rate, enabled, period, divider, step, accumulator);
+}
+
+impl State for Vrc6 {
+    fields!(pulse, saw, halt, shift);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pulse_duty() {
+        let mut chip = Vrc6::new();
+        chip.write(0x9000, 0b0011_1111); // 4/16 duty, full volume
+        chip.write(0x9001, 0x00);
+        chip.write(0x9002, 0x80);
+
+        let mut high = 0;
+        for _ in 0..16 {
+            chip.clock();
+            if chip.pulse[0].output() != 0 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 4);
+    }
+
+    #[test]
+    fn saw_resets() {
+        let mut chip = Vrc6::new();
+        chip.write(0xB000, 0x08);
+        chip.write(0xB002, 0x80);
+
+        for _ in 0..12 {
+            chip.clock();
+        }
+        assert_eq!(chip.saw.accumulator, 6 * 0x08);
+        chip.clock();
+        chip.clock();
+        assert_eq!(chip.saw.accumulator, 0);
+    }
+
+    #[test]
+    fn halt() {
+        let mut chip = Vrc6::new();
+        chip.write(0x9000, 0x8F);
+        chip.write(0x9002, 0x80);
+        chip.write(0x9003, 0x01);
+        chip.clock();
+        assert_eq!(chip.pulse[0].step, 15);
+    }
+}
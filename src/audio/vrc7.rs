@@ -0,0 +1,628 @@
+//! The VRC7's sound chip, a cut-down Yamaha YM2413 (OPLL) with 6 FM channels and no rhythm mode.
+//!
+//! Like the real chip, operators are computed in the log domain: a log-sine lookup is added to
+//! the attenuation and converted back through an exponent table. The envelope rates are an
+//! approximation of the hardware's, close enough to sound right without its cycle-exact counters.
+
+use super::Expansion;
+use crate::state::{self, fields, Reader, State, Writer};
+
+/// The built-in instruments, as dumped from a decapped VRC7. Instrument 0 is the user-defined patch.
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+/// Frequency multipliers, doubled so that `MULT = 0` (×½) is an integer.
+const MULT: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/// Key scale level attenuation by the top 4 bits of the F-number, in 0.75dB steps.
+const KSL: [u8; 16] = [
+    0, 24, 32, 37, 40, 43, 45, 47, 48, 50, 51, 52, 53, 54, 55, 56,
+];
+
+/// Vibrato offsets, in units of `fnum >> 6`.
+const PM: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
+
+/// Maximum attenuation, in 0.375dB steps.
+const MAX_ATTENUATION: u32 = 127;
+
+const ONE_Q32: i128 = 1 << 32;
+const PI_Q32: i128 = 13_493_037_705;
+const LN2_Q32: i128 = 2_977_044_472;
+
+/// `sin(x)` for `x` in `0..=π/2`, in Q32 fixed point.
+const fn sin_q32(x: i128) -> i128 {
+    let x2 = (x * x) >> 32;
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 1;
+    while n < 10 {
+        term = -((term * x2) >> 32) / ((2 * n) * (2 * n + 1));
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+/// `-log2(v)` for `v` in `0..=1` (Q32), in 1/256ths.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // always in 0..4096
+const fn neg_log2_q8(mut v: i128) -> u16 {
+    let mut int = 0;
+    while v < ONE_Q32 {
+        v <<= 1;
+        int += 1;
+    }
+
+    let mut frac = 0;
+    let mut i = 0;
+    while i < 16 {
+        v = (v * v) >> 32;
+        frac <<= 1;
+        if v >= 2 * ONE_Q32 {
+            v >>= 1;
+            frac |= 1;
+        }
+        i += 1;
+    }
+
+    (((int << 16) - frac + (1 << 7)) >> 8) as u16
+}
+
+/// `e^y` for small `y` (Q32), in Q32.
+const fn exp_q32(y: i128) -> i128 {
+    let mut term = ONE_Q32;
+    let mut sum = ONE_Q32;
+    let mut n = 1;
+    while n < 12 {
+        term = ((term * y) >> 32) / n;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+/// `-log2(sin(x))` over the first quarter of a sine wave, in 1/256ths.
+static LOG_SIN: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        // sample at the middle of each step, so the table never hits sin(0)
+        table[i] = neg_log2_q8(sin_q32((2 * i as i128 + 1) * PI_Q32 / 1024));
+        i += 1;
+    }
+    table
+};
+
+/// `2^(i/256) - 1`, scaled by 1024.
+static EXP: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let val = ((exp_q32(i as i128 * LN2_Q32 / 256) - ONE_Q32) * 1024 + ONE_Q32 / 2) >> 32;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // always in 0..1024
+        {
+            table[i] = val as u16;
+        }
+        i += 1;
+    }
+    table
+};
+
+/// Looks up a sine wave at `phase` (10 bits per cycle), attenuated by `att` 0.375dB steps.
+///
+/// Returns a value in `-4095..=4095`.
+fn wave(phase: u32, att: u32, half_wave: bool) -> i32 {
+    let phase = phase & 0x3FF;
+    let negative = phase & 0x200 != 0;
+    if negative && half_wave {
+        return 0;
+    }
+
+    let quarter = if phase & 0x100 == 0 { phase } else { !phase } & 0xFF;
+    let total = u32::from(LOG_SIN[quarter as usize]) + (att << 4);
+    let shift = total >> 8;
+    if shift >= 13 {
+        return 0;
+    }
+
+    let val = i32::from((EXP[(!total & 0xFF) as usize] | 0x400) << 1) >> shift;
+    if negative {
+        -val
+    } else {
+        val
+    }
+}
+
+/// The decoded parameters of one operator in a patch.
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the register layout
+struct Operator {
+    am: bool,
+    vibrato: bool,
+    /// Holds the sustain level until key off; otherwise the tone keeps decaying (percussive).
+    sustained: bool,
+    ksr: bool,
+    mult: u8,
+    ksl: u8,
+    half_wave: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl Operator {
+    const fn new(patch: [u8; 8], carrier: bool) -> Self {
+        let i = carrier as usize;
+        Self {
+            am: patch[i] & 0x80 != 0,
+            vibrato: patch[i] & 0x40 != 0,
+            sustained: patch[i] & 0x20 != 0,
+            ksr: patch[i] & 0x10 != 0,
+            mult: patch[i] & 0x0F,
+            ksl: patch[2 + i] >> 6,
+            half_wave: patch[3] & (0x08 << i) != 0,
+            attack: patch[4 + i] >> 4,
+            decay: patch[4 + i] & 0x0F,
+            sustain_level: patch[6 + i] >> 4,
+            release: patch[6 + i] & 0x0F,
+        }
+    }
+}
+
+/// Scales a 4-bit envelope rate by the key scale rate; `0` stays `0`.
+const fn rate(rate: u8, rks: u8) -> u8 {
+    if rate == 0 {
+        0
+    } else {
+        let rate = rate * 4 + rks;
+        if rate > 63 {
+            63
+        } else {
+            rate
+        }
+    }
+}
+
+/// Attenuation change per sample at an effective `rate`, in Q16 0.375dB steps.
+const fn env_step(rate: u8) -> u32 {
+    if rate == 0 {
+        0
+    } else {
+        (4 + (rate as u32 & 0b11)) << (rate >> 2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    /// 18-bit phase accumulator.
+    phase: u32,
+    /// Envelope attenuation, in Q16 0.375dB steps.
+    env: u32,
+    stage: Stage,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            phase: 0,
+            env: MAX_ATTENUATION << 16,
+            stage: Stage::Off,
+        }
+    }
+
+    const fn key_on(&mut self) {
+        self.phase = 0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+        }
+    }
+
+    fn envelope(&mut self, op: &Operator, rks: u8, release: u8) {
+        let max = MAX_ATTENUATION << 16;
+        match self.stage {
+            Stage::Attack => {
+                let rate = rate(op.attack, rks);
+                if rate >= 60 {
+                    self.env = 0;
+                } else {
+                    let dec =
+                        ((u64::from(self.env >> 3) + (1 << 16)) * u64::from(env_step(rate))) >> 16;
+                    self.env = self
+                        .env
+                        .saturating_sub(u32::try_from(dec).unwrap_or(u32::MAX));
+                }
+                if self.env == 0 {
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain = (u32::from(op.sustain_level) * 8) << 16;
+                self.env += env_step(rate(op.decay, rks));
+                if self.env >= sustain {
+                    self.env = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => {
+                if !op.sustained {
+                    self.env += env_step(rate(op.release, rks));
+                }
+            }
+            Stage::Release => self.env += env_step(rate(release, rks)),
+            Stage::Off => {}
+        }
+
+        if self.env >= max {
+            self.env = max;
+            if self.stage != Stage::Attack {
+                self.stage = Stage::Off;
+            }
+        }
+    }
+
+    fn advance(&mut self, op: &Operator, fnum: u32, block: u8) {
+        let inc = ((fnum * MULT[usize::from(op.mult)]) << block) >> 2;
+        self.phase = (self.phase + inc) & 0x3_FFFF;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Slot,
+    carrier: Slot,
+    /// The last two modulator outputs, for self-feedback.
+    feedback: [i32; 2],
+}
+
+impl Channel {
+    const fn new() -> Self {
+        Self {
+            fnum: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Slot::new(),
+            carrier: Slot::new(),
+            feedback: [0; 2],
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)] // phases are masked to 10 bits by `wave`
+    fn sample(&mut self, patch: [u8; 8], am: u32, pm: usize) -> i32 {
+        let ops = [Operator::new(patch, false), Operator::new(patch, true)];
+
+        let kcode = (self.block << 1) | u8::from(self.fnum & 0x100 != 0);
+        // in 0.375dB steps; KSL 3 applies all of it, and each step below halves it
+        let ksl = (u32::from(KSL[usize::from(self.fnum >> 5)]) * 2)
+            .saturating_sub(16 * u32::from(7 - self.block));
+
+        let release = |op: &Operator| {
+            if self.sustain {
+                5
+            } else if op.sustained {
+                op.release
+            } else {
+                7
+            }
+        };
+        let atten = |op: &Operator, env: u32| {
+            let ksl = match op.ksl {
+                0 => 0,
+                n => ksl >> (3 - n),
+            };
+            (env >> 16) + ksl + if op.am { am } else { 0 }
+        };
+
+        for (slot, op) in [&mut self.modulator, &mut self.carrier]
+            .into_iter()
+            .zip(&ops)
+        {
+            let rks = if op.ksr { kcode } else { kcode >> 2 };
+            let fnum = if op.vibrato {
+                i32::from(self.fnum) + ((i32::from(self.fnum >> 6) * PM[pm]) >> 1)
+            } else {
+                i32::from(self.fnum)
+            };
+
+            slot.envelope(op, rks, release(op));
+            slot.advance(op, u32::try_from(fnum).unwrap_or(0), self.block);
+        }
+
+        let feedback = match patch[3] & 0b111 {
+            0 => 0,
+            fb => (self.feedback[0] + self.feedback[1]) >> (9 - fb),
+        };
+        let total_level = u32::from(patch[2] & 0x3F) * 2;
+        let modulator = if self.modulator.stage == Stage::Off {
+            0
+        } else {
+            wave(
+                (self.modulator.phase >> 8).wrapping_add(feedback as u32),
+                atten(&ops[0], self.modulator.env) + total_level,
+                ops[0].half_wave,
+            )
+        };
+        self.feedback = [self.feedback[1], modulator];
+
+        if self.carrier.stage == Stage::Off {
+            return 0;
+        }
+        wave(
+            (self.carrier.phase >> 8).wrapping_add((modulator >> 1) as u32),
+            atten(&ops[1], self.carrier.env) + u32::from(self.volume) * 8,
+            ops[1].half_wave,
+        )
+    }
+}
+
+/// The VRC7's FM synthesizer.
+#[derive(Debug, Clone)]
+pub struct Vrc7 {
+    selected: u8,
+    custom: [u8; 8],
+    channels: [Channel; 6],
+    /// Held in reset by bit 6 of `$E000`.
+    silenced: bool,
+    /// CPU cycles until the next sample; the chip produces one every 36.
+    divider: u8,
+    /// Counts samples, driving the tremolo and vibrato.
+    lfo: u32,
+    sample: i16,
+}
+
+impl Vrc7 {
+    /// A single channel at full volume is about as loud as a full-volume APU pulse.
+    const SCALE: f32 = 0.149_4 / 4096.0;
+    const CYCLES_PER_SAMPLE: u8 = 36;
+
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            selected: 0,
+            custom: [0; 8],
+            channels: [Channel::new(); 6],
+            silenced: false,
+            divider: Self::CYCLES_PER_SAMPLE,
+            lfo: 0,
+            sample: 0,
+        }
+    }
+
+    /// Selects the register the next data write goes to (`$9010`).
+    pub const fn select(&mut self, reg: u8) {
+        self.selected = reg;
+    }
+
+    /// Writes to the selected register (`$9030`).
+    pub fn write(&mut self, val: u8) {
+        let reg = self.selected;
+        let ch = usize::from(reg & 0x0F);
+
+        match reg {
+            0x00..=0x07 => self.custom[usize::from(reg)] = val,
+            0x10..=0x15 => {
+                let ch = &mut self.channels[ch];
+                ch.fnum = (ch.fnum & 0x100) | u16::from(val);
+            }
+            0x20..=0x25 => {
+                let ch = &mut self.channels[ch];
+                ch.fnum = (ch.fnum & 0xFF) | (u16::from(val & 1) << 8);
+                ch.block = (val >> 1) & 0b111;
+                ch.sustain = val & 0x20 != 0;
+
+                let key = val & 0x10 != 0;
+                if key && !ch.key {
+                    ch.modulator.key_on();
+                    ch.carrier.key_on();
+                } else if !key && ch.key {
+                    ch.modulator.key_off();
+                    ch.carrier.key_off();
+                }
+                ch.key = key;
+            }
+            0x30..=0x35 => {
+                let ch = &mut self.channels[ch];
+                ch.instrument = val >> 4;
+                ch.volume = val & 0x0F;
+            }
+            _ => log::warn!("ignoring write to VRC7 audio register: {reg:#02x}"),
+        }
+    }
+
+    /// Holds the chip in reset, silencing it, while `silenced` is set.
+    pub const fn set_silenced(&mut self, silenced: bool) {
+        if silenced && !self.silenced {
+            *self = Self {
+                silenced,
+                ..Self::new()
+            };
+        }
+        self.silenced = silenced;
+    }
+
+    fn generate(&mut self) {
+        self.lfo = self.lfo.wrapping_add(1);
+
+        let am = match (self.lfo >> 9) % 26 {
+            step @ 0..=13 => step,
+            step => 26 - step,
+        };
+        let pm = ((self.lfo >> 10) & 0b111) as usize;
+
+        let mut sum = 0;
+        for ch in &mut self.channels {
+            let patch = match ch.instrument {
+                0 => self.custom,
+                i => PATCHES[usize::from(i - 1)],
+            };
+            sum += ch.sample(patch, am, pm);
+        }
+
+        self.sample = i16::try_from(sum).unwrap_or(if sum < 0 { i16::MIN } else { i16::MAX });
+    }
+}
+
+impl Default for Vrc7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Expansion for Vrc7 {
+    fn clock(&mut self) {
+        if self.silenced {
+            return;
+        }
+
+        self.divider -= 1;
+        if self.divider == 0 {
+            self.divider = Self::CYCLES_PER_SAMPLE;
+            self.generate();
+        }
+    }
+
+    fn output(&self) -> f32 {
+        f32::from(self.sample) * Self::SCALE
+    }
+}
+
+impl State for Stage {
+    fn save(&self, w: &mut Writer) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+        *self = match r.array()? {
+            [0] => Self::Attack,
+            [1] => Self::Decay,
+            [2] => Self::Sustain,
+            [3] => Self::Release,
+            [4] => Self::Off,
+            _ => return Err(state::Error::Corrupt),
+        };
+        Ok(())
+    }
+}
+
+impl State for Slot {
+    fields!(phase, env, stage; valid |m| m.env <= MAX_ATTENUATION << 16);
+}
+
+impl State for Channel {
+    fields!(
+        fnum,
+        block,
+        key,
+        sustain,
+        instrument,
+        volume,
+        modulator,
+        carrier,
+        feedback;
+        valid |m| m.fnum < 0x200 && m.block < 8 && m.instrument < 16
+    );
+}
+
+impl State for Vrc7 {
+    fields!(selected, custom, channels, silenced, divider, lfo, sample);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tables() {
+        assert_eq!(LOG_SIN[0], 2137);
+        assert_eq!(LOG_SIN[255], 0);
+        assert_eq!(EXP[0], 0);
+        assert_eq!(EXP[255], 1018);
+    }
+
+    #[test]
+    fn wave_shape() {
+        assert_eq!(wave(0x100, 0, false), 4084);
+        assert_eq!(wave(0x300, 0, false), -4084);
+        assert_eq!(wave(0x300, 0, true), 0);
+        // 6dB = 16 steps halves the output
+        assert_eq!(wave(0x100, 16, false), 2042);
+        assert_eq!(wave(0x100, MAX_ATTENUATION, false), 16);
+    }
+
+    fn write(chip: &mut Vrc7, reg: u8, val: u8) {
+        chip.select(reg);
+        chip.write(val);
+    }
+
+    fn peak(chip: &mut Vrc7, cycles: usize) -> i16 {
+        let mut peak = 0;
+        for _ in 0..cycles {
+            chip.clock();
+            peak = peak.max(chip.sample.abs());
+        }
+        peak
+    }
+
+    #[test]
+    fn key_on_and_off() {
+        let mut chip = Vrc7::new();
+        assert_eq!(peak(&mut chip, 36 * 100), 0);
+
+        write(&mut chip, 0x10, 0xAC);
+        write(&mut chip, 0x30, 0x30); // instrument 3 (piano), full volume
+        write(&mut chip, 0x20, 0x18); // key on, block 4
+        assert!(peak(&mut chip, 36 * 1000) > 1000);
+
+        write(&mut chip, 0x20, 0x08); // key off
+        peak(&mut chip, 36 * 50_000);
+        assert_eq!(peak(&mut chip, 36 * 100), 0);
+    }
+
+    #[test]
+    fn silenced() {
+        let mut chip = Vrc7::new();
+        write(&mut chip, 0x10, 0xAC);
+        write(&mut chip, 0x30, 0x30);
+        write(&mut chip, 0x20, 0x18);
+        chip.set_silenced(true);
+        assert_eq!(peak(&mut chip, 36 * 1000), 0);
+        assert!(!chip.channels[0].key);
+    }
+}
@@ -1,25 +1,341 @@
-use crate::rom::Rom;
-use core::{ops::RangeInclusive, ptr::NonNull};
+use crate::{
+    mapper::{Board, Mapper, Nrom},
+    rom::{fds::SIDE_SIZE, Mirroring, Rom},
+    state::{self, Reader, State, Writer},
+};
+use core::{cell::Cell, ops::RangeInclusive, ptr::NonNull};
+
+/// The most PRG-RAM a cartridge can have; there's no allocator to size it at runtime.
+pub const PRG_RAM_MAX: usize = 0x8000;
+/// The most CHR-RAM a cartridge can have.
+pub const CHR_RAM_MAX: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("cartridge has no battery-backed RAM"))]
+    NoBattery,
+    #[snafu(display("expected {expected} bytes of save data, got {actual}"))]
+    SaveSize { expected: usize, actual: usize },
+    #[snafu(display("cartridge has no disk drive"))]
+    NoDiskDrive,
+    #[snafu(display("no disk is inserted"))]
+    NoDisk,
+    #[snafu(display("disk has {sides} sides, so there's no side {side}"))]
+    NoSuchSide { side: usize, sides: usize },
+}
+
+/// Which bus an [`Access`] was on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Space {
+    Cpu,
+    /// The PPU's, where the pattern tables are.
+    Ppu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// A read of an instruction's bytes.
+    Execute,
+}
+
+/// A memory access, recorded for [watchpoints](crate::debugger).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub space: Space,
+    pub kind: AccessKind,
+    pub addr: u16,
+    /// The byte read or written.
+    pub val: u8,
+}
+
+/// The most accesses recorded at once; more than any instruction and interrupt make together.
+const ACCESS_LOG_SIZE: usize = 16;
+
+/// Where the PPU is in the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PpuPosition {
+    /// Frames since power-on.
+    pub frame: u64,
+    /// `0..262`, with 241 the start of vblank and 261 the pre-render line.
+    pub scanline: u16,
+    /// `0..341`.
+    pub dot: u16,
+}
 
 #[derive(Debug, Clone)]
 pub struct Bus<'rom> {
     pub vram: [u8; 2048],
+    /// Cartridge PRG-RAM; only the first `prg_ram_len` bytes are used, of which the first
+    /// `prg_nvram_len` are battery-backed.
+    prg_ram: [u8; PRG_RAM_MAX],
+    prg_ram_len: usize,
+    prg_nvram_len: usize,
+    /// Cartridge CHR-RAM, used instead of CHR ROM when the cartridge has none; only the first
+    /// `chr_ram_len` bytes are used.
+    chr_ram: [u8; CHR_RAM_MAX],
+    chr_ram_len: usize,
     pub rom: Rom<'rom>,
+    pub mapper: Board,
+    /// CPU cycles since power-on.
+    cycles: u64,
+    /// Whether accesses are being recorded into `accesses`.
+    recording: bool,
+    accesses: [Cell<Option<Access>>; ACCESS_LOG_SIZE],
 }
 
 impl<'rom> Bus<'rom> {
     pub const RAM_RANGE: RangeInclusive<u16> = (0x0000..=0x1FFF);
     pub const ROM_RANGE: RangeInclusive<u16> = (0x8000..=0xFFFF);
+    /// Where mappers may put PRG ROM banks.
+    pub const PRG_RANGE: RangeInclusive<u16> = (0x6000..=0xFFFF);
     pub const PPU_REGISTER_RANGE: RangeInclusive<u16> = (0x2000..=0x3FFF);
+    pub const CARTRIDGE_RANGE: RangeInclusive<u16> = (0x4020..=0xFFFF);
+    /// Where a ROM's trainer is loaded.
+    pub const TRAINER_ADDR: u16 = 0x7000;
+    /// PPU dots in a scanline.
+    pub const DOTS: u64 = 341;
+    /// Scanlines in an NTSC frame.
+    pub const SCANLINES: u64 = 262;
 
+    /// Creates a bus with the board for the ROM's mapper. Unsupported mappers fall back to NROM.
     #[must_use]
-    pub const fn new(rom: Rom<'rom>) -> Self {
-        Self {
+    #[allow(clippy::large_stack_arrays, clippy::large_stack_frames)] // the bus is meant to live on the stack
+    pub fn new(rom: Rom<'rom>) -> Self {
+        let mapper = Board::new(&rom).unwrap_or_else(|| {
+            log::warn!("unsupported mapper {}, falling back to NROM", rom.mapper);
+            Board::Nrom(Nrom::new(&rom))
+        });
+
+        let prg_ram_len = ram_len("PRG", rom.prg_ram_size + rom.prg_nvram_size, PRG_RAM_MAX);
+        let chr_ram_len = if rom.chr_rom.is_empty() {
+            ram_len("CHR", rom.chr_ram_size + rom.chr_nvram_size, CHR_RAM_MAX)
+        } else {
+            0
+        };
+
+        let mut bus = Self {
             vram: [0; 2048],
+            prg_ram: [0; PRG_RAM_MAX],
+            prg_ram_len,
+            prg_nvram_len: rom.prg_nvram_size.min(prg_ram_len),
+            chr_ram: [0; CHR_RAM_MAX],
+            chr_ram_len,
             rom,
+            mapper,
+            cycles: 0,
+            recording: false,
+            accesses: Default::default(),
+        };
+
+        if let Some(trainer) = bus.rom.trainer.clone() {
+            bus.load_trainer(&trainer);
+        }
+        bus
+    }
+
+    /// Copies the ROM's trainer into PRG-RAM at `$7000`, like copiers did before reset.
+    fn load_trainer(&mut self, trainer: &[u8]) {
+        for (addr, &val) in (Self::TRAINER_ADDR..).zip(trainer) {
+            let Some(offset) = self.prg_ram_addr(addr) else {
+                log::warn!("no PRG RAM at {addr:#02x} to load the trainer into");
+                return;
+            };
+            self.prg_ram[offset] = val;
+        }
+    }
+
+    /// The battery-backed PRG-RAM, for saving to a `.sav` file. [`None`] if the cartridge has
+    /// no battery.
+    #[must_use]
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        (self.prg_nvram_len > 0).then(|| &self.prg_ram[..self.prg_nvram_len])
+    }
+
+    /// Restores battery-backed PRG-RAM from a `.sav` file.
+    ///
+    /// # Errors
+    /// Errors if the cartridge has no battery, or if `sav` isn't the size of its battery-backed
+    /// PRG-RAM.
+    pub fn load_battery_ram(&mut self, sav: &[u8]) -> Result<(), Error> {
+        if self.prg_nvram_len == 0 {
+            return Err(Error::NoBattery);
+        }
+        if sav.len() != self.prg_nvram_len {
+            return Err(Error::SaveSize {
+                expected: self.prg_nvram_len,
+                actual: sav.len(),
+            });
+        }
+
+        self.prg_ram[..self.prg_nvram_len].copy_from_slice(sav);
+        Ok(())
+    }
+
+    /// Inserts a side of the ROM's disk image into the Famicom Disk System's drive, or ejects
+    /// the disk with [`None`]. Sides count from 0 for disk 1 side A.
+    ///
+    /// # Errors
+    /// Errors if the cartridge isn't an FDS, or the image doesn't have the side.
+    pub fn insert_disk(&mut self, side: Option<usize>) -> Result<(), Error> {
+        let Board::Fds(drive) = &mut self.mapper else {
+            return Err(Error::NoDiskDrive);
+        };
+        let Some(side) = side else {
+            drive.eject();
+            return Ok(());
+        };
+
+        let disk = self.rom.disk.as_ref().ok_or(Error::NoDiskDrive)?;
+        let data = disk.side(side).ok_or_else(|| Error::NoSuchSide {
+            side,
+            sides: disk.sides(),
+        })?;
+        drive.insert(side, data);
+        Ok(())
+    }
+
+    /// Copies the side in the FDS drive into `out`, in the image's layout, so the game's writes
+    /// can be saved. Returns the side's number; its data starts at `side * SIDE_SIZE` in a raw
+    /// image, after the 16-byte header in a `.fds` file.
+    ///
+    /// # Errors
+    /// Errors if the cartridge isn't an FDS, or no disk is inserted.
+    pub fn save_disk(&self, out: &mut [u8; SIDE_SIZE]) -> Result<usize, Error> {
+        let Board::Fds(drive) = &self.mapper else {
+            return Err(Error::NoDiskDrive);
+        };
+        let side = drive.side().ok_or(Error::NoDisk)?;
+        drive.save(out);
+        Ok(side)
+    }
+
+    /// Reads a byte from the pattern tables (`$0000..=$1FFF` on the PPU bus), through the mapper.
+    #[must_use]
+    pub fn chr_read(&self, addr: u16) -> u8 {
+        let val = if self.chr_ram_len == 0 {
+            self.chr_offset(addr)
+                .map_or(0, |offset| self.rom.chr_rom[offset])
+        } else {
+            self.chr_ram[self.mapper.chr_addr(addr) % self.chr_ram_len]
+        };
+        self.record(Space::Ppu, AccessKind::Read, addr, val);
+        val
+    }
+
+    /// Where a pattern table address is in CHR ROM, with the banks that are currently switched
+    /// in, or `None` if the cartridge has CHR-RAM instead.
+    #[must_use]
+    pub fn chr_offset(&self, addr: u16) -> Option<usize> {
+        if self.chr_ram_len != 0 || self.rom.chr_rom.is_empty() {
+            return None;
+        }
+        Some(self.mapper.chr_addr(addr) % self.rom.chr_rom.len())
+    }
+
+    /// Writes a byte to the pattern tables, if they're mapped to CHR-RAM.
+    pub fn chr_write(&mut self, addr: u16, val: u8) {
+        self.record(Space::Ppu, AccessKind::Write, addr, val);
+        if self.chr_ram_len == 0 {
+            log::warn!("attempt to write to CHR ROM: {addr:#02x}");
+            return;
+        }
+        let offset = self.mapper.chr_addr(addr) % self.chr_ram_len;
+        self.chr_ram[offset] = val;
+    }
+
+    /// Maps a CPU address to an offset into PRG-RAM, if the mapper has RAM there.
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        if self.prg_ram_len == 0 {
+            return None;
+        }
+        Some(self.mapper.prg_ram_addr(addr)? % self.prg_ram_len)
+    }
+
+    /// Advances the devices on the bus by the given number of CPU cycles.
+    pub fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            self.mapper.clock();
+        }
+        self.cycles += u64::from(cycles);
+    }
+
+    /// The number of CPU cycles since power-on.
+    #[must_use]
+    pub const fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Where the PPU is, worked out from the cycle count: it runs three dots for every CPU
+    /// cycle.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)] // both are taken modulo something smaller
+    pub const fn ppu_position(&self) -> PpuPosition {
+        let dots = self.cycles * 3;
+        let line = dots / Self::DOTS;
+        PpuPosition {
+            frame: line / Self::SCANLINES,
+            scanline: (line % Self::SCANLINES) as u16,
+            dot: (dots % Self::DOTS) as u16,
+        }
+    }
+
+    /// Starts or stops recording the accesses made through [`Self::mem_read`],
+    /// [`Self::mem_write`], [`Self::fetch`], [`Self::chr_read`] and [`Self::chr_write`], and
+    /// forgets the ones recorded so far.
+    pub fn record_accesses(&mut self, recording: bool) {
+        self.recording = recording;
+        self.clear_accesses();
+    }
+
+    /// The accesses recorded since they were last cleared, oldest first. Only the first 16 are
+    /// kept.
+    pub fn accesses(&self) -> impl Iterator<Item = Access> + '_ {
+        self.accesses.iter().map_while(Cell::get)
+    }
+
+    /// Forgets the accesses recorded so far.
+    pub fn clear_accesses(&self) {
+        for access in &self.accesses {
+            access.set(None);
+        }
+    }
+
+    fn record(&self, space: Space, kind: AccessKind, addr: u16, val: u8) {
+        if !self.recording {
+            return;
+        }
+        if let Some(slot) = self.accesses.iter().find(|a| a.get().is_none()) {
+            slot.set(Some(Access {
+                space,
+                kind,
+                addr,
+                val,
+            }));
         }
     }
 
+    /// Whether any device is asserting the IRQ line.
+    #[must_use]
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// The current nametable mirroring; the mapper's if it controls it, otherwise the header's.
+    #[must_use]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring().unwrap_or(self.rom.mirroring)
+    }
+
+    /// The current audio output level, in `0.0..=1.0`.
+    ///
+    /// This only mixes cartridge expansion audio for now; the APU isn't emulated yet.
+    #[must_use]
+    pub fn audio_sample(&self) -> f32 {
+        self.mapper.audio()
+    }
+
     #[must_use]
     pub fn mirror(&self, addr: u16) -> Option<&u8> {
         // SAFETY: all ptrs come from valid references.
@@ -35,19 +351,14 @@ impl<'rom> Bus<'rom> {
         if Self::RAM_RANGE.contains(&addr) {
             let mirror_down_addr = addr & 0b0000_0111_1111_1111;
             self.vram.get(mirror_down_addr as usize).map(NonNull::from)
-        } else if Self::ROM_RANGE.contains(&addr) {
-            let mirror_down_addr = {
-                let addr = addr - Self::ROM_RANGE.start();
-                if self.rom.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-                    addr % 0x4000 // mirror, if needed
-                } else {
-                    addr
-                }
-            };
-            self.rom
-                .prg_rom
-                .get(mirror_down_addr as usize)
-                .map(NonNull::from)
+        } else if Self::PRG_RANGE.contains(&addr) {
+            if let Some(offset) = self.prg_offset(addr) {
+                self.rom.prg_rom.get(offset).map(NonNull::from)
+            } else {
+                self.prg_ram
+                    .get(self.prg_ram_addr(addr)?)
+                    .map(NonNull::from)
+            }
         } else if Self::PPU_REGISTER_RANGE.contains(&addr) {
             // let _mirror_down_addr = addr & 0b0010_0000_0000_0111;
             todo!("PPU is not supported yet")
@@ -62,18 +373,81 @@ impl<'rom> Bus<'rom> {
     /// This does not increment the program counter; use [`Cpu::take`](crate::cpu::Cpu::take) for that.
     #[must_use]
     pub fn mem_read(&self, addr: u16) -> u8 {
+        let val = self.read(addr);
+        self.record(Space::Cpu, AccessKind::Read, addr, val);
+        val
+    }
+
+    /// Reads a byte of an instruction. The same as [`Self::mem_read`], but recorded as an
+    /// [`AccessKind::Execute`].
+    #[must_use]
+    pub fn fetch(&self, addr: u16) -> u8 {
+        let val = self.read(addr);
+        self.record(Space::Cpu, AccessKind::Execute, addr, val);
+        val
+    }
+
+    fn read(&self, addr: u16) -> u8 {
         if let Some(&val) = self.mirror(addr) {
             val
+        } else if let Some(val) = Self::CARTRIDGE_RANGE
+            .contains(&addr)
+            .then(|| self.mapper.read(addr))
+            .flatten()
+        {
+            val
         } else {
             log::warn!("ignoring memory read at: {addr:#02x}");
             0
         }
     }
 
+    /// Reads a byte from memory without side effects, for tracers and debuggers: registers
+    /// that change when read, like the FDS's disk status, are left as they are.
+    ///
+    /// PPU and APU registers read as `0`.
+    #[must_use]
+    pub fn peek(&self, addr: u16) -> u8 {
+        if Self::PPU_REGISTER_RANGE.contains(&addr) {
+            0
+        } else if let Some(&val) = self.mirror(addr) {
+            val
+        } else if Self::CARTRIDGE_RANGE.contains(&addr) {
+            self.mapper.peek(addr).unwrap_or(0)
+        } else {
+            0
+        }
+    }
+
+    /// Reads a little-endian, 16-bit number from memory without side effects; see
+    /// [`Self::peek`].
+    #[must_use]
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+
+    /// Where an address is in PRG ROM, with the banks that are currently switched in, or
+    /// `None` if it isn't mapped to PRG ROM.
+    #[must_use]
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        if !Self::PRG_RANGE.contains(&addr) {
+            return None;
+        }
+        let offset = self.mapper.prg_addr(addr)?;
+        Some(offset % self.rom.prg_rom.len().max(1))
+    }
+
     /// Writes a byte to memory.
     pub fn mem_write(&mut self, addr: u16, val: u8) {
-        if Self::ROM_RANGE.contains(&addr) {
-            log::warn!("attempt to write to cartridge ROM: {addr:#02x}");
+        self.record(Space::Cpu, AccessKind::Write, addr, val);
+        if let Some(offset) = self.prg_ram_addr(addr) {
+            self.prg_ram[offset] = val;
+            return;
+        }
+
+        if Self::CARTRIDGE_RANGE.contains(&addr) {
+            self.mapper.write(addr, val);
+            self.copy_nsf_bank(addr);
             return;
         }
 
@@ -84,6 +458,28 @@ impl<'rom> Bus<'rom> {
         }
     }
 
+    /// Copies the bank an FDS tune just switched into RAM; see [`Nsf`](crate::mapper::Nsf).
+    fn copy_nsf_bank(&mut self, addr: u16) {
+        let Board::Nsf(nsf) = &self.mapper else {
+            return;
+        };
+        let Some(window) = nsf.ram_window(addr) else {
+            return;
+        };
+        for offset in 0..0x1000 {
+            let val = nsf
+                .bank_addr(window, offset)
+                .and_then(|i| self.rom.prg_rom.get(i))
+                .copied()
+                .unwrap_or(0);
+            if let Some(ram) =
+                self.prg_ram[..self.prg_ram_len].get_mut(window + usize::from(offset))
+            {
+                *ram = val;
+            }
+        }
+    }
+
     #[must_use]
     /// Reads a little-endian, 16-bit number from memory.
     ///
@@ -103,3 +499,146 @@ impl<'rom> Bus<'rom> {
         self.mem_write(addr + 1, hi);
     }
 }
+
+/// Clamps a cartridge RAM size to the space the bus has for it.
+fn ram_len(kind: &str, size: usize, max: usize) -> usize {
+    if size > max {
+        log::warn!("{size:#X} bytes of {kind} RAM is too much, truncating");
+        max
+    } else {
+        size
+    }
+}
+
+// only the RAM the cartridge has is saved
+impl State for Bus<'_> {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.vram);
+        w.bytes(&self.prg_ram[..self.prg_ram_len]);
+        w.bytes(&self.chr_ram[..self.chr_ram_len]);
+        self.cycles.save(w);
+        self.mapper.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+        r.bytes(&mut self.vram)?;
+        r.bytes(&mut self.prg_ram[..self.prg_ram_len])?;
+        r.bytes(&mut self.chr_ram[..self.chr_ram_len])?;
+        self.cycles.load(r)?;
+        self.mapper.load(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+    use crate::testing::{create_rom, TestRom};
+    use pretty_assertions::assert_eq;
+
+    fn rom(flags_6: u8) -> Vec<u8> {
+        create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, flags_6, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: (flags_6 & 0b0100 != 0).then(|| (0..=255).cycle().take(512).collect()),
+            prg_rom: vec![0; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        })
+    }
+
+    #[test]
+    fn chr_ram() {
+        let raw = rom(0);
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        bus.chr_write(0x1234, 0x56);
+        assert_eq!(bus.chr_read(0x1234), 0x56);
+    }
+
+    #[test]
+    fn chr_rom_is_read_only() {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![7; CHR_ROM_PAGE_SIZE],
+        });
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        bus.chr_write(0x0000, 0x56);
+        assert_eq!(bus.chr_read(0x0000), 7);
+    }
+
+    #[test]
+    fn prg_ram() {
+        let raw = rom(0);
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        bus.mem_write(0x6000, 0x12);
+        bus.mem_write(0x7FFF, 0x34);
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+        assert_eq!(bus.mem_read(0x7FFF), 0x34);
+        assert_eq!(bus.battery_ram(), None);
+        assert_eq!(bus.load_battery_ram(&[0; 0x2000]), Err(Error::NoBattery));
+    }
+
+    #[test]
+    fn battery_ram() {
+        let raw = rom(0b0010);
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        bus.mem_write(0x6001, 0xAB);
+        let sav = bus.battery_ram().unwrap().to_vec();
+        assert_eq!(sav.len(), 0x2000);
+        assert_eq!(sav[1], 0xAB);
+
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        assert_eq!(
+            bus.load_battery_ram(&sav[..0x1000]),
+            Err(Error::SaveSize {
+                expected: 0x2000,
+                actual: 0x1000
+            })
+        );
+        bus.load_battery_ram(&sav).unwrap();
+        assert_eq!(bus.mem_read(0x6001), 0xAB);
+    }
+
+    #[test]
+    fn disk() {
+        let mut image = crate::rom::fds::test::side();
+        image.extend(crate::rom::fds::test::side());
+        image[SIDE_SIZE + 21] = 1; // side B
+        let bios = [0; crate::rom::fds::BIOS_SIZE];
+        let mut bus = Bus::new(Rom::fds(&bios, &image).unwrap());
+
+        let mut out: Box<[u8; SIDE_SIZE]> = vec![0; SIDE_SIZE].try_into().unwrap();
+        assert_eq!(bus.save_disk(&mut out), Ok(0));
+        assert_eq!(out.as_slice(), &image[..SIDE_SIZE]);
+
+        bus.insert_disk(Some(1)).unwrap();
+        assert_eq!(bus.save_disk(&mut out), Ok(1));
+        assert_eq!(out.as_slice(), &image[SIDE_SIZE..]);
+
+        assert_eq!(
+            bus.insert_disk(Some(2)),
+            Err(Error::NoSuchSide { side: 2, sides: 2 })
+        );
+        bus.insert_disk(None).unwrap();
+        assert_eq!(bus.save_disk(&mut out), Err(Error::NoDisk));
+
+        let raw = rom(0);
+        let mut bus = Bus::new(Rom::new(&raw).unwrap());
+        assert_eq!(bus.insert_disk(Some(0)), Err(Error::NoDiskDrive));
+    }
+
+    #[test]
+    fn trainer() {
+        let raw = rom(0b0100);
+        let bus = Bus::new(Rom::new(&raw).unwrap());
+        assert_eq!(bus.mem_read(0x6FFF), 0);
+        assert_eq!(bus.mem_read(0x7000), 0);
+        assert_eq!(bus.mem_read(0x7001), 1);
+        assert_eq!(bus.mem_read(0x71FF), 0xFF);
+        assert_eq!(bus.mem_read(0x7200), 0);
+    }
+}
@@ -0,0 +1,305 @@
+//! A code/data logger, which marks what each byte of PRG and CHR ROM is used for as the CPU
+//! runs, for telling code from data when disassembling or hacking a ROM.
+//!
+//! The log is kept in FCEUX's `.cdl` format: a byte of [`Prg`] flags for each byte of PRG ROM,
+//! then a byte of [`Chr`] flags for each byte of CHR ROM. Addresses are mapped to offsets into
+//! [`Rom::prg_rom`] and [`Rom::chr_rom`] through the mapper, with the banks switched in when
+//! they're accessed, so the same address in different banks is logged separately.
+//!
+//! [`CodeDataLog::tick`] logs code, data and CHR reads. The DMC and rendering aren't emulated,
+//! so [`Prg::PCM`] and [`Chr::RENDERED`] are only logged when something calls
+//! [`CodeDataLog::mark_prg`] or [`CodeDataLog::mark_chr`] with them.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, rom::Rom};
+//! use fete::{
+//!     asm,
+//!     cdl::{CodeDataLog, Prg},
+//!     cpu::Cpu,
+//! };
+//!
+//! # let mut raw = fete::testing::test_rom();
+//! // in PRG ROM, at $8000
+//! let code = asm!(0x8000, "LDA table\nBRK\ntable: .byte 1");
+//! raw[16..16 + code.len()].copy_from_slice(&code);
+//! let rom = Rom::new(&raw).unwrap();
+//! let mut cdl = CodeDataLog::new(&rom);
+//! let mut cpu = Cpu::new(Bus::new(rom));
+//! cpu.pc = 0x8000;
+//!
+//! while !cdl.tick(&mut cpu).unwrap() {}
+//! assert_eq!(cdl.prg(0), Prg::CODE);
+//! assert_eq!(cdl.prg(4), Prg::DATA);
+//! assert_eq!(
+//!     cdl.as_bytes().len(),
+//!     cpu.bus.rom.prg_rom.len() + cpu.bus.rom.chr_rom.len()
+//! );
+//! ```
+
+use crate::{
+    bus::{AccessKind, Bus, Space},
+    cpu::{self, AddressingMode, Cpu},
+    opcode::OPCODES,
+    rom::Rom,
+};
+use alloc::{vec, vec::Vec};
+use bitflags::bitflags;
+use snafu::prelude::*;
+
+/// `JMP ($nnnn)`, whose target is logged as [`Prg::INDIRECT_CODE`].
+const JMP_INDIRECT: u8 = 0x6C;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Snafu)]
+pub enum Error {
+    #[snafu(display("the log is {actual} bytes, but the ROM needs {expected}"))]
+    WrongSize { expected: usize, actual: usize },
+}
+
+bitflags! {
+    /// What a byte of PRG ROM has been used for.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Prg: u8 {
+        /// Run as part of an instruction.
+        const CODE = 0x01;
+        /// Read by an instruction.
+        const DATA = 0x02;
+        /// Which 8 KiB window of the CPU's bus it was last accessed through; see
+        /// [`Prg::window`].
+        const WINDOW = 0x0C;
+        /// The target of a `JMP ($nnnn)`.
+        const INDIRECT_CODE = 0x10;
+        /// Read through a pointer, with `($nn,X)` or `($nn),Y` addressing.
+        const INDIRECT_DATA = 0x20;
+        /// Played as a DMC sample.
+        const PCM = 0x40;
+    }
+}
+
+impl Prg {
+    /// The start of the window it was last accessed through: `$8000`, `$A000`, `$C000` or
+    /// `$E000`.
+    #[must_use]
+    pub const fn window(self) -> u16 {
+        0x8000 + (self.bits() & Self::WINDOW.bits()) as u16 / 4 * 0x2000
+    }
+
+    /// The window bits for an address.
+    const fn of_window(addr: u16) -> Self {
+        #[allow(clippy::cast_possible_truncation)] // only two bits are left
+        Self::from_bits_retain(((addr >> 13) & 0b11) as u8 * 4)
+    }
+}
+
+bitflags! {
+    /// What a byte of CHR ROM has been used for.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Chr: u8 {
+        /// Drawn by the PPU.
+        const RENDERED = 0x01;
+        /// Read by the CPU, through `PPUDATA`.
+        const READ = 0x02;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLog {
+    /// The `.cdl` file: the PRG ROM's flags, then the CHR ROM's.
+    log: Vec<u8>,
+    prg_len: usize,
+}
+
+impl CodeDataLog {
+    /// An empty log for a ROM.
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            log: vec![0; rom.prg_rom.len() + rom.chr_rom.len()],
+            prg_len: rom.prg_rom.len(),
+        }
+    }
+
+    /// Carries on from a `.cdl` file.
+    ///
+    /// # Errors
+    /// Errors if the file isn't the right size for the ROM.
+    pub fn from_cdl(rom: &Rom, cdl: &[u8]) -> Result<Self, Error> {
+        let mut log = Self::new(rom);
+        ensure!(
+            cdl.len() == log.log.len(),
+            WrongSizeSnafu {
+                expected: log.log.len(),
+                actual: cdl.len()
+            }
+        );
+        log.log.copy_from_slice(cdl);
+        Ok(log)
+    }
+
+    /// The log as a `.cdl` file.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.log
+    }
+
+    /// The flags for an offset into PRG ROM.
+    #[must_use]
+    pub fn prg(&self, offset: usize) -> Prg {
+        self.log[..self.prg_len]
+            .get(offset)
+            .map_or(Prg::empty(), |&flags| Prg::from_bits_retain(flags))
+    }
+
+    /// The flags for an offset into CHR ROM.
+    #[must_use]
+    pub fn chr(&self, offset: usize) -> Chr {
+        self.log[self.prg_len..]
+            .get(offset)
+            .map_or(Chr::empty(), |&flags| Chr::from_bits_retain(flags))
+    }
+
+    /// Adds flags to the byte of PRG ROM at an address on the CPU's bus, if there's one there.
+    pub fn mark_prg(&mut self, bus: &Bus, addr: u16, flags: Prg) {
+        let Some(offset) = bus.prg_offset(addr) else {
+            return;
+        };
+        if let Some(byte) = self.log[..self.prg_len].get_mut(offset) {
+            let old = Prg::from_bits_retain(*byte).difference(Prg::WINDOW);
+            *byte = (old | flags | Prg::of_window(addr)).bits();
+        }
+    }
+
+    /// Adds flags to the byte of CHR ROM at an address on the PPU's bus, if there's one there.
+    pub fn mark_chr(&mut self, bus: &Bus, addr: u16, flags: Chr) {
+        let Some(offset) = bus.chr_offset(addr) else {
+            return;
+        };
+        if let Some(byte) = self.log[self.prg_len..].get_mut(offset) {
+            *byte |= flags.bits();
+        }
+    }
+
+    /// Runs an instruction with [`Cpu::tick`], logging the bytes it's made of and the ones it
+    /// reads.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn tick(&mut self, cpu: &mut Cpu) -> Result<bool, cpu::Error> {
+        let pc = cpu.pc;
+        let opcode = cpu.bus.peek(pc);
+        let mode = OPCODES.get(&opcode).map(|op| op.mode);
+        let len = mode.map_or(1, |mode| 1 + mode.size());
+        // logged before it runs, in case it switches the bank it's in
+        for i in 0..len {
+            self.mark_prg(&cpu.bus, pc.wrapping_add(i.into()), Prg::CODE);
+        }
+
+        cpu.bus.record_accesses(true);
+        let result = cpu.tick();
+        let data = if matches!(
+            mode,
+            Some(AddressingMode::IndirectX | AddressingMode::IndirectY)
+        ) {
+            Prg::DATA | Prg::INDIRECT_DATA
+        } else {
+            Prg::DATA
+        };
+        // immediate operands are read like data, but they're part of the instruction
+        let operand = pc.wrapping_add(1)..pc.wrapping_add(len.into());
+        for access in cpu.bus.accesses() {
+            match (access.space, access.kind) {
+                (Space::Cpu, AccessKind::Read) if !operand.contains(&access.addr) => {
+                    self.mark_prg(&cpu.bus, access.addr, data);
+                }
+                (Space::Ppu, AccessKind::Read) => self.mark_chr(&cpu.bus, access.addr, Chr::READ),
+                _ => {}
+            }
+        }
+        cpu.bus.record_accesses(false);
+
+        if opcode == JMP_INDIRECT && result.is_ok() {
+            self.mark_prg(&cpu.bus, cpu.pc, Prg::INDIRECT_CODE);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::test_rom;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn prg() {
+        let mut raw = test_rom();
+        let code = crate::asm!(
+            0x8000,
+            "
+                LDA data
+                LDY #0
+                LDA ($00),Y
+                JMP (vector)
+            data:
+                .byte 1, 2
+            vector:
+                .word target
+            target:
+                NOP
+            "
+        );
+        raw[16..16 + code.len()].copy_from_slice(&code);
+        let rom = Rom::new(&raw).unwrap();
+        let mut cdl = CodeDataLog::new(&rom);
+        let mut cpu = Cpu::new(Bus::new(rom));
+        cpu.bus.mem_write(0x0000, 0x0B);
+        cpu.bus.mem_write(0x0001, 0x80);
+        cpu.pc = 0x8000;
+        for _ in 0..5 {
+            cdl.tick(&mut cpu).unwrap();
+        }
+
+        let flags: Vec<_> = (0..16).map(|offset| cdl.prg(offset)).collect();
+        assert_eq!(
+            flags,
+            [
+                [Prg::CODE; 10].as_slice(),
+                &[Prg::DATA, Prg::DATA | Prg::INDIRECT_DATA],
+                &[Prg::DATA; 2],
+                &[Prg::CODE | Prg::INDIRECT_CODE, Prg::empty()],
+            ]
+            .concat()
+        );
+
+        // in the second bank, which is at $C000
+        cpu.pc = 0xC00E;
+        cdl.tick(&mut cpu).unwrap();
+        assert_eq!(cdl.prg(0x400E), Prg::CODE | Prg::of_window(0xC000));
+        assert_eq!(cdl.prg(0x400E).window(), 0xC000);
+    }
+
+    #[test]
+    fn cdl_file() {
+        let raw = test_rom();
+        let rom = Rom::new(&raw).unwrap();
+        let bus = Bus::new(Rom::new(&raw).unwrap());
+        let mut cdl = CodeDataLog::new(&rom);
+        cdl.mark_chr(&bus, 0x0010, Chr::RENDERED);
+        cdl.mark_chr(&bus, 0x0010, Chr::READ);
+        cdl.mark_prg(&bus, 0xE000, Prg::PCM);
+        assert_eq!(cdl.chr(0x0010), Chr::RENDERED | Chr::READ);
+
+        let file = cdl.as_bytes();
+        assert_eq!(file.len(), rom.prg_rom.len() + rom.chr_rom.len());
+        assert_eq!(file[0x6000], 0x4C);
+        assert_eq!(file[rom.prg_rom.len() + 0x10], 0x03);
+        assert_eq!(CodeDataLog::from_cdl(&rom, file), Ok(cdl.clone()));
+        assert_eq!(
+            CodeDataLog::from_cdl(&rom, &file[1..]),
+            Err(Error::WrongSize {
+                expected: file.len(),
+                actual: file.len() - 1
+            })
+        );
+    }
+}
@@ -214,6 +214,12 @@ mod test {
 
         assert_eq!(cpu.get_op_addr(AddressingMode::Indirect), 0x5678);
         assert_eq!(cpu.pc, 0x0002);
+
+        // the high byte comes from the start of the page, not the next one
+        cpu.bus.mem_write_u16(0x0002, 0x02FF);
+        cpu.bus.mem_write(0x02FF, 0x78);
+        cpu.bus.mem_write(0x0200, 0x56);
+        assert_eq!(cpu.get_op_addr(AddressingMode::Indirect), 0x5678);
     }
 
     #[test]
@@ -222,11 +228,17 @@ mod test {
         let bus = Bus::new(Rom::new(&rom).unwrap());
         let mut cpu = Cpu::new(bus);
         cpu.bus.mem_write(0x0000, 0x12);
-        cpu.bus.mem_write_u16(0x0012, 0x00FF);
+        cpu.bus.mem_write_u16(0x0017, 0x1234);
         cpu.reg_x = 0x05;
 
-        assert_eq!(cpu.get_op_addr(AddressingMode::IndirectX), 0x0005); // wraps around zero-page
+        assert_eq!(cpu.get_op_addr(AddressingMode::IndirectX), 0x1234);
         assert_eq!(cpu.pc, 0x0001);
+
+        // the pointer wraps around the zero page
+        cpu.bus.mem_write(0x0001, 0xFA);
+        cpu.bus.mem_write(0x00FF, 0x78);
+        cpu.bus.mem_write(0x0000, 0x56);
+        assert_eq!(cpu.get_op_addr(AddressingMode::IndirectX), 0x5678);
     }
 
     #[test]
@@ -240,6 +252,26 @@ mod test {
 
         assert_eq!(cpu.get_op_addr(AddressingMode::IndirectY), 0x1239);
         assert_eq!(cpu.pc, 0x0001);
+
+        // the pointer wraps around the zero page
+        cpu.bus.mem_write(0x0001, 0xFF);
+        cpu.bus.mem_write(0x00FF, 0x78);
+        cpu.bus.mem_write(0x0000, 0x56);
+        assert_eq!(cpu.get_op_addr(AddressingMode::IndirectY), 0x567D);
+    }
+
+    #[test]
+    fn op_addr_relative() {
+        let rom = test_rom();
+        let bus = Bus::new(Rom::new(&rom).unwrap());
+        let mut cpu = Cpu::new(bus);
+        cpu.bus.mem_write(0x0010, 0x05);
+        cpu.bus.mem_write(0x0011, 0xFB); // -5
+        cpu.pc = 0x0010;
+
+        assert_eq!(cpu.get_op_addr(AddressingMode::Relative), 0x0016);
+        assert_eq!(cpu.get_op_addr(AddressingMode::Relative), 0x000D);
+        assert_eq!(cpu.pc, 0x0012);
     }
 
     #[test]
@@ -251,4 +283,43 @@ mod test {
 
         cpu.get_op_addr(AddressingMode::NoneAddressing);
     }
+
+    #[test]
+    fn extra_cycles() {
+        let rom = test_rom();
+        let bus = Bus::new(Rom::new(&rom).unwrap());
+        let mut cpu = Cpu::new(bus);
+        cpu.load(&[
+            0xA2, 0x01, // LDX #1
+            0xBD, 0xFF, 0x02, // LDA $02FF,X
+            0xBD, 0x00, 0x02, // LDA $0200,X
+            0x9D, 0xFF, 0x02, // STA $02FF,X
+            0xA0, 0x01, // LDY #1
+            0xB1, 0x10, // LDA ($10),Y
+            0xF0, 0x00, // BEQ +0
+            0xD0, 0x00, // BNE +0
+        ]);
+        cpu.bus.mem_write_u16(0x0010, 0x02FF);
+        cpu.bus.mem_write(0x06FC, 0xF0); // BEQ to the next page
+        cpu.bus.mem_write(0x06FD, 0x10);
+
+        let mut cycles = |n| {
+            (0..n)
+                .map(|_| {
+                    let start = cpu.bus.cycles();
+                    cpu.tick().unwrap();
+                    cpu.bus.cycles() - start
+                })
+                .collect::<Vec<_>>()
+        };
+        // page crossings cost reads a cycle, but writes always take it
+        assert_eq!(cycles(6), [2, 5, 4, 5, 2, 6]);
+        // taken branches cost a cycle, and another to a different page
+        assert_eq!(cycles(2), [3, 2]);
+
+        cpu.pc = 0x06FC;
+        let start = cpu.bus.cycles();
+        cpu.tick().unwrap();
+        assert_eq!((cpu.pc, cpu.bus.cycles() - start), (0x070E, 4));
+    }
 }
@@ -1,4 +1,7 @@
-use crate::bus::Bus;
+use crate::{
+    bus::Bus,
+    state::{fields, State},
+};
 use snafu::prelude::*;
 
 pub mod status;
@@ -11,7 +14,7 @@ pub use addr_mode::AddressingMode;
 
 use self::trace::TraceOp;
 
-#[derive(Snafu)]
+#[derive(Clone, Copy, PartialEq, Eq, Snafu)]
 pub enum Error {
     #[snafu(display("invalid opcode: {:#02x}", opcode))]
     InvalidOpcode { opcode: u8, offset: u16 },
@@ -54,9 +57,14 @@ impl<'rom> core::fmt::Debug for Cpu<'rom> {
     }
 }
 
+impl State for Cpu<'_> {
+    fields!(reg_a, reg_x, reg_y, status, sp, pc, bus);
+}
+
 impl<'rom> Cpu<'rom> {
     pub const STACK: u16 = 0x0100;
     pub const STACK_RESET: u8 = 0xFD;
+    pub const IRQ_VECTOR: u16 = 0xFFFE;
 
     /// Creates a new CPU with the default state.
     #[must_use]
@@ -95,24 +103,23 @@ impl<'rom> Cpu<'rom> {
             AddressingMode::AbsoluteY => self.take_u16().wrapping_add(u16::from(self.reg_y)),
             AddressingMode::Indirect => {
                 let real_addr = self.take_u16();
-                self.bus.mem_read_u16(real_addr)
+                // the high byte is read from the same page, even if the low byte ends it
+                let [lo, hi] = real_addr.to_le_bytes();
+                let hi_addr = u16::from_le_bytes([lo.wrapping_add(1), hi]);
+                u16::from_le_bytes([self.bus.mem_read(real_addr), self.bus.mem_read(hi_addr)])
             }
             AddressingMode::IndirectX => {
-                let real_addr = u16::from(self.take());
-                self.bus
-                    .mem_read_u16(real_addr)
-                    .wrapping_add(u16::from(self.reg_x))
-                    % 0xFF
+                let ptr = self.take().wrapping_add(self.reg_x);
+                self.read_zero_page_u16(ptr)
             }
             AddressingMode::IndirectY => {
-                let real_addr = u16::from(self.take());
-                self.bus
-                    .mem_read_u16(real_addr)
+                let ptr = self.take();
+                self.read_zero_page_u16(ptr)
                     .wrapping_add(u16::from(self.reg_y))
             }
             AddressingMode::Relative => {
-                let offset = self.take(); // self.pc + 1
-                self.pc.wrapping_add(u16::from(offset))
+                let offset = i8::from_ne_bytes([self.take()]); // self.pc + 1
+                self.pc.wrapping_add_signed(offset.into())
             }
             AddressingMode::NoneAddressing => {
                 unreachable!("AddressingMode::NoneAddressing is not a valid addressing mode");
@@ -124,6 +131,13 @@ impl<'rom> Cpu<'rom> {
         addr
     }
 
+    /// Reads a little-endian, 16-bit number from the zero page, wrapping around it.
+    fn read_zero_page_u16(&mut self, addr: u8) -> u16 {
+        let lo = self.bus.mem_read(u16::from(addr));
+        let hi = self.bus.mem_read(u16::from(addr.wrapping_add(1)));
+        u16::from_le_bytes([lo, hi])
+    }
+
     /// Loads the given program into memory, and sets the program counter to the start of the program.
     ///
     /// The program is truncated to `u16::MAX`.
@@ -201,25 +215,39 @@ impl<'rom> Cpu<'rom> {
         }
     }
 
+    /// The extra cycle an indexed read takes when the index carries into the high byte, for the
+    /// instruction whose operand is at `pc`. Writes and read-modify-writes always take it, so
+    /// it's already in their cycle counts.
+    fn page_cross_cycles(&self, opcode: &crate::opcode::OpCode) -> u8 {
+        let (base, index) = match (opcode.mode, opcode.cycles) {
+            (AddressingMode::AbsoluteX, 4) => (self.bus.peek_u16(self.pc), self.reg_x),
+            (AddressingMode::AbsoluteY, 4) => (self.bus.peek_u16(self.pc), self.reg_y),
+            (AddressingMode::IndirectY, 5) => (
+                self.bus.peek_u16(u16::from(self.bus.peek(self.pc))),
+                self.reg_y,
+            ),
+            _ => return 0,
+        };
+        u8::from(base & 0xFF00 != base.wrapping_add(u16::from(index)) & 0xFF00)
+    }
+
     /// Ticks the current cpu cycle, executing the current instruction loaded into memory.
     ///
     /// # Errors
     /// Returns an [`Error::InvalidOpcode`] if an invalid opcode is encountered.
     pub fn tick(&mut self) -> Result<bool, Error> {
+        #[cfg(any(test, fete_doctest))]
+        if let Some(trace) = TraceOp::new(self) {
+            log::trace!("{trace}");
+        }
+
         let opcode = self.take();
         let opcode_info: Option<&crate::opcode::OpCode> = crate::opcode::OPCODES.get(&opcode);
 
         if let Some(opcode) = opcode_info {
-            #[cfg(any(test, fete_doctest))]
-            log::trace!(
-                "{}",
-                TraceOp {
-                    cpu: self,
-                    op: opcode
-                }
-            );
-
+            let page_cross = self.page_cross_cycles(opcode);
             (opcode.op)(self, opcode.mode);
+            self.bus.tick(opcode.cycles + page_cross);
         } else {
             return Err(Error::InvalidOpcode {
                 opcode,
@@ -227,9 +255,22 @@ impl<'rom> Cpu<'rom> {
             });
         }
 
+        if self.bus.irq() && !self.status.contains(Status::INTERRUPT_DISABLE) {
+            self.interrupt(Self::IRQ_VECTOR);
+        }
+
         Ok(opcode == 0x00)
     }
 
+    /// Services an interrupt: pushes the program counter and status, disables interrupts and jumps through `vector`.
+    pub fn interrupt(&mut self, vector: u16) {
+        self.push_u16(self.pc);
+        self.push(((self.status | Status::BREAK2) & !Status::BREAK).bits());
+        self.status |= Status::INTERRUPT_DISABLE;
+        self.pc = self.bus.mem_read_u16(vector);
+        self.bus.tick(7);
+    }
+
     /// Pushes a byte onto the stack.
     pub fn push(&mut self, val: u8) {
         self.bus
@@ -260,14 +301,14 @@ impl<'rom> Cpu<'rom> {
 
     /// Takes the next byte from memory, and increments the program counter.
     pub fn take(&mut self) -> u8 {
-        let byte = self.bus.mem_read(self.pc);
+        let byte = self.bus.fetch(self.pc);
         self.pc = self.pc.wrapping_add(1);
         byte
     }
     /// Takes the next little-endian, 16-bit number from memory, and increments the program counter.
     pub fn take_u16(&mut self) -> u16 {
-        let num = self.bus.mem_read_u16(self.pc);
-        self.pc = self.pc.wrapping_add(2);
-        num
+        let lo = self.take();
+        let hi = self.take();
+        u16::from_le_bytes([lo, hi])
     }
 }
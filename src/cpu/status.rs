@@ -1,3 +1,4 @@
+use crate::state::{self, Reader, State, Writer};
 use bitflags::bitflags;
 
 bitflags! {
@@ -35,6 +36,17 @@ bitflags! {
     }
 }
 
+impl State for Status {
+    fn save(&self, w: &mut Writer) {
+        self.bits().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+        *self = Self::from_bits_retain(u8::from_le_bytes(r.array()?));
+        Ok(())
+    }
+}
+
 impl Default for Status {
     fn default() -> Self {
         // Self::INTERRUPT_DISABLE | Self::UNUSED
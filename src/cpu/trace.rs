@@ -1,8 +1,10 @@
 #![allow(clippy::module_name_repetitions)]
 
-use super::Cpu;
+use super::{Cpu, Status};
 use crate::{
+    bus::PpuPosition,
     cpu::AddressingMode,
+    disasm::{Labels, OperandAddr},
     opcode::{OpCode, OPCODES},
 };
 use core::fmt::{Display, Write};
@@ -12,135 +14,241 @@ use core::fmt::{Display, Write};
 pub struct TraceAddrMode<'cpu> {
     cpu: &'cpu Cpu<'cpu>,
     op: &'cpu OpCode,
+    labels: Option<&'cpu dyn Labels>,
+}
+impl TraceAddrMode<'_> {
+    fn read(&self, addr: u16) -> u8 {
+        self.cpu.bus.peek(addr)
+    }
+
+    /// Reads a pointer from the zero page, wrapping around within it like the CPU does.
+    fn read_zero_page_u16(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(u16::from(addr)),
+            self.read(u16::from(addr.wrapping_add(1))),
+        ])
+    }
 }
 impl<'a> Display for TraceAddrMode<'a> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        // size outputted- used for padding later
-        let mut out_size = 0_usize;
-
-        let addr_mode = self.op.mode;
-        if addr_mode != AddressingMode::NoneAddressing {
-            let pc = self.cpu.pc + 1;
-
-            let real_addr = {
-                let mut cloned = self.cpu.clone();
-                cloned.pc = pc;
-                cloned.get_op_addr(addr_mode)
-            };
-
-            let got_addr = match addr_mode {
-                AddressingMode::Immediate => {
-                    let val = self.cpu.bus.mem_read(pc);
-
-                    write!(f, "#${val:02X}")?;
-                    out_size += "#$xx".len();
-
-                    pc
-                }
-                AddressingMode::ZeroPage => {
-                    let addr = self.cpu.bus.mem_read(pc);
-                    let val = self.cpu.bus.mem_read(u16::from(addr));
-
-                    write!(f, "${addr:02X} = {val:02X}")?;
-                    out_size += "#xx = xx".len();
-
-                    u16::from(addr)
-                }
-                AddressingMode::ZeroPageX => {
-                    let addr = self.cpu.bus.mem_read(pc);
-                    let with_x = addr.wrapping_add(self.cpu.reg_x);
-                    let val = self.cpu.bus.mem_read(u16::from(with_x));
-
-                    write!(f, "${addr:02X},X @ {with_x:02X} = {val:02X}")?;
-                    out_size += "$xx,X @ xx = xx".len();
-
-                    u16::from(with_x)
-                }
-                AddressingMode::IndirectX => {
-                    let addr = self.cpu.bus.mem_read(pc);
-                    let with_x = addr.wrapping_add(self.cpu.reg_x);
-                    let real_addr = self.cpu.bus.mem_read_u16(u16::from(with_x));
-                    let val = self.cpu.bus.mem_read(real_addr);
-
-                    write!(
-                        f,
-                        "(${addr:02X},X) @ {with_x:02X} = {real_addr:04X} = {val:02X}"
-                    )?;
-                    out_size += "($xx,X) @ xx = xxxx = xx".len();
-
-                    real_addr
-                }
-                AddressingMode::Relative => {
-                    let addend = self.cpu.bus.mem_read(pc) + 1;
-                    let addr = pc + u16::from(addend);
-
-                    write!(f, "${addr:04X}")?;
-                    out_size += "$xxxx".len();
-
-                    addr
-                }
-                AddressingMode::Absolute => {
-                    let addr = self.cpu.bus.mem_read_u16(pc);
-                    if self.op.code == 0x4C || self.op.code == 0x20
-                    /* JMP & JSR absolute */
-                    {
-                        write!(f, "${addr:04X}")?;
-                        out_size += "$xxxx".len();
-                    } else {
-                        let val = self.cpu.bus.mem_read(addr);
-                        write!(f, "${addr:04X} = {val:02X}")?;
-                        out_size += "$xxxx = xx".len();
-                    }
-                    addr
-                }
-                mode => todo!("{mode:#?}"),
-            };
-
-            assert_eq!(got_addr, real_addr);
-        }
+        // formatted into a buffer first, so it can be padded to the width
+        let mut out = Buffer::<96>::new();
+        let pc = self.cpu.pc.wrapping_add(1);
+        let byte = self.read(pc);
+        let word = u16::from_le_bytes([byte, self.read(pc.wrapping_add(1))]);
+        let zp = OperandAddr::zero_page(byte, self.labels);
+        let abs = OperandAddr::absolute(word, self.labels);
+        let (x, y) = (self.cpu.reg_x, self.cpu.reg_y);
 
-        for _ in 0..(f.width().unwrap_or(0).saturating_sub(out_size)) {
-            f.write_char(' ')?;
-        }
+        match self.op.mode {
+            // the shifts are the only instructions with an accumulator form
+            AddressingMode::NoneAddressing if matches!(self.op.code, 0x0A | 0x2A | 0x4A | 0x6A) => {
+                write!(out, "A")
+            }
+            AddressingMode::NoneAddressing => Ok(()),
+            AddressingMode::Immediate => write!(out, "#${byte:02X}"),
+            AddressingMode::ZeroPage => {
+                write!(out, "{zp} = {:02X}", self.read(u16::from(byte)))
+            }
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let (reg, name) = if self.op.mode == AddressingMode::ZeroPageX {
+                    (x, 'X')
+                } else {
+                    (y, 'Y')
+                };
+                let addr = byte.wrapping_add(reg);
+                let val = self.read(u16::from(addr));
+                write!(out, "{zp},{name} @ {addr:02X} = {val:02X}")
+            }
+            // JMP & JSR absolute
+            AddressingMode::Absolute if matches!(self.op.code, 0x4C | 0x20) => {
+                write!(out, "{abs}")
+            }
+            AddressingMode::Absolute => write!(out, "{abs} = {:02X}", self.read(word)),
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let (reg, name) = if self.op.mode == AddressingMode::AbsoluteX {
+                    (x, 'X')
+                } else {
+                    (y, 'Y')
+                };
+                let addr = word.wrapping_add(u16::from(reg));
+                let val = self.read(addr);
+                write!(out, "{abs},{name} @ {addr:04X} = {val:02X}")
+            }
+            AddressingMode::Indirect => {
+                // the high byte doesn't carry into the next page
+                let [lo, hi] = word.to_le_bytes();
+                let addr = u16::from_le_bytes([
+                    self.read(word),
+                    self.read(u16::from_le_bytes([lo.wrapping_add(1), hi])),
+                ]);
+                write!(out, "({abs}) = {addr:04X}")
+            }
+            AddressingMode::IndirectX => {
+                let ptr = byte.wrapping_add(x);
+                let addr = self.read_zero_page_u16(ptr);
+                let val = self.read(addr);
+                write!(out, "({zp},X) @ {ptr:02X} = {addr:04X} = {val:02X}")
+            }
+            AddressingMode::IndirectY => {
+                let base = self.read_zero_page_u16(byte);
+                let addr = base.wrapping_add(u16::from(y));
+                let val = self.read(addr);
+                write!(out, "({zp}),Y = {base:04X} @ {addr:04X} = {val:02X}")
+            }
+            AddressingMode::Relative => {
+                let offset = i8::from_le_bytes([byte]);
+                let addr = pc.wrapping_add(1).wrapping_add_signed(offset.into());
+                write!(out, "{}", OperandAddr::absolute(addr, self.labels))
+            }
+        }?;
 
-        Ok(())
+        f.pad(out.as_str())
     }
 }
 
+/// The layout of a [`TraceOp`]'s line, after the log it matches.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// nestest's log:
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+    #[default]
+    Nestest,
+    /// FCEUX's trace logger: `A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5`
+    Fceux,
+    /// Mesen's trace logger:
+    /// `C000  $4C $F5 $C5     JMP $C5F5                                A:00 X:00 Y:00 P:24 SP:FD CYC:21  SL:0   FC:0 CPU Cycle:7`
+    Mesen,
+}
+
 pub struct TraceOp<'cpu> {
     pub cpu: &'cpu Cpu<'cpu>,
     pub op: &'cpu OpCode,
+    pub labels: Option<&'cpu dyn Labels>,
+    pub format: Format,
 }
 impl<'a> TraceOp<'a> {
     #[must_use]
     pub fn new(cpu: &'a Cpu<'a>) -> Option<Self> {
         Some(Self {
             cpu,
-            op: OPCODES.get(&cpu.bus.mem_read(cpu.pc))?,
+            op: OPCODES.get(&cpu.bus.peek(cpu.pc))?,
+            labels: None,
+            format: Format::Nestest,
         })
     }
+
+    /// Writes operand addresses with labels as the labels.
+    #[must_use]
+    pub const fn with_labels(mut self, labels: &'a dyn Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+
+    /// Writes the line in another format.
+    #[must_use]
+    pub const fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    const fn addr_mode(&self) -> TraceAddrMode<'a> {
+        TraceAddrMode {
+            cpu: self.cpu,
+            op: self.op,
+            labels: self.labels,
+        }
+    }
 }
-impl<'a> Display for TraceOp<'a> {
+impl Display for TraceOp<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let PpuPosition {
+            frame,
+            scanline,
+            dot,
+        } = self.cpu.bus.ppu_position();
+        let cpu = self.cpu;
+        let (reg_a, reg_x, reg_y, status, sp) =
+            (cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status.bits(), cpu.sp);
+
+        match self.format {
+            Format::Nestest => write!(
+                f,
+                "{:04X} {:10} {} {:27} A:{reg_a:02X} X:{reg_x:02X} Y:{reg_y:02X} P:{status:02X} SP:{sp:02X} PPU:{scanline:3},{dot:3} CYC:{}",
+                cpu.pc,
+                TraceBytes { cpu, op: self.op },
+                DisplayUppercase(self.op.name),
+                self.addr_mode(),
+                cpu.bus.cycles(),
+            ),
+            Format::Fceux => write!(
+                f,
+                "A:{reg_a:02X} X:{reg_x:02X} Y:{reg_y:02X} S:{sp:02X} P:{}  ${:04X}:{:9} {}",
+                DisplayFlags(cpu.status),
+                cpu.pc,
+                HexBytes {
+                    trace: self,
+                    prefix: ""
+                },
+                Disassembly(self),
+            ),
+            Format::Mesen => write!(
+                f,
+                "{:04X}  {:15} {:40} A:{reg_a:02X} X:{reg_x:02X} Y:{reg_y:02X} P:{status:02X} SP:{sp:02X} CYC:{dot:<3} SL:{scanline:<3} FC:{frame} CPU Cycle:{}",
+                cpu.pc,
+                HexBytes {
+                    trace: self,
+                    prefix: "$"
+                },
+                Disassembly(self),
+                cpu.bus.cycles(),
+            ),
+        }
+    }
+}
+
+/// An instruction's bytes, separated by spaces.
+struct HexBytes<'a> {
+    trace: &'a TraceOp<'a>,
+    prefix: &'static str,
+}
+impl Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut out = Buffer::<16>::new();
+        let pc = self.trace.cpu.pc;
+        for i in 0..=self.trace.op.mode.size() {
+            let sep = if i == 0 { "" } else { " " };
+            let byte = self.trace.cpu.bus.peek(pc.wrapping_add(u16::from(i)));
+            write!(out, "{sep}{}{byte:02X}", self.prefix)?;
+        }
+        f.pad(out.as_str())
+    }
+}
+
+/// The mnemonic and operand, without a space after instructions that don't have an operand.
+struct Disassembly<'a>(&'a TraceOp<'a>);
+impl Display for Disassembly<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut out = Buffer::<128>::new();
         write!(
-            f,
-            "{:04X} {:10} {} {:27} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
-            self.cpu.pc,
-            TraceBytes {
-                cpu: self.cpu,
-                op: self.op,
-            },
-            DisplayUppercase(self.op.name),
-            TraceAddrMode {
-                cpu: self.cpu,
-                op: self.op
-            },
-            self.cpu.reg_a,
-            self.cpu.reg_x,
-            self.cpu.reg_y,
-            self.cpu.status.bits(),
-            self.cpu.sp
-        )
+            out,
+            "{} {}",
+            DisplayUppercase(self.0.op.name),
+            self.0.addr_mode()
+        )?;
+        f.pad(out.as_str().trim_end())
+    }
+}
+
+/// The status flags as letters, in capitals if they're set.
+struct DisplayFlags(Status);
+impl Display for DisplayFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, c) in "NVUBDIZC".chars().enumerate() {
+            let set = self.0.bits() & (0x80 >> i) != 0;
+            f.write_char(if set { c } else { c.to_ascii_lowercase() })?;
+        }
+        Ok(())
     }
 }
 
@@ -155,7 +263,7 @@ impl<'a> Display for TraceBytes<'a> {
             write!(
                 f,
                 " {:02X}",
-                self.cpu.bus.mem_read(self.cpu.pc + u16::from(i))
+                self.cpu.bus.peek(self.cpu.pc.wrapping_add(u16::from(i)))
             )?;
         }
         for _ in 0..(f
@@ -178,3 +286,120 @@ impl<'a> Display for DisplayUppercase<'a> {
         Ok(())
     }
 }
+
+/// A fixed-size string, for formatting without allocating.
+struct Buffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+impl<const N: usize> Buffer<N> {
+    const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only ever written to with whole `str`s
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+impl<const N: usize> Write for Buffer<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let buf = self
+            .buf
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(core::fmt::Error)?;
+        buf.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+    use std::string::ToString;
+
+    #[test]
+    fn addr_modes() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.reg_x = 0x02;
+        cpu.reg_y = 0x10;
+        for (addr, val) in [
+            (0x00FF, 0x34),
+            (0x0000, 0x02),
+            (0x0201, 0x12),
+            (0x0244, 0x56),
+        ] {
+            cpu.bus.mem_write(addr, val);
+        }
+
+        let trace = |cpu: &mut Cpu, code: &[u8]| {
+            for (i, &b) in code.iter().enumerate() {
+                cpu.bus.mem_write(0x0600 + u16::try_from(i).unwrap(), b);
+            }
+            cpu.pc = 0x0600;
+            let trace = TraceOp::new(cpu).unwrap().to_string();
+            trace[16..48].trim_end().to_string()
+        };
+        assert_eq!(trace(&mut cpu, &[0x4A]), "LSR A");
+        assert_eq!(trace(&mut cpu, &[0xB6, 0xFF]), "LDX $FF,Y @ 0F = 00");
+        assert_eq!(
+            trace(&mut cpu, &[0xBD, 0xFF, 0x01]),
+            "LDA $01FF,X @ 0201 = 12"
+        );
+        assert_eq!(
+            trace(&mut cpu, &[0xB9, 0x34, 0x02]),
+            "LDA $0234,Y @ 0244 = 56"
+        );
+        assert_eq!(trace(&mut cpu, &[0x6C, 0xFF, 0x00]), "JMP ($00FF) = 0234");
+        assert_eq!(
+            trace(&mut cpu, &[0xA1, 0xFD]),
+            "LDA ($FD,X) @ FF = 0234 = 00"
+        );
+        assert_eq!(
+            trace(&mut cpu, &[0xB1, 0xFF]),
+            "LDA ($FF),Y = 0234 @ 0244 = 56"
+        );
+        assert_eq!(trace(&mut cpu, &[0xD0, 0xFC]), "BNE $05FE");
+
+        cpu.bus.tick(114);
+        let trace = TraceOp::new(&cpu).unwrap().to_string();
+        assert!(trace.ends_with("PPU:  1,  1 CYC:114"), "{trace}");
+    }
+
+    #[test]
+    fn formats() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.load(&[0x4C, 0xF5, 0xC5, 0xEA]);
+        cpu.pc = 0x0600;
+        cpu.status = Status::from_bits_retain(0x24);
+        cpu.bus.tick(7);
+
+        let trace = |cpu: &Cpu, format| TraceOp::new(cpu).unwrap().with_format(format).to_string();
+        assert_eq!(
+            trace(&cpu, Format::Nestest),
+            "0600  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+        assert_eq!(
+            trace(&cpu, Format::Fceux),
+            "A:00 X:00 Y:00 S:FD P:nvUbdIzc  $0600:4C F5 C5  JMP $C5F5"
+        );
+        assert_eq!(
+            trace(&cpu, Format::Mesen),
+            "0600  $4C $F5 $C5     JMP $C5F5                                A:00 X:00 Y:00 P:24 SP:FD CYC:21  SL:0   FC:0 CPU Cycle:7"
+        );
+
+        cpu.pc = 0x0603;
+        assert_eq!(
+            trace(&cpu, Format::Fceux),
+            "A:00 X:00 Y:00 S:FD P:nvUbdIzc  $0603:EA        NOP"
+        );
+    }
+}
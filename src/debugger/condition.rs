@@ -0,0 +1,340 @@
+//! Conditions on the CPU's registers and memory, like `A == #$10 && X > 3`.
+//!
+//! A condition is comparisons joined by `&&` and `||`, with `&&` binding tighter; there are no
+//! parentheses. Each side of a comparison is one of:
+//! - a register: `A`, `X`, `Y`, `P`, `SP` or `PC`, in any case;
+//! - a number: `$10` in hex, `%1010` in binary or `16` in decimal, optionally after a `#`;
+//! - a byte of memory, read without side effects: `[$0300]`.
+//!
+//! The comparisons are `==`, `!=`, `<`, `<=`, `>` and `>=`.
+
+use super::Error;
+use crate::cpu::Cpu;
+use core::str::FromStr;
+
+/// The most comparisons a condition can have.
+pub const MAX_TERMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    Const(u16),
+    /// The byte at an address.
+    Mem(u16),
+}
+
+impl Value {
+    fn eval(self, cpu: &Cpu) -> u16 {
+        match self {
+            Self::A => cpu.reg_a.into(),
+            Self::X => cpu.reg_x.into(),
+            Self::Y => cpu.reg_y.into(),
+            Self::P => cpu.status.bits().into(),
+            Self::Sp => cpu.sp.into(),
+            Self::Pc => cpu.pc,
+            Self::Const(val) => val,
+            Self::Mem(addr) => cpu.bus.peek(addr).into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A single comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Term {
+    pub lhs: Value,
+    pub cmp: Cmp,
+    pub rhs: Value,
+    /// Whether this is joined to the comparison before it with `||` rather than `&&`.
+    pub or: bool,
+}
+
+impl Term {
+    const EMPTY: Self = Self {
+        lhs: Value::Const(0),
+        cmp: Cmp::Eq,
+        rhs: Value::Const(0),
+        or: false,
+    };
+
+    fn eval(&self, cpu: &Cpu) -> bool {
+        let (lhs, rhs) = (self.lhs.eval(cpu), self.rhs.eval(cpu));
+        match self.cmp {
+            Cmp::Eq => lhs == rhs,
+            Cmp::Ne => lhs != rhs,
+            Cmp::Lt => lhs < rhs,
+            Cmp::Le => lhs <= rhs,
+            Cmp::Gt => lhs > rhs,
+            Cmp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// A parsed condition; see the [module docs](self) for the syntax.
+///
+/// # Examples
+/// ```
+/// # use fete::{bus::Bus, cpu::Cpu, rom::Rom, debugger::Condition};
+/// # let raw = fete::testing::test_rom();
+/// let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+/// let cond: Condition = "A == #$10 && X > 3".parse().unwrap();
+///
+/// cpu.reg_a = 0x10;
+/// assert!(!cond.eval(&cpu));
+/// cpu.reg_x = 4;
+/// assert!(cond.eval(&cpu));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    terms: [Term; MAX_TERMS],
+    len: usize,
+}
+
+impl Condition {
+    /// The comparisons, in order.
+    #[must_use]
+    pub fn terms(&self) -> &[Term] {
+        &self.terms[..self.len]
+    }
+
+    /// Whether the condition holds for the CPU.
+    #[must_use]
+    pub fn eval(&self, cpu: &Cpu) -> bool {
+        let mut any = false;
+        let mut all = true;
+        for term in self.terms() {
+            if term.or {
+                any |= all;
+                all = true;
+            }
+            all = all && term.eval(cpu);
+        }
+        any || all
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parser = Parser { s, pos: 0 };
+        let mut cond = Self {
+            terms: [Term::EMPTY; MAX_TERMS],
+            len: 0,
+        };
+
+        let mut or = false;
+        loop {
+            let lhs = parser.value()?;
+            let cmp = parser.cmp()?;
+            let rhs = parser.value()?;
+            *cond.terms.get_mut(cond.len).ok_or(Error::TooComplex)? = Term { lhs, cmp, rhs, or };
+            cond.len += 1;
+
+            or = if parser.eat("&&") {
+                false
+            } else if parser.eat("||") {
+                true
+            } else {
+                break;
+            };
+        }
+
+        parser.skip_space();
+        if parser.pos == s.len() {
+            Ok(cond)
+        } else {
+            Err(parser.expected("`&&` or `||`"))
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips `token`, if it's next.
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let next = self.rest().get(..token.len());
+        if next.is_some_and(|next| next.eq_ignore_ascii_case(token)) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    const fn expected(&self, expected: &'static str) -> Error {
+        Error::Syntax {
+            pos: self.pos,
+            expected,
+        }
+    }
+
+    fn cmp(&mut self) -> Result<Cmp, Error> {
+        // longest first, so `<=` isn't taken for `<`
+        let cmps = [
+            ("==", Cmp::Eq),
+            ("!=", Cmp::Ne),
+            ("<=", Cmp::Le),
+            (">=", Cmp::Ge),
+            ("<", Cmp::Lt),
+            (">", Cmp::Gt),
+        ];
+        cmps.into_iter()
+            .find(|(token, _)| self.eat(token))
+            .map(|(_, cmp)| cmp)
+            .ok_or_else(|| self.expected("a comparison"))
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        if self.eat("[") {
+            let addr = self.number()?;
+            if !self.eat("]") {
+                return Err(self.expected("`]`"));
+            }
+            return Ok(Value::Mem(addr));
+        }
+
+        // `SP` and `PC` before the single letters, so `P` isn't taken for `PC`
+        let regs = [
+            ("SP", Value::Sp),
+            ("PC", Value::Pc),
+            ("A", Value::A),
+            ("X", Value::X),
+            ("Y", Value::Y),
+            ("P", Value::P),
+        ];
+        if let Some((_, reg)) = regs.into_iter().find(|(name, _)| self.eat(name)) {
+            return Ok(reg);
+        }
+        Ok(Value::Const(self.number()?))
+    }
+
+    fn number(&mut self) -> Result<u16, Error> {
+        self.eat("#");
+        let radix = if self.eat("$") {
+            16
+        } else if self.eat("%") {
+            2
+        } else {
+            10
+        };
+
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(rest.len());
+        let num =
+            u16::from_str_radix(&rest[..len], radix).map_err(|_| self.expected("a number"))?;
+        self.pos += len;
+        Ok(num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse() {
+        let cond: Condition = "a==#$10 && [$0300] != %101 || PC >= 49152".parse().unwrap();
+        assert_eq!(
+            cond.terms(),
+            [
+                Term {
+                    lhs: Value::A,
+                    cmp: Cmp::Eq,
+                    rhs: Value::Const(0x10),
+                    or: false
+                },
+                Term {
+                    lhs: Value::Mem(0x0300),
+                    cmp: Cmp::Ne,
+                    rhs: Value::Const(5),
+                    or: false
+                },
+                Term {
+                    lhs: Value::Pc,
+                    cmp: Cmp::Ge,
+                    rhs: Value::Const(0xC000),
+                    or: true
+                },
+            ]
+        );
+
+        let err = |s: &str| s.parse::<Condition>().unwrap_err();
+        assert_eq!(
+            err("A = 1"),
+            Error::Syntax {
+                pos: 2,
+                expected: "a comparison"
+            }
+        );
+        assert_eq!(
+            err("X < $10000"),
+            Error::Syntax {
+                pos: 5,
+                expected: "a number"
+            }
+        );
+        assert_eq!(
+            err("Y > 1 Y"),
+            Error::Syntax {
+                pos: 6,
+                expected: "`&&` or `||`"
+            }
+        );
+        let long = "A == 1 || A == 2 || A == 3 || A == 4 || A == 5 || A == 6 || A == 7 || A == 8";
+        assert!(long.parse::<Condition>().is_ok());
+        assert_eq!(err(&long.replace('8', "8 || X == 9")), Error::TooComplex);
+    }
+
+    #[test]
+    fn eval() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        let cond: Condition = "A == 1 && X == 2 || Y == 3".parse().unwrap();
+        assert!(!cond.eval(&cpu));
+        cpu.reg_a = 1;
+        assert!(!cond.eval(&cpu));
+        cpu.reg_x = 2;
+        assert!(cond.eval(&cpu));
+        cpu.reg_a = 0;
+        cpu.reg_y = 3;
+        assert!(cond.eval(&cpu));
+
+        let mem: Condition = "[$10] > A".parse().unwrap();
+        assert!(!mem.eval(&cpu));
+        cpu.bus.mem_write(0x0010, 1);
+        assert!(mem.eval(&cpu));
+    }
+}
@@ -0,0 +1,531 @@
+//! A debugger, which runs a [`Cpu`] until it hits a breakpoint or watchpoint, or for a step.
+//!
+//! Breakpoints stop before the instruction at an address runs. Watchpoints stop after an
+//! instruction reads, writes or executes memory in a range on the CPU's bus. Both can have a
+//! [`Condition`] on the registers and memory, checked when they're hit.
+//!
+//! The PPU's bus can't be watched yet: the PPU isn't connected to the [`Bus`](crate::bus::Bus),
+//! so nothing reads or writes the pattern tables while the CPU runs.
+//!
+//! Running never stops on the instruction it starts at, so carrying on from a breakpoint
+//! doesn't hit it again.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, cpu::Cpu, rom::Rom};
+//! use fete::debugger::{Breakpoint, Debugger, Stop};
+//!
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//! // LDX #$00; loop: INX; JMP loop
+//! cpu.load(&[0xA2, 0x00, 0xE8, 0x4C, 0x02, 0x06]);
+//!
+//! let mut debugger = Debugger::new(cpu);
+//! let id = debugger
+//!     .add_breakpoint(Breakpoint {
+//!         addr: 0x0603,
+//!         condition: Some("X == 3".parse().unwrap()),
+//!     })
+//!     .unwrap();
+//! assert_eq!(debugger.run(), Ok(Stop::Breakpoint(id)));
+//! assert_eq!(debugger.cpu.reg_x, 3);
+//! ```
+
+use crate::{
+    asm::{self, Program},
+    bus::{Access, AccessKind, Space},
+    cpu::{self, Cpu},
+    disasm::Instruction,
+    opcode::OPCODES,
+};
+use bitflags::bitflags;
+use snafu::prelude::*;
+
+pub mod condition;
+pub use condition::Condition;
+
+/// The most breakpoints a debugger can have.
+pub const MAX_BREAKPOINTS: usize = 32;
+/// The most watchpoints a debugger can have.
+pub const MAX_WATCHPOINTS: usize = 16;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Snafu)]
+pub enum Error {
+    #[snafu(display("expected {expected} at {pos}"))]
+    Syntax { pos: usize, expected: &'static str },
+    #[snafu(display("conditions can't have more than {} comparisons", condition::MAX_TERMS))]
+    TooComplex,
+    #[snafu(display("there's no room for another breakpoint or watchpoint"))]
+    Full,
+}
+
+bitflags! {
+    /// The kinds of [`Access`] a [`Watchpoint`] stops on.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct Watch: u8 {
+        const READ = 0b001;
+        const WRITE = 0b010;
+        const EXECUTE = 0b100;
+    }
+}
+
+impl Watch {
+    const fn of(kind: AccessKind) -> Self {
+        match kind {
+            AccessKind::Read => Self::READ,
+            AccessKind::Write => Self::WRITE,
+            AccessKind::Execute => Self::EXECUTE,
+        }
+    }
+}
+
+/// Stops before the instruction at an address runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Only stops if this holds.
+    pub condition: Option<Condition>,
+}
+
+/// Stops after an instruction accesses the CPU's bus in `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub watch: Watch,
+    /// Only stops if this holds, after the instruction.
+    pub condition: Option<Condition>,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: Access) -> bool {
+        access.space == Space::Cpu
+            && (self.start..=self.end).contains(&access.addr)
+            && self.watch.contains(Watch::of(access.kind))
+    }
+}
+
+/// Why the debugger stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The step, or run to a frame or scanline, finished.
+    Done,
+    /// The breakpoint with this ID was hit.
+    Breakpoint(usize),
+    /// The watchpoint with this ID was hit, by the access.
+    Watchpoint { id: usize, access: Access },
+    /// The CPU ran a `BRK`.
+    Brk,
+}
+
+pub struct Debugger<'rom> {
+    pub cpu: Cpu<'rom>,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    watchpoints: [Option<Watchpoint>; MAX_WATCHPOINTS],
+}
+
+impl<'rom> Debugger<'rom> {
+    #[must_use]
+    pub const fn new(cpu: Cpu<'rom>) -> Self {
+        Self {
+            cpu,
+            breakpoints: [None; MAX_BREAKPOINTS],
+            watchpoints: [None; MAX_WATCHPOINTS],
+        }
+    }
+
+    /// Adds a breakpoint, returning its ID.
+    ///
+    /// # Errors
+    /// Errors if there are already [`MAX_BREAKPOINTS`].
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Result<usize, Error> {
+        add(&mut self.breakpoints, breakpoint)
+    }
+
+    /// Removes a breakpoint, returning it.
+    pub fn remove_breakpoint(&mut self, id: usize) -> Option<Breakpoint> {
+        self.breakpoints.get_mut(id)?.take()
+    }
+
+    /// Every breakpoint, with its ID.
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, b)| Some((id, b.as_ref()?)))
+    }
+
+    /// Adds a watchpoint, returning its ID.
+    ///
+    /// # Errors
+    /// Errors if there are already [`MAX_WATCHPOINTS`].
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Result<usize, Error> {
+        add(&mut self.watchpoints, watchpoint)
+    }
+
+    /// Removes a watchpoint, returning it.
+    pub fn remove_watchpoint(&mut self, id: usize) -> Option<Watchpoint> {
+        self.watchpoints.get_mut(id)?.take()
+    }
+
+    /// Every watchpoint, with its ID.
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(id, w)| Some((id, w.as_ref()?)))
+    }
+
+    /// The instruction the CPU will run next, to show with
+    /// [labels](crate::disasm::Formatted::labels).
+    #[must_use]
+    pub fn instruction(&self) -> Instruction {
+        Instruction::read(&self.cpu.bus, self.cpu.pc)
+    }
+
+    /// Assembles code into memory at `addr`, returning its length. It's written like the CPU
+    /// writes, so it can patch code in RAM, but writing to ROM goes to the mapper.
+    ///
+    /// # Errors
+    /// Errors if the code doesn't assemble, or is more than [`asm::MAX_PROGRAM`] bytes.
+    pub fn assemble(&mut self, addr: u16, src: &str) -> Result<usize, asm::Error> {
+        let program = Program::<{ asm::MAX_PROGRAM }>::assemble(addr, src)?;
+        for (i, &byte) in (0..).zip(program.iter()) {
+            self.cpu.bus.mem_write(addr.wrapping_add(i), byte);
+        }
+        Ok(program.len())
+    }
+
+    /// Runs until a breakpoint, watchpoint or `BRK`.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn run(&mut self) -> Result<Stop, cpu::Error> {
+        self.run_until(|_, _| false)
+    }
+
+    /// Runs a single instruction.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn step_into(&mut self) -> Result<Stop, cpu::Error> {
+        self.run_until(|_, _| true)
+    }
+
+    /// Runs a single instruction, or a whole subroutine if it's a `JSR`.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn step_over(&mut self) -> Result<Stop, cpu::Error> {
+        if self.cpu.bus.peek(self.cpu.pc) != JSR {
+            return self.step_into();
+        }
+        let (ret, sp) = (self.cpu.pc.wrapping_add(3), self.cpu.sp);
+        self.run_until(|cpu, _| cpu.pc == ret && cpu.sp == sp)
+    }
+
+    /// Runs until the current subroutine or interrupt handler returns: until an `RTS` or `RTI`
+    /// leaves the stack above where it is now.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn step_out(&mut self) -> Result<Stop, cpu::Error> {
+        let sp = self.cpu.sp;
+        self.run_until(|cpu, opcode| matches!(opcode, RTS | RTI) && cpu.sp > sp)
+    }
+
+    /// Runs until the next frame starts.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn run_to_frame(&mut self) -> Result<Stop, cpu::Error> {
+        let frame = self.cpu.bus.ppu_position().frame;
+        self.run_until(|cpu, _| cpu.bus.ppu_position().frame > frame)
+    }
+
+    /// Runs until the PPU next reaches the start of a scanline, which is wrapped to the
+    /// [number of scanlines](crate::bus::Bus::SCANLINES).
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn run_to_scanline(&mut self, scanline: u16) -> Result<Stop, cpu::Error> {
+        #[allow(clippy::cast_possible_truncation)] // there aren't that many
+        let scanline = scanline % crate::bus::Bus::SCANLINES as u16;
+        let mut prev = self.cpu.bus.ppu_position().scanline;
+        self.run_until(|cpu, _| {
+            let now = cpu.bus.ppu_position().scanline;
+            let reached = now == scanline && prev != scanline;
+            prev = now;
+            reached
+        })
+    }
+
+    /// Runs instructions until `done` is true after one, given the CPU and the opcode that just
+    /// ran, or something else stops it.
+    fn run_until(&mut self, mut done: impl FnMut(&Cpu, u8) -> bool) -> Result<Stop, cpu::Error> {
+        let watching = self.watchpoints.iter().any(Option::is_some);
+        self.cpu.bus.record_accesses(watching);
+        let stop = loop {
+            let pc = self.cpu.pc;
+            let opcode = self.cpu.bus.peek(pc);
+            self.cpu.bus.clear_accesses();
+            let brk = match self.cpu.tick() {
+                Ok(brk) => brk,
+                Err(err) => {
+                    self.cpu.bus.record_accesses(false);
+                    return Err(err);
+                }
+            };
+
+            if let Some(stop) = self.watchpoint_hit(pc, opcode) {
+                break stop;
+            }
+            if brk {
+                break Stop::Brk;
+            }
+            if done(&self.cpu, opcode) {
+                break Stop::Done;
+            }
+            if let Some(id) = self.breakpoint_hit() {
+                break Stop::Breakpoint(id);
+            }
+        };
+        self.cpu.bus.record_accesses(false);
+        Ok(stop)
+    }
+
+    fn breakpoint_hit(&self) -> Option<usize> {
+        self.breakpoints().find_map(|(id, b)| {
+            (b.addr == self.cpu.pc && b.condition.map_or(true, |c| c.eval(&self.cpu))).then_some(id)
+        })
+    }
+
+    /// Checks the accesses made by the instruction at `pc`.
+    fn watchpoint_hit(&self, pc: u16, opcode: u8) -> Option<Stop> {
+        // immediate operands are read like data, but they're part of the instruction
+        let len = OPCODES.get(&opcode).map_or(1, |op| 1 + op.mode.size());
+        let operand = pc.wrapping_add(1)..pc.wrapping_add(len.into());
+
+        self.cpu.bus.accesses().find_map(|mut access| {
+            if access.kind == AccessKind::Read && operand.contains(&access.addr) {
+                access.kind = AccessKind::Execute;
+            }
+            self.watchpoints().find_map(|(id, w)| {
+                (w.matches(access) && w.condition.map_or(true, |c| c.eval(&self.cpu)))
+                    .then_some(Stop::Watchpoint { id, access })
+            })
+        })
+    }
+}
+
+/// Puts `item` in the first free slot, returning its index.
+fn add<T>(slots: &mut [Option<T>], item: T) -> Result<usize, Error> {
+    let (id, slot) = slots
+        .iter_mut()
+        .enumerate()
+        .find(|(_, slot)| slot.is_none())
+        .ok_or(Error::Full)?;
+    *slot = Some(item);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+
+    /// A CPU with `prog` loaded at `$0600`.
+    #[allow(clippy::large_stack_frames)] // the bus is meant to live on the stack
+    fn debugger<'a>(raw: &'a [u8], prog: &[u8]) -> Debugger<'a> {
+        let mut cpu = Cpu::new(Bus::new(Rom::new(raw).unwrap()));
+        cpu.load(prog);
+        Debugger::new(cpu)
+    }
+
+    // 0600: JSR $0607
+    // 0603: LDA #$01
+    // 0605: BRK
+    // 0606: BRK
+    // 0607: JSR $060B
+    // 060A: RTS
+    // 060B: STA $10
+    // 060D: RTS
+    const PROG: [u8; 14] = [
+        0x20, 0x07, 0x06, 0xA9, 0x01, 0x00, 0x00, 0x20, 0x0B, 0x06, 0x60, 0x85, 0x10, 0x60,
+    ];
+
+    #[test]
+    fn stepping() {
+        let raw = test_rom();
+        let mut d = debugger(&raw, &PROG);
+        assert_eq!(d.cpu.pc, 0x0600);
+
+        assert_eq!(d.step_into(), Ok(Stop::Done));
+        assert_eq!(d.cpu.pc, 0x0607);
+        assert_eq!(d.step_over(), Ok(Stop::Done));
+        assert_eq!(d.cpu.pc, 0x060A);
+        assert_eq!(d.step_out(), Ok(Stop::Done));
+        assert_eq!(d.cpu.pc, 0x0603);
+        assert_eq!(d.step_over(), Ok(Stop::Done));
+        assert_eq!(d.cpu.pc, 0x0605);
+        assert_eq!(d.run(), Ok(Stop::Brk));
+
+        // out of two subroutines at once, from the innermost
+        let mut d = debugger(&raw, &PROG);
+        d.step_into().unwrap();
+        d.step_into().unwrap();
+        assert_eq!(d.cpu.pc, 0x060B);
+        assert_eq!(d.step_out(), Ok(Stop::Done));
+        assert_eq!(d.cpu.pc, 0x060A);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let raw = test_rom();
+        let mut d = debugger(&raw, &PROG);
+        let inner = d
+            .add_breakpoint(Breakpoint {
+                addr: 0x060B,
+                condition: None,
+            })
+            .unwrap();
+        d.add_breakpoint(Breakpoint {
+            addr: 0x0605,
+            condition: Some("A == 2".parse().unwrap()),
+        })
+        .unwrap();
+        assert_eq!(d.breakpoints().count(), 2);
+
+        // stepping over stops inside too
+        assert_eq!(d.step_over(), Ok(Stop::Breakpoint(inner)));
+        assert_eq!(d.cpu.pc, 0x060B);
+        // the condition doesn't hold
+        assert_eq!(d.run(), Ok(Stop::Brk));
+
+        let mut d = debugger(&raw, &PROG);
+        d.cpu.reg_a = 2;
+        let id = d
+            .add_breakpoint(Breakpoint {
+                addr: 0x0605,
+                condition: Some("A == 1".parse().unwrap()),
+            })
+            .unwrap();
+        assert_eq!(d.run(), Ok(Stop::Breakpoint(id)));
+        assert_eq!(d.remove_breakpoint(id).map(|b| b.addr), Some(0x0605));
+        assert_eq!(d.remove_breakpoint(id), None);
+
+        for _ in 0..MAX_BREAKPOINTS {
+            d.add_breakpoint(Breakpoint {
+                addr: 0,
+                condition: None,
+            })
+            .unwrap();
+        }
+        let full = d.add_breakpoint(Breakpoint {
+            addr: 0,
+            condition: None,
+        });
+        assert_eq!(full, Err(Error::Full));
+    }
+
+    #[test]
+    fn watchpoints() {
+        let raw = test_rom();
+        let mut d = debugger(&raw, &PROG);
+        let write = d
+            .add_watchpoint(Watchpoint {
+                start: 0x0000,
+                end: 0x00FF,
+                watch: Watch::WRITE,
+                condition: None,
+            })
+            .unwrap();
+        let stack = d
+            .add_watchpoint(Watchpoint {
+                start: 0x0100,
+                end: 0x01FF,
+                watch: Watch::READ,
+                condition: Some("SP == $FD".parse().unwrap()),
+            })
+            .unwrap();
+
+        let access = |kind, addr, val| Access {
+            space: Space::Cpu,
+            kind,
+            addr,
+            val,
+        };
+        assert_eq!(
+            d.run(),
+            Ok(Stop::Watchpoint {
+                id: write,
+                access: access(AccessKind::Write, 0x0010, 0x00)
+            })
+        );
+        assert_eq!(d.cpu.pc, 0x060D);
+        // only once the last RTS empties the stack
+        assert_eq!(
+            d.run(),
+            Ok(Stop::Watchpoint {
+                id: stack,
+                access: access(AccessKind::Read, 0x01FC, 0x02)
+            })
+        );
+        assert_eq!(d.cpu.pc, 0x0603);
+
+        d.remove_watchpoint(write);
+        d.remove_watchpoint(stack);
+        let exec = d
+            .add_watchpoint(Watchpoint {
+                start: 0x0604,
+                end: 0x0604,
+                watch: Watch::EXECUTE,
+                condition: None,
+            })
+            .unwrap();
+        assert_eq!(
+            d.step_into(),
+            Ok(Stop::Watchpoint {
+                id: exec,
+                access: access(AccessKind::Execute, 0x0604, 0x01)
+            })
+        );
+    }
+
+    #[test]
+    fn patching() {
+        let raw = test_rom();
+        let mut d = debugger(&raw, &PROG);
+        assert_eq!(d.assemble(0x0603, "LDA #$02"), Ok(2));
+        d.step_over().unwrap();
+        assert_eq!(d.instruction().to_string(), "lda #$02");
+        assert_eq!(d.run(), Ok(Stop::Brk));
+        assert_eq!(d.cpu.reg_a, 0x02);
+        assert_eq!(
+            d.assemble(0x0603, "LDA #$100"),
+            Err(asm::Error::OutOfRange { line: 1 })
+        );
+    }
+
+    #[test]
+    fn frames() {
+        let raw = test_rom();
+        // loop: JMP loop
+        let mut d = debugger(&raw, &[0x4C, 0x00, 0x06]);
+        assert_eq!(d.run_to_scanline(10), Ok(Stop::Done));
+        let pos = d.cpu.bus.ppu_position();
+        assert_eq!((pos.frame, pos.scanline), (0, 10));
+        assert!(pos.dot < 9);
+
+        assert_eq!(d.run_to_frame(), Ok(Stop::Done));
+        let pos = d.cpu.bus.ppu_position();
+        assert_eq!((pos.frame, pos.scanline), (1, 0));
+        assert_eq!(d.run_to_scanline(10 + 262), Ok(Stop::Done));
+        assert_eq!(d.cpu.bus.ppu_position().scanline, 10);
+    }
+}
@@ -0,0 +1,418 @@
+//! A 6502 disassembler, decoding the instructions in [`OPCODES`].
+//!
+//! Unlike [tracing](crate::cpu::trace), this doesn't need a running CPU: it decodes any bytes,
+//! as if they were at some address, and doesn't read memory the instructions would access.
+//!
+//! # Examples
+//! ```
+//! # use fete::disasm::{disassemble, Syntax};
+//! let code = [0xA9, 0x10, 0x0A, 0xD0, 0xFB, 0x02];
+//! let mut lines = disassemble(0x8000, &code);
+//!
+//! let lda = lines.next().unwrap();
+//! assert_eq!(lda.bytes(), [0xA9, 0x10]);
+//! assert_eq!(lda.to_string(), "lda #$10");
+//! assert_eq!(
+//!     lines.next().unwrap().display(Syntax::Nestest).to_string(),
+//!     "ASL A"
+//! );
+//! assert_eq!(lines.next().unwrap().target(), Some(0x8000));
+//! assert_eq!(lines.next().unwrap().to_string(), ".byte $02");
+//! assert!(lines.next().is_none());
+//! ```
+
+use crate::{bus::Bus, cpu::AddressingMode, opcode::OPCODES};
+use core::fmt;
+
+/// Names for addresses, which are shown instead of the numbers, e.g. from the `symbols`
+/// module.
+pub trait Labels {
+    /// The label for an address on the CPU's bus.
+    fn label(&self, addr: u16) -> Option<&str>;
+}
+
+/// How an instruction is written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Lowercase, as ca65 assembles it: `lda #$10`, `sta a:$0010,x`.
+    #[default]
+    Ca65,
+    /// Uppercase, as in nestest's log, without the memory values: `LDA #$10`, `STA $0010,X`.
+    Nestest,
+}
+
+/// What an instruction operates on, decoded from the bytes after the opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    /// Implied by the instruction, e.g. `clc`.
+    None,
+    /// The A register, e.g. `asl a`.
+    Accumulator,
+    Immediate(u8),
+    ZeroPage(u8),
+    ZeroPageX(u8),
+    ZeroPageY(u8),
+    Absolute(u16),
+    AbsoluteX(u16),
+    AbsoluteY(u16),
+    Indirect(u16),
+    IndirectX(u8),
+    IndirectY(u8),
+    /// A branch offset, from the instruction after the branch.
+    Relative(i8),
+}
+
+/// A decoded instruction, or a byte that isn't one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u16,
+    bytes: [u8; 3],
+    len: u8,
+    /// The lowercase mnemonic, or [`None`] for bytes that aren't an instruction: either an
+    /// opcode not in [`OPCODES`], or one cut off before its operand. Those are shown as data.
+    pub mnemonic: Option<&'static str>,
+    pub mode: AddressingMode,
+    pub operand: Operand,
+}
+
+impl Instruction {
+    /// Decodes the instruction at the start of `bytes`, which are at `addr`. Trailing bytes are
+    /// ignored.
+    ///
+    /// # Panics
+    /// Panics if `bytes` is empty.
+    #[must_use]
+    pub fn decode(addr: u16, bytes: &[u8]) -> Self {
+        let code = bytes[0];
+        let Some(opcode) = OPCODES.get(&code) else {
+            return Self::data(addr, code);
+        };
+        let len = 1 + opcode.mode.size();
+        let Some(instr) = bytes.get(..len.into()) else {
+            return Self::data(addr, code);
+        };
+
+        let byte = instr.get(1).copied().unwrap_or(0);
+        let word = u16::from_le_bytes([byte, instr.get(2).copied().unwrap_or(0)]);
+        let operand = match opcode.mode {
+            // the shifts are the only instructions with an accumulator form
+            AddressingMode::NoneAddressing if matches!(code, 0x0A | 0x2A | 0x4A | 0x6A) => {
+                Operand::Accumulator
+            }
+            AddressingMode::NoneAddressing => Operand::None,
+            AddressingMode::Immediate => Operand::Immediate(byte),
+            AddressingMode::ZeroPage => Operand::ZeroPage(byte),
+            AddressingMode::ZeroPageX => Operand::ZeroPageX(byte),
+            AddressingMode::ZeroPageY => Operand::ZeroPageY(byte),
+            AddressingMode::Absolute => Operand::Absolute(word),
+            AddressingMode::AbsoluteX => Operand::AbsoluteX(word),
+            AddressingMode::AbsoluteY => Operand::AbsoluteY(word),
+            AddressingMode::Indirect => Operand::Indirect(word),
+            AddressingMode::IndirectX => Operand::IndirectX(byte),
+            AddressingMode::IndirectY => Operand::IndirectY(byte),
+            AddressingMode::Relative => Operand::Relative(i8::from_le_bytes([byte])),
+        };
+
+        let mut bytes = [0; 3];
+        bytes[..instr.len()].copy_from_slice(instr);
+        Self {
+            addr,
+            bytes,
+            len,
+            mnemonic: Some(opcode.name),
+            mode: opcode.mode,
+            operand,
+        }
+    }
+
+    /// Decodes the instruction at `addr` on the bus, reading it with [`Bus::peek`].
+    #[must_use]
+    pub fn read(bus: &Bus, addr: u16) -> Self {
+        let bytes = [0, 1, 2].map(|i| bus.peek(addr.wrapping_add(i)));
+        Self::decode(addr, &bytes)
+    }
+
+    const fn data(addr: u16, byte: u8) -> Self {
+        Self {
+            addr,
+            bytes: [byte, 0, 0],
+            len: 1,
+            mnemonic: None,
+            mode: AddressingMode::NoneAddressing,
+            operand: Operand::None,
+        }
+    }
+
+    /// The instruction's bytes, opcode first.
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.len.into()]
+    }
+
+    /// The number of bytes the instruction takes up.
+    #[must_use]
+    pub const fn len(&self) -> u16 {
+        self.len as u16
+    }
+
+    /// Always false: every instruction is at least one byte.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The address of the instruction after this one.
+    #[must_use]
+    pub const fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len())
+    }
+
+    /// Where a branch, `jmp` or `jsr` goes, if it's known without reading memory; that rules
+    /// out indirect jumps.
+    #[must_use]
+    pub fn target(&self) -> Option<u16> {
+        match (self.mnemonic?, self.operand) {
+            (_, Operand::Relative(offset)) => {
+                Some(self.next_addr().wrapping_add_signed(offset.into()))
+            }
+            ("jmp" | "jsr", Operand::Absolute(addr)) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// The address in an absolute operand.
+    const fn operand_word(&self) -> Option<u16> {
+        match self.operand {
+            Operand::Absolute(addr) | Operand::AbsoluteX(addr) | Operand::AbsoluteY(addr) => {
+                Some(addr)
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes the instruction in some syntax; [`Display`](fmt::Display) uses
+    /// [`Syntax::Ca65`].
+    #[must_use]
+    pub const fn display(&self, syntax: Syntax) -> Formatted<'_> {
+        Formatted {
+            instr: self,
+            syntax,
+            labels: None,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(Syntax::Ca65).fmt(f)
+    }
+}
+
+/// An [`Instruction`] written in some [`Syntax`].
+#[derive(Clone, Copy)]
+pub struct Formatted<'a> {
+    instr: &'a Instruction,
+    syntax: Syntax,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl<'a> Formatted<'a> {
+    /// Writes addresses with labels as the labels.
+    #[must_use]
+    pub const fn labels(mut self, labels: &'a dyn Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+}
+
+impl fmt::Display for Formatted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let upper = self.syntax == Syntax::Nestest;
+        let (x, y) = if upper { ("X", "Y") } else { ("x", "y") };
+
+        let Some(mnemonic) = self.instr.mnemonic else {
+            let directive = if upper { ".BYTE" } else { ".byte" };
+            return write!(f, "{directive} ${:02X}", self.instr.bytes[0]);
+        };
+        for c in mnemonic.chars() {
+            write!(f, "{}", if upper { c.to_ascii_uppercase() } else { c })?;
+        }
+
+        // ca65 would assemble absolute addresses in the zero page to the shorter zero page form
+        let abs = if !upper && self.instr.operand_word().is_some_and(|addr| addr < 0x100) {
+            "a:"
+        } else {
+            ""
+        };
+        let zp = |addr| OperandAddr::zero_page(addr, self.labels);
+        let abs_addr = |addr| OperandAddr::absolute(addr, self.labels);
+        match self.instr.operand {
+            Operand::None => Ok(()),
+            Operand::Accumulator => f.write_str(if upper { " A" } else { " a" }),
+            Operand::Immediate(val) => write!(f, " #${val:02X}"),
+            Operand::ZeroPage(addr) => write!(f, " {}", zp(addr)),
+            Operand::ZeroPageX(addr) => write!(f, " {},{x}", zp(addr)),
+            Operand::ZeroPageY(addr) => write!(f, " {},{y}", zp(addr)),
+            Operand::Absolute(addr) => write!(f, " {abs}{}", abs_addr(addr)),
+            Operand::AbsoluteX(addr) => write!(f, " {abs}{},{x}", abs_addr(addr)),
+            Operand::AbsoluteY(addr) => write!(f, " {abs}{},{y}", abs_addr(addr)),
+            Operand::Indirect(addr) => write!(f, " ({})", abs_addr(addr)),
+            Operand::IndirectX(addr) => write!(f, " ({},{x})", zp(addr)),
+            Operand::IndirectY(addr) => write!(f, " ({}),{y}", zp(addr)),
+            Operand::Relative(_) => write!(f, " {}", abs_addr(self.instr.target().unwrap_or(0))),
+        }
+    }
+}
+
+/// An address in an operand, written as its label if it has one.
+pub(crate) struct OperandAddr<'a> {
+    addr: u16,
+    zero_page: bool,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl<'a> OperandAddr<'a> {
+    pub(crate) fn zero_page(addr: u8, labels: Option<&'a dyn Labels>) -> Self {
+        Self {
+            addr: addr.into(),
+            zero_page: true,
+            labels,
+        }
+    }
+
+    pub(crate) const fn absolute(addr: u16, labels: Option<&'a dyn Labels>) -> Self {
+        Self {
+            addr,
+            zero_page: false,
+            labels,
+        }
+    }
+}
+
+impl fmt::Display for OperandAddr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = self.labels.and_then(|l| l.label(self.addr)) {
+            f.write_str(label)
+        } else if self.zero_page {
+            write!(f, "${:02X}", self.addr)
+        } else {
+            write!(f, "${:04X}", self.addr)
+        }
+    }
+}
+
+/// Decodes every instruction in `bytes`, which start at `addr`.
+#[must_use]
+pub const fn disassemble(addr: u16, bytes: &[u8]) -> Disassemble<'_> {
+    Disassemble { addr, bytes }
+}
+
+/// Decodes instructions on the bus, starting at `addr` and carrying on forever; see
+/// [`Instruction::read`].
+pub fn disassemble_bus<'a>(bus: &'a Bus, addr: u16) -> impl Iterator<Item = Instruction> + 'a {
+    core::iter::successors(Some(Instruction::read(bus, addr)), |instr| {
+        Some(Instruction::read(bus, instr.next_addr()))
+    })
+}
+
+/// The iterator returned by [`disassemble`].
+#[derive(Debug, Clone)]
+pub struct Disassemble<'a> {
+    addr: u16,
+    bytes: &'a [u8],
+}
+
+impl Iterator for Disassemble<'_> {
+    type Item = Instruction;
+
+    fn next(&mut self) -> Option<Instruction> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let instr = Instruction::decode(self.addr, self.bytes);
+        self.bytes = &self.bytes[instr.bytes().len()..];
+        self.addr = instr.next_addr();
+        Some(instr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+    use std::{string::ToString, vec::Vec};
+
+    #[test]
+    fn syntaxes() {
+        let code = [
+            0xA9, 0x10, // lda #$10
+            0xB5, 0x80, // lda $80,x
+            0xB6, 0x80, // ldx $80,y
+            0xBD, 0x00, 0x02, // lda $0200,x
+            0x99, 0x10, 0x00, // sta a:$0010,y
+            0x6C, 0xFC, 0xFF, // jmp ($FFFC)
+            0xA1, 0x20, // lda ($20,x)
+            0x91, 0x20, // sta ($20),y
+            0x6A, // ror a
+            0x18, // clc
+            0x10, 0x7F, // bpl
+        ];
+        let lines: Vec<_> = disassemble(0xC000, &code)
+            .map(|i| (i.to_string(), i.display(Syntax::Nestest).to_string()))
+            .collect();
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|(a, b)| (a.as_str(), b.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                ("lda #$10", "LDA #$10"),
+                ("lda $80,x", "LDA $80,X"),
+                ("ldx $80,y", "LDX $80,Y"),
+                ("lda $0200,x", "LDA $0200,X"),
+                ("sta a:$0010,y", "STA $0010,Y"),
+                ("jmp ($FFFC)", "JMP ($FFFC)"),
+                ("lda ($20,x)", "LDA ($20,X)"),
+                ("sta ($20),y", "STA ($20),Y"),
+                ("ror a", "ROR A"),
+                ("clc", "CLC"),
+                ("bpl $C096", "BPL $C096"),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let jsr = Instruction::decode(0x8000, &[0x20, 0x34, 0x12, 0xEA]);
+        assert_eq!(jsr.bytes(), [0x20, 0x34, 0x12]);
+        assert_eq!(jsr.mnemonic, Some("jsr"));
+        assert_eq!(jsr.operand, Operand::Absolute(0x1234));
+        assert_eq!(jsr.target(), Some(0x1234));
+        assert_eq!(jsr.next_addr(), 0x8003);
+
+        // backwards, and around the end of memory
+        let beq = Instruction::decode(0x0000, &[0xF0, 0x80]);
+        assert_eq!(beq.target(), Some(0xFF82));
+        assert_eq!(Instruction::decode(0x8000, &[0x6C, 0, 0]).target(), None);
+
+        // cut off before its operand
+        let lines: Vec<_> = disassemble(0x8000, &[0xEA, 0xAD, 0x02]).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].mnemonic, None);
+        assert_eq!(lines[2].to_string(), ".byte $02");
+    }
+
+    #[test]
+    fn bus() {
+        let raw = test_rom();
+        let bus = Bus::new(Rom::new(&raw).unwrap());
+        let lines: Vec<_> = disassemble_bus(&bus, 0x8000).take(8).collect();
+        let mut prg = [0; 32];
+        for (i, byte) in prg.iter_mut().enumerate() {
+            *byte = bus.mem_read(0x8000 + u16::try_from(i).unwrap());
+        }
+        assert_eq!(lines, disassemble(0x8000, &prg).take(8).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,621 @@
+//! A stub for GDB's [remote serial protocol], so gdb and IDEs can debug code running on the
+//! emulator.
+//!
+//! It's served over any [`Connection`]; with the `std` feature, that can be a TCP socket (see
+//! [`listen`]). It supports:
+//! - `g`/`G`: the registers, which are `a`, `x`, `y`, `p` and `sp`, one byte each, then `pc`,
+//!   little-endian;
+//! - `m`/`M`: memory on the CPU's bus, read without side effects;
+//! - `Z`/`z`: breakpoints, and write (`2`), read (`3`) and access (`4`) watchpoints;
+//! - `c`/`s`: continuing and stepping, optionally from an address;
+//! - `?`, `D`, `k`, `qSupported`, `QStartNoAckMode`, and the target description, through
+//!   `qXfer:features:read`.
+//!
+//! Anything else gets the empty reply, which tells the client it isn't supported.
+//!
+//! [remote serial protocol]: https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+
+use crate::{
+    bus::AccessKind,
+    debugger::{Breakpoint, Debugger, Stop, Watch, Watchpoint},
+};
+
+/// The most bytes in a packet, either way.
+pub const PACKET_SIZE: usize = 1024;
+
+/// Describes the registers, for clients that don't know the 6502.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.fete.6502">
+    <reg name="a" bitsize="8" type="uint8"/>
+    <reg name="x" bitsize="8" type="uint8"/>
+    <reg name="y" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="uint8"/>
+    <reg name="sp" bitsize="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Signals reported when the CPU stops.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Sent by the client to stop a running CPU.
+const INTERRUPT: u8 = 0x03;
+
+/// A byte stream to a client.
+pub trait Connection {
+    type Error;
+
+    /// Reads the next byte, waiting for one if needed.
+    ///
+    /// # Errors
+    /// Errors if the connection does, which ends the session.
+    fn read(&mut self) -> Result<u8, Self::Error>;
+
+    /// Writes all of `bytes`.
+    ///
+    /// # Errors
+    /// Errors if the connection does, which ends the session.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error>;
+
+    /// Whether the client has asked to stop the CPU while it's running (with Ctrl-C), without
+    /// waiting. Checked once a frame; the default never stops it.
+    ///
+    /// # Errors
+    /// Errors if the connection does, which ends the session.
+    fn interrupted(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+/// Serves a single client until it detaches or kills the session, or the connection fails.
+///
+/// # Errors
+/// Errors if the connection does.
+pub fn serve<C: Connection>(debugger: &mut Debugger, conn: C) -> Result<(), C::Error> {
+    let mut stub = Stub {
+        debugger,
+        conn,
+        ack: true,
+        reply: Reply::new(),
+    };
+    stub.serve()
+}
+
+/// Waits for a client on a TCP address, such as `localhost:2345`, and [`serve`]s it.
+///
+/// # Errors
+/// Errors if listening or the connection fails.
+#[cfg(feature = "std")]
+pub fn listen(debugger: &mut Debugger, addr: impl std::net::ToSocketAddrs) -> std::io::Result<()> {
+    let (stream, _) = std::net::TcpListener::bind(addr)?.accept()?;
+    stream.set_nodelay(true)?;
+    serve(debugger, stream)
+}
+
+#[cfg(any(feature = "std", test))]
+impl Connection for std::net::TcpStream {
+    type Error = std::io::Error;
+
+    fn read(&mut self) -> std::io::Result<u8> {
+        let mut byte = [0];
+        std::io::Read::read_exact(self, &mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        std::io::Write::write_all(self, bytes)
+    }
+
+    fn interrupted(&mut self) -> std::io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(0) => Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == INTERRUPT => Connection::read(self).map(|_| true),
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+struct Stub<'d, 'rom, C> {
+    debugger: &'d mut Debugger<'rom>,
+    conn: C,
+    /// Whether packets are acknowledged; the client can turn it off.
+    ack: bool,
+    reply: Reply,
+}
+
+/// What to do after a packet.
+enum Then {
+    Reply,
+    /// Reply, then end the session.
+    Quit,
+    /// End the session without replying.
+    Kill,
+}
+
+impl<C: Connection> Stub<'_, '_, C> {
+    fn serve(&mut self) -> Result<(), C::Error> {
+        let mut packet = [0; PACKET_SIZE];
+        loop {
+            let len = self.read_packet(&mut packet)?;
+            self.reply.clear();
+            let then = self.handle(&packet[..len]).unwrap_or_else(|| {
+                self.reply.clear();
+                self.reply.push_str("E01");
+                Then::Reply
+            });
+            match then {
+                Then::Reply => self.write_reply()?,
+                Then::Quit => return self.write_reply(),
+                Then::Kill => return Ok(()),
+            }
+        }
+    }
+
+    /// Reads a packet's data into `packet`, returning its length. An interrupt outside a
+    /// packet is returned as a packet of just [`INTERRUPT`].
+    fn read_packet(&mut self, packet: &mut [u8; PACKET_SIZE]) -> Result<usize, C::Error> {
+        loop {
+            match self.conn.read()? {
+                b'$' => {}
+                INTERRUPT => {
+                    packet[0] = INTERRUPT;
+                    return Ok(1);
+                }
+                // acknowledgements, and anything else between packets
+                _ => continue,
+            }
+
+            let mut len = 0;
+            let mut sum = 0_u8;
+            let mut overflowed = false;
+            loop {
+                let byte = self.conn.read()?;
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                if let Some(slot) = packet.get_mut(len) {
+                    *slot = byte;
+                    len += 1;
+                } else {
+                    overflowed = true;
+                }
+            }
+            let checksum = [self.conn.read()?, self.conn.read()?];
+
+            if self.ack {
+                let ok = !overflowed && parse_hex(&checksum) == Some(sum.into());
+                self.conn.write(if ok { b"+" } else { b"-" })?;
+                if !ok {
+                    continue;
+                }
+            }
+            return Ok(len);
+        }
+    }
+
+    fn write_reply(&mut self) -> Result<(), C::Error> {
+        // in one write, so it isn't split into several TCP segments
+        let data = self.reply.as_bytes();
+        let mut frame = [0; PACKET_SIZE + 4];
+        frame[0] = b'$';
+        frame[1..=data.len()].copy_from_slice(data);
+        let sum = data.iter().fold(0_u8, |sum, &b| sum.wrapping_add(b));
+        let [hi, lo] = hex_byte(sum);
+        frame[data.len() + 1..data.len() + 4].copy_from_slice(&[b'#', hi, lo]);
+        self.conn.write(&frame[..data.len() + 4])
+    }
+
+    /// Handles a packet, leaving the reply in `self.reply`. Returns [`None`] for malformed
+    /// packets.
+    fn handle(&mut self, packet: &[u8]) -> Option<Then> {
+        let (&cmd, args) = packet.split_first()?;
+        let cpu = &mut self.debugger.cpu;
+
+        match cmd {
+            INTERRUPT => self.reply.stop(SIGINT),
+            b'?' => self.reply.stop(SIGTRAP),
+            b'g' => {
+                for byte in [cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.status.bits(), cpu.sp] {
+                    self.reply.push_hex(byte);
+                }
+                for byte in cpu.pc.to_le_bytes() {
+                    self.reply.push_hex(byte);
+                }
+            }
+            b'G' => {
+                let mut regs = [0; 7];
+                decode_hex(args, &mut regs)?;
+                let [a, x, y, p, sp, pc_lo, pc_hi] = regs;
+                (cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.sp) = (a, x, y, sp);
+                cpu.status = crate::cpu::Status::from_bits_retain(p);
+                cpu.pc = u16::from_le_bytes([pc_lo, pc_hi]);
+                self.reply.push_str("OK");
+            }
+            b'm' => {
+                let (addr, len) = split2(args, b',')?;
+                let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+                // each byte takes two characters
+                for offset in (0..len).take(PACKET_SIZE / 2) {
+                    self.reply.push_hex(cpu.bus.peek(addr.wrapping_add(offset)));
+                }
+            }
+            b'M' => {
+                let (range, data) = split2(args, b':')?;
+                let (addr, len) = split2(range, b',')?;
+                let (addr, len) = (parse_hex(addr)?, usize::from(parse_hex(len)?));
+                if data.len() != len * 2 {
+                    return None;
+                }
+                for (offset, pair) in (0..).zip(data.chunks(2)) {
+                    let val = u8::try_from(parse_hex(pair)?).ok()?;
+                    cpu.bus.mem_write(addr.wrapping_add(offset), val);
+                }
+                self.reply.push_str("OK");
+            }
+            b'Z' | b'z' => {
+                self.toggle_point(cmd == b'Z', args)?;
+                self.reply.push_str("OK");
+            }
+            b'c' | b's' => {
+                if !args.is_empty() {
+                    cpu.pc = parse_hex(args)?;
+                }
+                self.resume(cmd == b's');
+            }
+            b'D' => {
+                self.reply.push_str("OK");
+                return Some(Then::Quit);
+            }
+            b'k' => return Some(Then::Kill),
+            b'H' => self.reply.push_str("OK"),
+            b'q' | b'Q' => self.query(packet),
+            _ => {}
+        }
+        Some(Then::Reply)
+    }
+
+    fn query(&mut self, packet: &[u8]) {
+        const FEATURES: &[u8] = b"qXfer:features:read:target.xml:";
+
+        if packet.starts_with(b"qSupported") {
+            self.reply
+                .push_str("PacketSize=400;QStartNoAckMode+;qXfer:features:read+");
+        } else if packet == b"QStartNoAckMode" {
+            self.ack = false;
+            self.reply.push_str("OK");
+        } else if packet == b"qAttached" {
+            self.reply.push_str("1");
+        } else if let Some(range) = packet.strip_prefix(FEATURES) {
+            let Some((offset, len)) = split2(range, b',')
+                .and_then(|(offset, len)| Some((parse_hex(offset)?, parse_hex(len)?)))
+            else {
+                self.reply.push_str("E01");
+                return;
+            };
+            let xml = TARGET_XML.as_bytes();
+            let start = usize::from(offset).min(xml.len());
+            // leaves room for the `m` or `l` and escapes; the XML has none
+            let end = xml.len().min(start + usize::from(len).min(PACKET_SIZE - 1));
+            self.reply.push_str(if end < xml.len() { "m" } else { "l" });
+            self.reply.push(&xml[start..end]);
+        }
+    }
+
+    /// Adds (`insert`) or removes a breakpoint or watchpoint from the arguments of a `Z` or `z`
+    /// packet. Returns [`None`] if the packet is malformed, or there's no room.
+    fn toggle_point(&mut self, insert: bool, args: &[u8]) -> Option<()> {
+        let (&kind, args) = args.split_first()?;
+        let (addr, len) = split2(args.get(1..)?, b',')?;
+        let addr = parse_hex(addr)?;
+        // the length can be followed by conditions, which aren't supported
+        let len = len.split(|&b| b == b';').next()?;
+        let end = addr.wrapping_add(parse_hex(len)?.max(1) - 1);
+
+        let debugger = &mut *self.debugger;
+        let watch = match kind {
+            b'0' | b'1' => {
+                if insert {
+                    let condition = None;
+                    debugger
+                        .add_breakpoint(Breakpoint { addr, condition })
+                        .ok()?;
+                } else {
+                    let (id, _) = debugger.breakpoints().find(|(_, b)| b.addr == addr)?;
+                    debugger.remove_breakpoint(id);
+                }
+                return Some(());
+            }
+            b'2' => Watch::WRITE,
+            b'3' => Watch::READ,
+            b'4' => Watch::READ | Watch::WRITE,
+            _ => return None,
+        };
+        let point = Watchpoint {
+            start: addr,
+            end,
+            watch,
+            condition: None,
+        };
+        if insert {
+            debugger.add_watchpoint(point).ok()?;
+        } else {
+            let (id, _) = debugger.watchpoints().find(|(_, w)| **w == point)?;
+            debugger.remove_watchpoint(id);
+        }
+        Some(())
+    }
+
+    /// Runs the CPU for a step, or until it stops, and replies with why it stopped.
+    fn resume(&mut self, single: bool) {
+        let stop = loop {
+            let result = if single {
+                self.debugger.step_into()
+            } else {
+                // a frame at a time, to check for interrupts in between
+                self.debugger.run_to_frame()
+            };
+            if single || result != Ok(Stop::Done) {
+                break result;
+            }
+            // the connection's errors are left for the next read
+            if self.conn.interrupted().unwrap_or(true) {
+                break result;
+            }
+        };
+
+        match stop {
+            Ok(Stop::Done) if !single => self.reply.stop(SIGINT),
+            Ok(Stop::Watchpoint { id, access }) => {
+                let both = self
+                    .debugger
+                    .watchpoints()
+                    .any(|(i, w)| i == id && w.watch.contains(Watch::READ | Watch::WRITE));
+                let kind = match access.kind {
+                    _ if both => "awatch",
+                    AccessKind::Read | AccessKind::Execute => "rwatch",
+                    AccessKind::Write => "watch",
+                };
+                self.reply.push_str("T05");
+                self.reply.push_str(kind);
+                self.reply.push_str(":");
+                for byte in access.addr.to_be_bytes() {
+                    self.reply.push_hex(byte);
+                }
+                self.reply.push_str(";");
+            }
+            Ok(_) => self.reply.stop(SIGTRAP),
+            Err(_) => self.reply.stop(SIGILL),
+        }
+    }
+}
+
+/// A reply being built.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    const fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    const fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Appends `bytes`, dropping what doesn't fit.
+    fn push(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.push(s.as_bytes());
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        self.push(&hex_byte(byte));
+    }
+
+    /// A stop reply with a signal.
+    fn stop(&mut self, signal: u8) {
+        self.push_str("S");
+        self.push_hex(signal);
+    }
+}
+
+/// Splits at the first `sep`.
+fn split2(bytes: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = bytes.iter().position(|&b| b == sep)?;
+    Some((&bytes[..i], &bytes[i + 1..]))
+}
+
+fn parse_hex(hex: &[u8]) -> Option<u16> {
+    let hex = core::str::from_utf8(hex).ok()?;
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Decodes pairs of hex digits into `out`, which must be filled exactly.
+fn decode_hex(hex: &[u8], out: &mut [u8]) -> Option<()> {
+    if hex.len() != out.len() * 2 {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::try_from(parse_hex(pair)?).ok()?;
+    }
+    Some(())
+}
+
+const fn hex_byte(byte: u8) -> [u8; 2] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    [DIGITS[(byte >> 4) as usize], DIGITS[(byte & 0xF) as usize]]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        bus::Bus,
+        cpu::{Cpu, Status},
+        rom::Rom,
+        testing::test_rom,
+    };
+    use pretty_assertions::assert_eq;
+    use std::{
+        format,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        string::{String, ToString},
+        vec::Vec,
+    };
+
+    /// A client on the other end of a loopback socket.
+    struct Client {
+        stream: TcpStream,
+        ack: bool,
+    }
+
+    impl Client {
+        /// Sends a packet and returns the reply's data.
+        fn send(&mut self, data: &str) -> String {
+            let sum = data.bytes().fold(0_u8, u8::wrapping_add);
+            write!(self.stream, "${data}#{sum:02x}").unwrap();
+            if self.ack {
+                assert_eq!(self.byte(), b'+');
+            }
+            self.reply()
+        }
+
+        fn byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        fn reply(&mut self) -> String {
+            assert_eq!(self.byte(), b'$');
+            let mut data = Vec::new();
+            loop {
+                match self.byte() {
+                    b'#' => break,
+                    byte => data.push(byte),
+                }
+            }
+            let checksum = [self.byte(), self.byte()];
+            let sum = data.iter().fold(0_u8, |sum, &b| sum.wrapping_add(b));
+            assert_eq!(checksum, hex_byte(sum));
+            if self.ack {
+                self.stream.write_all(b"+").unwrap();
+            }
+            String::from_utf8(data).unwrap()
+        }
+    }
+
+    /// Serves a CPU with `prog` loaded at `$0600` over a loopback socket, for `client`.
+    #[allow(clippy::large_stack_frames)] // the bus is meant to live on the stack
+    fn session<'a>(raw: &'a [u8], prog: &[u8], client: impl FnOnce(&mut Client) + Send) -> Cpu<'a> {
+        let mut cpu = Cpu::new(Bus::new(Rom::new(raw).unwrap()));
+        cpu.load(prog);
+        let mut debugger = Debugger::new(cpu);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let stream = TcpStream::connect(addr).unwrap();
+                stream.set_nodelay(true).unwrap();
+                client(&mut Client { stream, ack: true });
+            });
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_nodelay(true).unwrap();
+            serve(&mut debugger, stream).unwrap();
+        });
+        debugger.cpu
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let raw = test_rom();
+        let cpu = session(&raw, &[0xEA], |c| {
+            assert_eq!(
+                c.send("qSupported:swbreak+"),
+                "PacketSize=400;QStartNoAckMode+;qXfer:features:read+"
+            );
+            assert_eq!(c.send("?"), "S05");
+            let p = Status::default().bits();
+            assert_eq!(c.send("g"), format!("000000{p:02x}fd0006"));
+            assert_eq!(c.send("G01020324ff0080"), "OK");
+            assert_eq!(c.send("g"), "01020324ff0080");
+
+            assert_eq!(c.send("M10,3:abcdef"), "OK");
+            assert_eq!(c.send("m0f,5"), "00abcdef00");
+            assert_eq!(c.send("M10,3:ab"), "E01");
+            // wraps around to $0000
+            assert_eq!(c.send("Mffff,2:0142"), "OK");
+            assert_eq!(c.send("m0,1"), "42");
+            assert_eq!(c.send("vMustReplyEmpty"), "");
+
+            let xml = c.send("qXfer:features:read:target.xml:0,400");
+            assert_eq!(xml, "l".to_string() + TARGET_XML);
+            assert_eq!(c.send("qXfer:features:read:target.xml:0,5"), "m<?xml");
+            assert_eq!(c.send("D"), "OK");
+        });
+        assert_eq!((cpu.reg_a, cpu.sp, cpu.pc), (0x01, 0xFF, 0x8000));
+        assert_eq!(cpu.bus.mem_read(0x0011), 0xCD);
+    }
+
+    #[test]
+    fn breakpoints() {
+        // 0600: LDX #$00
+        // 0602: INX
+        // 0603: STX $10
+        // 0605: JMP $0602
+        let prog = [0xA2, 0x00, 0xE8, 0x86, 0x10, 0x4C, 0x02, 0x06];
+        let raw = test_rom();
+        let cpu = session(&raw, &prog, |c| {
+            assert_eq!(c.send("s"), "S05");
+            assert_eq!(c.send("Z0,605,1"), "OK");
+            assert_eq!(c.send("c"), "S05");
+            assert_eq!(&c.send("g")[10..], "0506");
+            assert_eq!(c.send("z0,605,1"), "OK");
+            assert_eq!(c.send("z0,605,1"), "E01");
+
+            assert_eq!(c.send("Z2,10,1"), "OK");
+            assert_eq!(c.send("c"), "T05watch:0010;");
+            assert_eq!(c.send("m10,1"), "02");
+            assert_eq!(c.send("z2,10,1"), "OK");
+            assert_eq!(c.send("Z4,10,1"), "OK");
+            assert_eq!(c.send("c"), "T05awatch:0010;");
+
+            assert_eq!(c.send("QStartNoAckMode"), "OK");
+            c.ack = false;
+            // Ctrl-C stops it between frames
+            assert_eq!(c.send("z4,10,1"), "OK");
+            c.stream.write_all(b"$c#63\x03").unwrap();
+            assert_eq!(c.reply(), "S02");
+            c.stream.write_all(b"$k#6b").unwrap();
+        });
+        assert!(cpu.bus.ppu_position().frame >= 1);
+    }
+}
@@ -3,11 +3,36 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod asm;
+pub mod audio;
 pub mod bus;
+#[cfg(feature = "alloc")]
+pub mod cdl;
 pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod gdb;
+pub mod mapper;
 pub mod opcode;
+pub mod player;
+#[cfg(feature = "alloc")]
+pub mod profiler;
+#[cfg(feature = "alloc")]
+pub mod rewind;
 pub mod rom;
+#[cfg(feature = "romdb")]
+pub mod romdb;
+pub mod state;
+#[cfg(feature = "alloc")]
+pub mod symbols;
+pub mod tracer;
 
+mod crc;
 mod ppu;
 #[cfg(any(test, fete_doctest))]
 pub mod testing;
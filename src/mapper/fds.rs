@@ -0,0 +1,617 @@
+use super::Mapper;
+use crate::state::{self, fields, Reader, State, Writer};
+use crate::{
+    audio::{self, Expansion},
+    rom::{
+        fds::{Block, Side, SIDE_SIZE},
+        Mirroring, Rom,
+    },
+};
+use core::cell::Cell;
+
+/// Size of a side as the drive sees it, with gaps between the blocks and CRCs after them.
+/// Whatever's left after the last block is gap, so games can write new files.
+const DISK_SIZE: usize = 0x14000;
+/// Zero bytes before the first block: 28300 bits.
+const LEAD_IN: usize = 28300 / 8;
+/// Zero bytes after each block: 976 bits.
+const BLOCK_GAP: usize = 976 / 8;
+/// The byte ending a gap, just before a block.
+const GAP_MARK: u8 = 0x80;
+/// Cycles for the head to get back to the start of the disk.
+const SPIN_UP: u32 = 50_000;
+/// Cycles between bytes, at the drive's ~96.4kbit/s.
+const BYTE_CYCLES: u32 = 150;
+/// Cycles the drive reads as empty after swapping sides, so games notice the swap.
+const SWAP_CYCLES: u32 = 1_000_000;
+
+bitflags::bitflags! {
+    /// The drive control register, `$4025`.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Control: u8 {
+        const MOTOR          = 0b0000_0001;
+        /// Keeps the head at the start of the disk until it's scanning.
+        const RESET_TRANSFER = 0b0000_0010;
+        /// Reads when set, writes when clear.
+        const READ_MODE      = 0b0000_0100;
+        const HORIZONTAL     = 0b0000_1000;
+        /// Transfers the CRC instead of data.
+        const CRC_CONTROL    = 0b0001_0000;
+        /// Clear while the head is in a gap.
+        const DISK_READY     = 0b0100_0000;
+        const IRQ            = 0b1000_0000;
+    }
+}
+
+/// Mapper 20: the Famicom Disk System's RAM adapter.
+///
+/// The BIOS is mapped at `$E000`, and PRG-RAM fills `$6000..=$DFFF`. The timer and drive are
+/// controlled from `$4020..=$4033`, and the wavetable channel lives at `$4040..=$4097`.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the I/O registers
+pub struct Fds {
+    /// The inserted side, laid out as the drive sees it.
+    disk: [u8; DISK_SIZE],
+    /// Which side of the image is inserted.
+    side: Option<usize>,
+    /// Cycles until a swapped side can be seen.
+    swap_delay: u32,
+
+    /// `$4023` bit 0.
+    disk_io: bool,
+    /// `$4023` bit 1.
+    sound_io: bool,
+
+    timer_reload: u16,
+    timer: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: Cell<bool>,
+
+    control: Control,
+    write_data: u8,
+    read_data: u8,
+    transfer_complete: Cell<bool>,
+    disk_irq: Cell<bool>,
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+    /// Whether the CRC was being transferred on the last byte.
+    was_crc_control: bool,
+
+    audio: audio::Fds,
+}
+
+impl Fds {
+    /// Creates the RAM adapter with the first side of the ROM's disk inserted, if it has one.
+    #[must_use]
+    #[allow(clippy::large_stack_arrays)] // the bus is meant to live on the stack
+    pub fn new(rom: &Rom) -> Self {
+        let mut fds = Self {
+            disk: [0; DISK_SIZE],
+            side: None,
+            swap_delay: 0,
+            disk_io: true,
+            sound_io: true,
+            timer_reload: 0,
+            timer: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: Cell::new(false),
+            control: if rom.mirroring == Mirroring::Horizontal {
+                Control::HORIZONTAL
+            } else {
+                Control::empty()
+            },
+            write_data: 0,
+            read_data: 0,
+            transfer_complete: Cell::new(false),
+            disk_irq: Cell::new(false),
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            was_crc_control: false,
+            audio: audio::Fds::new(),
+        };
+        if let Some(side) = rom.disk.as_ref().and_then(|disk| disk.side(0)) {
+            fds.insert(0, side);
+        }
+        fds
+    }
+
+    /// The number of the inserted side, if there is one.
+    #[must_use]
+    pub const fn side(&self) -> Option<usize> {
+        self.side
+    }
+
+    /// Inserts a side into the drive, replacing the one in it. Writes to the old side are lost;
+    /// [save](Self::save) them first.
+    pub fn insert(&mut self, number: usize, side: Side) {
+        if self.side.is_some() {
+            self.swap_delay = SWAP_CYCLES;
+        }
+        self.side = Some(number);
+        self.end_of_head = true;
+
+        self.disk.fill(0);
+        let mut pos = LEAD_IN;
+        let mut file_size = 0;
+        let mut blocks = side.data;
+        while let Some(block) = blocks.first().copied().and_then(Block::from_type) {
+            let Some(data) = blocks.get(..block.len(file_size)) else {
+                break;
+            };
+            if block == Block::FileHeader {
+                file_size = u16::from_le_bytes([data[13], data[14]]);
+            }
+
+            let end = pos + 1 + data.len() + 2;
+            if end > DISK_SIZE {
+                log::warn!("disk side {number} doesn't fit in the drive, truncating");
+                break;
+            }
+            self.disk[pos] = GAP_MARK;
+            self.disk[pos + 1..end - 2].copy_from_slice(data);
+            let crc = block_crc(&self.disk[pos..end - 2]);
+            self.disk[end - 2..end].copy_from_slice(&crc.to_le_bytes());
+
+            pos = end + BLOCK_GAP;
+            blocks = &blocks[data.len()..];
+        }
+    }
+
+    /// Takes the disk out of the drive.
+    pub const fn eject(&mut self) {
+        self.side = None;
+    }
+
+    /// Copies the inserted side out in the image's layout, without the gaps and CRCs, so writes
+    /// can be saved back to the image.
+    pub fn save(&self, out: &mut [u8; SIDE_SIZE]) {
+        out.fill(0);
+        let mut pos = 0;
+        let mut out_pos = 0;
+        let mut file_size = 0;
+        while let Some(mark) = self.disk[pos..].iter().position(|&val| val == GAP_MARK) {
+            pos += mark + 1;
+            let Some(block) = self.disk.get(pos).copied().and_then(Block::from_type) else {
+                break;
+            };
+            let len = block.len(file_size);
+            let (Some(data), Some(dest)) = (
+                self.disk.get(pos..pos + len),
+                out.get_mut(out_pos..out_pos + len),
+            ) else {
+                break;
+            };
+            if block == Block::FileHeader {
+                file_size = u16::from_le_bytes([data[13], data[14]]);
+            }
+
+            dest.copy_from_slice(data);
+            out_pos += len;
+            pos += len + 2;
+        }
+    }
+
+    const fn inserted(&self) -> bool {
+        self.side.is_some() && self.swap_delay == 0
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+
+        if self.timer == 0 {
+            self.timer_irq.set(true);
+            self.timer = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Moves the head along the disk, transferring a byte every [`BYTE_CYCLES`].
+    fn clock_disk(&mut self) {
+        if !self.inserted() || !self.control.contains(Control::MOTOR) {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.control.contains(Control::RESET_TRANSFER) && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.end_of_head = false;
+            self.delay = SPIN_UP;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let irq = self.control.contains(Control::IRQ);
+        if self.control.contains(Control::READ_MODE) {
+            self.read_byte(irq);
+        } else {
+            self.write_byte(irq);
+        }
+        if !self.control.contains(Control::DISK_READY) {
+            self.crc = 0;
+        }
+        self.was_crc_control = self.control.contains(Control::CRC_CONTROL);
+
+        self.position += 1;
+        if self.position < DISK_SIZE {
+            self.delay = BYTE_CYCLES;
+        } else {
+            self.control.remove(Control::MOTOR);
+            self.disk_irq.set(irq);
+        }
+    }
+
+    fn read_byte(&mut self, mut irq: bool) {
+        let val = self.disk[self.position];
+        if !self.was_crc_control {
+            self.crc = crc_update(self.crc, val);
+        }
+
+        if !self.control.contains(Control::DISK_READY) {
+            self.gap_ended = false;
+        } else if val != 0 && !self.gap_ended {
+            // that's the gap mark, which doesn't need handling
+            self.gap_ended = true;
+            irq = false;
+        }
+
+        if self.gap_ended {
+            self.transfer_complete.set(true);
+            self.read_data = val;
+            if irq {
+                self.disk_irq.set(true);
+            }
+        }
+    }
+
+    fn write_byte(&mut self, irq: bool) {
+        let crc_control = self.control.contains(Control::CRC_CONTROL);
+        let mut val = 0;
+        if !crc_control {
+            self.transfer_complete.set(true);
+            val = self.write_data;
+            if irq {
+                self.disk_irq.set(true);
+            }
+        }
+        if !self.control.contains(Control::DISK_READY) {
+            val = 0;
+        }
+
+        if crc_control {
+            if !self.was_crc_control {
+                self.crc = crc_update(crc_update(self.crc, 0), 0);
+            }
+            val = self.crc.to_le_bytes()[0];
+            self.crc >>= 8;
+        } else {
+            self.crc = crc_update(self.crc, val);
+        }
+
+        self.disk[self.position] = val;
+        self.gap_ended = false;
+    }
+}
+
+impl Mapper for Fds {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        (addr >= 0xE000).then(|| usize::from(addr - 0xE000))
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        (0x6000..=0xDFFF)
+            .contains(&addr)
+            .then(|| usize::from(addr - 0x6000))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(addr)
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        let val = self.peek(addr);
+        match addr {
+            0x4030 if self.disk_io => {
+                self.timer_irq.set(false);
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            }
+            0x4031 if self.disk_io => {
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            }
+            _ => {}
+        }
+        val
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030 if self.disk_io => {
+                Some(u8::from(self.timer_irq.get()) | u8::from(self.transfer_complete.get()) << 1)
+            }
+            0x4031 if self.disk_io => Some(self.read_data),
+            0x4032 if self.disk_io => {
+                let empty = !self.inserted();
+                let not_ready = empty || !self.scanning;
+                // an inserted disk is never write-protected: writes change the image, which
+                // `Bus::save_disk` hands back to be saved
+                Some(u8::from(empty) | u8::from(not_ready) << 1 | u8::from(empty) << 2)
+            }
+            // the battery is good
+            0x4033 if self.disk_io => Some(0x80),
+            0x4040..=0x4097 if self.sound_io => self.audio.read(addr),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | u16::from(val),
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (u16::from(val) << 8),
+            0x4022 => {
+                self.timer_repeat = val & 0x01 != 0;
+                self.timer_enabled = val & 0x02 != 0 && self.disk_io;
+                if self.timer_enabled {
+                    self.timer = self.timer_reload;
+                } else {
+                    self.timer_irq.set(false);
+                }
+            }
+            0x4023 => {
+                self.disk_io = val & 0x01 != 0;
+                self.sound_io = val & 0x02 != 0;
+                if !self.disk_io {
+                    self.timer_enabled = false;
+                    self.timer_irq.set(false);
+                }
+            }
+            0x4024 if self.disk_io => {
+                self.write_data = val;
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            }
+            0x4025 if self.disk_io => {
+                self.control = Control::from_bits_retain(val);
+                self.disk_irq.set(false);
+            }
+            0x4040..=0x4097 if self.sound_io => self.audio.write(addr, val),
+            // the expansion port, and disabled registers
+            0x4024..=0x4097 => {}
+            _ => log::warn!("ignoring FDS write at: {addr:#02x}"),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(if self.control.contains(Control::HORIZONTAL) {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        })
+    }
+
+    fn clock(&mut self) {
+        self.clock_timer();
+        self.swap_delay = self.swap_delay.saturating_sub(1);
+        self.clock_disk();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+/// Feeds a byte into the disk's CRC-16, least significant bit first.
+const fn crc_update(mut crc: u16, val: u8) -> u16 {
+    let mut bit = 0;
+    while bit < 8 {
+        crc = (crc >> 1) | (((val >> bit) as u16 & 1) << 15);
+        if crc & 1 != 0 {
+            crc ^= 0x8408;
+        }
+        bit += 1;
+    }
+    crc
+}
+
+/// The CRC written after a block, which covers the gap mark before it.
+fn block_crc(block: &[u8]) -> u16 {
+    let crc = block.iter().fold(0, |crc, &val| crc_update(crc, val));
+    crc_update(crc_update(crc, 0), 0)
+}
+
+impl State for Control {
+    fn save(&self, w: &mut Writer) {
+        self.bits().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+        *self = Self::from_bits_retain(u8::from_le_bytes(r.array()?));
+        Ok(())
+    }
+}
+
+impl State for Fds {
+    fields!(
+        disk,
+        side,
+        swap_delay,
+        disk_io,
+        sound_io,
+        timer_reload,
+        timer,
+        timer_repeat,
+        timer_enabled,
+        timer_irq,
+        control,
+        write_data,
+        read_data,
+        transfer_complete,
+        disk_irq,
+        position,
+        delay,
+        end_of_head,
+        scanning,
+        gap_ended,
+        crc,
+        was_crc_control,
+        audio;
+        valid |m| m.position < DISK_SIZE
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::fds::{self, test::side};
+    use pretty_assertions::assert_eq;
+
+    fn fds(image: &[u8]) -> Fds {
+        Fds::new(&Rom::fds(&[0; fds::BIOS_SIZE], image).unwrap())
+    }
+
+    /// Waits for the next byte the drive transfers.
+    fn transfer(m: &mut Fds) {
+        while m.read(0x4030).unwrap() & 0x02 == 0 {
+            m.clock();
+        }
+    }
+
+    #[test]
+    fn gaps() {
+        let image = side();
+        let m = fds(&image);
+        assert_eq!(m.side(), Some(0));
+        assert_eq!(m.disk[LEAD_IN - 1], 0);
+        assert_eq!(m.disk[LEAD_IN], GAP_MARK);
+        assert_eq!(&m.disk[LEAD_IN + 1..LEAD_IN + 57], &image[..56]);
+        let crc = block_crc(&m.disk[LEAD_IN..LEAD_IN + 57]);
+        assert_eq!(m.disk[LEAD_IN + 57..LEAD_IN + 59], crc.to_le_bytes());
+        assert_eq!(m.disk[LEAD_IN + 59 + BLOCK_GAP], GAP_MARK);
+        assert_eq!(m.disk[LEAD_IN + 59 + BLOCK_GAP + 1], 2);
+
+        let mut out: Box<[u8; SIDE_SIZE]> = vec![0; SIDE_SIZE].try_into().unwrap();
+        m.save(&mut out);
+        assert_eq!(out.as_slice(), image.as_slice());
+    }
+
+    #[test]
+    fn read() {
+        let mut m = fds(&side());
+        m.write(0x4025, 0xC5); // IRQ, ready, read, motor
+        assert_eq!(m.read(0x4032), Some(0x02));
+
+        transfer(&mut m);
+        assert_eq!(m.read(0x4032), Some(0x00));
+        assert_eq!(m.read(0x4031), Some(GAP_MARK));
+        assert!(!m.irq());
+
+        while !m.irq() {
+            m.clock();
+        }
+        assert_eq!(m.peek(0x4030), Some(0x02));
+        assert_eq!(m.peek(0x4031), Some(1));
+        assert!(m.irq());
+        assert_eq!(m.read(0x4031), Some(1));
+        assert!(!m.irq());
+        transfer(&mut m);
+        assert_eq!(m.read(0x4031), Some(b'*'));
+    }
+
+    #[test]
+    fn write() {
+        let mut m = fds(&side());
+        m.write(0x4025, 0x41); // ready, write, motor
+        for val in [GAP_MARK, 0x12, 0x34] {
+            m.write(0x4024, val);
+            transfer(&mut m);
+        }
+        let pos = m.position - 3;
+        assert_eq!(&m.disk[pos..pos + 3], &[GAP_MARK, 0x12, 0x34]);
+
+        m.write(0x4025, 0x51); // CRC
+        for _ in 0..2 * (BYTE_CYCLES + 1) {
+            m.clock();
+        }
+        let crc = block_crc(&[GAP_MARK, 0x12, 0x34]);
+        assert_eq!(m.disk[pos + 3..pos + 5], crc.to_le_bytes());
+    }
+
+    #[test]
+    fn swap_sides() {
+        let image = side();
+        let mut m = fds(&image);
+        m.eject();
+        assert_eq!(m.read(0x4032), Some(0x07));
+
+        m.insert(1, Side { data: &image });
+        assert_eq!(m.side(), Some(1));
+        assert_eq!(m.read(0x4032), Some(0x02));
+
+        m.insert(0, Side { data: &image });
+        assert_eq!(m.read(0x4032), Some(0x07));
+        for _ in 0..SWAP_CYCLES {
+            m.clock();
+        }
+        assert_eq!(m.read(0x4032), Some(0x02));
+    }
+
+    #[test]
+    fn timer() {
+        let mut m = fds(&side());
+        m.write(0x4020, 0x02);
+        m.write(0x4021, 0x00);
+        m.write(0x4022, 0x03); // enabled, repeating
+
+        for _ in 0..3 {
+            assert!(!m.irq());
+            m.clock();
+        }
+        assert!(m.irq());
+        assert_eq!(m.read(0x4030), Some(0x01));
+        assert!(!m.irq());
+
+        for _ in 0..3 {
+            m.clock();
+        }
+        assert!(m.irq());
+
+        m.write(0x4023, 0x00);
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn memory() {
+        let m = fds(&side());
+        assert_eq!(m.prg_addr(0xDFFF), None);
+        assert_eq!(m.prg_addr(0xE000), Some(0));
+        assert_eq!(m.prg_ram_addr(0xDFFF), Some(0x7FFF));
+        assert_eq!(m.mirroring(), Some(Mirroring::Horizontal));
+    }
+}
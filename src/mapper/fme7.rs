@@ -0,0 +1,202 @@
+use super::{bank_count, vrc, Mapper};
+use crate::state::{fields, State};
+use crate::{
+    audio::{self, Expansion},
+    rom::{Mirroring, Rom},
+};
+
+/// Mapper 69: Sunsoft's FME-7, and the 5B which adds a PSG to it.
+///
+/// Registers are written by selecting a command at `$8000` and writing its parameter at
+/// `$A000`.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the IRQ control register
+pub struct Fme7 {
+    prg_banks: usize,
+    command: u8,
+    /// 1KiB banks.
+    chr: [u8; 8],
+    /// Command 8: `ER.B BBBB`. With `R` clear, ROM is mapped at `$6000`.
+    prg_6000: u8,
+    /// 8KiB banks at `$8000`, `$A000` and `$C000`.
+    prg: [u8; 3],
+    mirroring: Mirroring,
+    irq_enabled: bool,
+    counter_enabled: bool,
+    counter: u16,
+    irq_pending: bool,
+    audio: audio::Sunsoft5b,
+}
+
+impl Fme7 {
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            prg_banks: bank_count(rom.prg_rom.len(), 0x2000),
+            command: 0,
+            chr: [0; 8],
+            prg_6000: 0,
+            prg: [0; 3],
+            mirroring: rom.mirroring,
+            irq_enabled: false,
+            counter_enabled: false,
+            counter: 0,
+            irq_pending: false,
+            audio: audio::Sunsoft5b::new(),
+        }
+    }
+
+    const fn write_parameter(&mut self, val: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr[self.command as usize] = val,
+            0x8 => self.prg_6000 = val,
+            0x9..=0xB => self.prg[(self.command - 0x9) as usize] = val & 0x3F,
+            0xC => self.mirroring = vrc::mirroring(val),
+            0xD => {
+                self.irq_enabled = val & 0x01 != 0;
+                self.counter_enabled = val & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.counter = (self.counter & 0xFF00) | val as u16,
+            _ => self.counter = (self.counter & 0x00FF) | ((val as u16) << 8),
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x5FFF => return None,
+            0x6000..=0x7FFF if self.prg_6000 & 0x40 != 0 => return None,
+            0x6000..=0x7FFF => usize::from(self.prg_6000 & 0x3F),
+            0x8000..=0xDFFF => usize::from(self.prg[usize::from((addr - 0x8000) >> 13)]),
+            _ => self.prg_banks - 1,
+        };
+        Some((bank % self.prg_banks) * 0x2000 + usize::from(addr & 0x1FFF))
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        let enabled = self.prg_6000 & 0xC0 == 0xC0;
+        ((0x6000..=0x7FFF).contains(&addr) && enabled).then(|| usize::from(addr - 0x6000))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(self.chr[usize::from(addr >> 10) & 0b111]) * 0x0400 + usize::from(addr & 0x03FF)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.command = val & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(val),
+            0xC000..=0xDFFF => self.audio.select(val),
+            0xE000..=0xFFFF => self.audio.write(val),
+            _ => log::warn!("ignoring FME-7 write at: {addr:#02x}"),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn clock(&mut self) {
+        if self.counter_enabled {
+            self.counter = self.counter.wrapping_sub(1);
+            if self.counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl State for Fme7 {
+    fields!(
+        command,
+        chr,
+        prg_6000,
+        prg,
+        mirroring,
+        irq_enabled,
+        counter_enabled,
+        counter,
+        irq_pending,
+        audio,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rom::PRG_ROM_PAGE_SIZE,
+        testing::{create_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    fn fme7() -> Fme7 {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x50, 0x40, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 8 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        Fme7::new(&Rom::new(&raw).unwrap())
+    }
+
+    fn command(m: &mut Fme7, command: u8, val: u8) {
+        m.write(0x8000, command);
+        m.write(0xA000, val);
+    }
+
+    #[test]
+    fn prg() {
+        let mut m = fme7();
+        command(&mut m, 0x9, 0x01);
+        command(&mut m, 0xB, 0x03);
+        assert_eq!(m.prg_addr(0x8000), Some(0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(3 * 0x2000));
+        assert_eq!(m.prg_addr(0xE000), Some(15 * 0x2000));
+
+        command(&mut m, 0x8, 0x05);
+        assert_eq!(m.prg_addr(0x6000), Some(5 * 0x2000));
+        assert_eq!(m.prg_ram_addr(0x6000), None);
+        command(&mut m, 0x8, 0xC0);
+        assert_eq!(m.prg_addr(0x6000), None);
+        assert_eq!(m.prg_ram_addr(0x6000), Some(0));
+        command(&mut m, 0x8, 0x40); // RAM disabled
+        assert_eq!(m.prg_ram_addr(0x6000), None);
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut m = fme7();
+        command(&mut m, 0xC, 0x03);
+        assert_eq!(m.mirroring(), Some(Mirroring::SingleScreenUpper));
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = fme7();
+        command(&mut m, 0xE, 0x01);
+        command(&mut m, 0xF, 0x00);
+        command(&mut m, 0xD, 0x81);
+
+        m.clock(); // 0
+        assert!(!m.irq());
+        m.clock(); // wraps
+        assert!(m.irq());
+
+        command(&mut m, 0xD, 0x81);
+        assert!(!m.irq());
+    }
+}
@@ -0,0 +1,168 @@
+//! Cartridge boards, which map the CPU and PPU address spaces onto the ROM.
+#![allow(clippy::module_name_repetitions)]
+
+use crate::{
+    rom::{Mirroring, Rom},
+    state::{self, Reader, State, Writer},
+};
+
+pub mod fds;
+pub mod fme7;
+pub mod n163;
+pub mod nrom;
+pub mod nsf;
+pub mod vrc;
+
+pub use fds::Fds;
+pub use fme7::Fme7;
+pub use n163::N163;
+pub use nrom::Nrom;
+pub use nsf::Nsf;
+pub use vrc::{Vrc24, Vrc6, Vrc7};
+
+/// The interface every cartridge board implements.
+///
+/// Addresses handed to a mapper are raw CPU or PPU addresses; offsets it returns are
+/// relative to the start of [`Rom::prg_rom`] or [`Rom::chr_rom`] (or CHR-RAM, if the cartridge
+/// has no CHR ROM), and are wrapped to the length of the memory by the caller.
+pub trait Mapper {
+    /// Maps a CPU address in `$6000..=$FFFF` to an offset into PRG ROM. Returns [`None`] if
+    /// ROM isn't mapped there.
+    fn prg_addr(&self, addr: u16) -> Option<usize>;
+
+    /// Maps a CPU address to an offset into the cartridge's PRG-RAM, which is wrapped to its
+    /// size by the caller. Returns [`None`] if RAM isn't mapped there or is disabled.
+    ///
+    /// Only called for addresses [`Self::prg_addr`] doesn't map to ROM. By default, RAM is
+    /// always mapped into `$6000..=$7FFF`.
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        (0x6000..=0x7FFF)
+            .contains(&addr)
+            .then(|| usize::from(addr - 0x6000))
+    }
+
+    /// Maps a PPU address in `$0000..=$1FFF` to an offset into CHR ROM or RAM.
+    fn chr_addr(&self, addr: u16) -> usize;
+
+    /// Reads a register on the cartridge. Returns [`None`] for open bus.
+    fn read(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Reads a register like [`Self::read`], but without side effects such as acknowledging
+    /// IRQs, for debuggers. The default is [`Self::read`], for boards whose reads have none.
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.read(addr)
+    }
+
+    /// Writes to a register on the cartridge.
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// The nametable mirroring selected by the board, if it controls it.
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+
+    /// Advances the board by a single CPU cycle.
+    fn clock(&mut self) {}
+
+    /// Whether the board is asserting the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The current output level of the board's expansion audio, on the same scale as the
+    /// APU's mixer output (`0.0..=1.0`).
+    ///
+    /// There's no APU to mix it with yet, so this is all [`Bus::audio_sample`] plays.
+    ///
+    /// [`Bus::audio_sample`]: crate::bus::Bus::audio_sample
+    fn audio(&self) -> f32 {
+        0.0
+    }
+}
+
+macro_rules! boards {
+    ($($variant:ident),* $(,)?) => {
+        /// Every supported board.
+        #[derive(Debug, Clone)]
+        #[allow(clippy::large_enum_variant)] // there's no allocator to box the big ones with
+        pub enum Board {
+            $($variant($variant),)*
+        }
+
+        impl Mapper for Board {
+            fn prg_addr(&self, addr: u16) -> Option<usize> {
+                match self { $(Self::$variant(m) => m.prg_addr(addr),)* }
+            }
+            fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+                match self { $(Self::$variant(m) => m.prg_ram_addr(addr),)* }
+            }
+            fn chr_addr(&self, addr: u16) -> usize {
+                match self { $(Self::$variant(m) => m.chr_addr(addr),)* }
+            }
+            fn read(&self, addr: u16) -> Option<u8> {
+                match self { $(Self::$variant(m) => m.read(addr),)* }
+            }
+            fn peek(&self, addr: u16) -> Option<u8> {
+                match self { $(Self::$variant(m) => m.peek(addr),)* }
+            }
+            fn write(&mut self, addr: u16, val: u8) {
+                match self { $(Self::$variant(m) => m.write(addr, val),)* }
+            }
+            fn mirroring(&self) -> Option<Mirroring> {
+                match self { $(Self::$variant(m) => m.mirroring(),)* }
+            }
+            fn clock(&mut self) {
+                match self { $(Self::$variant(m) => m.clock(),)* }
+            }
+            fn irq(&self) -> bool {
+                match self { $(Self::$variant(m) => m.irq(),)* }
+            }
+            fn audio(&self) -> f32 {
+                match self { $(Self::$variant(m) => m.audio(),)* }
+            }
+        }
+
+        // the board comes from the ROM, so only its registers are saved
+        impl State for Board {
+            fn save(&self, w: &mut Writer) {
+                match self { $(Self::$variant(m) => State::save(m, w),)* }
+            }
+            fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+                match self { $(Self::$variant(m) => State::load(m, r),)* }
+            }
+        }
+    };
+}
+
+boards!(Nrom, N163, Fds, Nsf, Vrc24, Vrc6, Fme7, Vrc7);
+
+impl Board {
+    /// Creates the board for the mapper number in the given [`Rom`].
+    ///
+    /// Returns [`None`] if the mapper isn't supported.
+    #[must_use]
+    pub fn new(rom: &Rom) -> Option<Self> {
+        Some(match rom.mapper {
+            0 => Self::Nrom(Nrom::new(rom)),
+            19 => Self::N163(N163::new(rom)),
+            20 => Self::Fds(Fds::new(rom)),
+            21 | 22 | 23 | 25 => Self::Vrc24(Vrc24::new(rom)?),
+            24 | 26 => Self::Vrc6(Vrc6::new(rom)?),
+            31 => Self::Nsf(Nsf::new(rom)),
+            69 => Self::Fme7(Fme7::new(rom)),
+            85 => Self::Vrc7(Vrc7::new(rom)),
+            _ => return None,
+        })
+    }
+}
+
+/// Number of `size`-byte banks in `len` bytes, never zero so it can be used as a modulus.
+pub(crate) const fn bank_count(len: usize, size: usize) -> usize {
+    if len < size {
+        1
+    } else {
+        len / size
+    }
+}
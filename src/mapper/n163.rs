@@ -0,0 +1,187 @@
+use super::{bank_count, Mapper};
+use crate::state::{fields, State};
+use crate::{
+    audio::{self, Expansion},
+    rom::Rom,
+};
+
+/// Mapper 19: the Namco 163, with its wavetable synthesizer.
+///
+/// Nametable control (`$C000..=$DFFF`) isn't supported, as it needs the PPU to fetch
+/// nametables from CHR ROM; the header's mirroring is used instead.
+#[derive(Debug, Clone)]
+pub struct N163 {
+    prg_banks: usize,
+    /// 8KiB banks at `$8000`, `$A000` and `$C000`.
+    prg: [u8; 3],
+    /// 1KiB banks.
+    chr: [u8; 8],
+    /// 15-bit counter in the low bits, enable in bit 15.
+    irq_counter: u16,
+    audio: audio::N163,
+}
+
+impl N163 {
+    const IRQ_ENABLE: u16 = 0x8000;
+
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            prg_banks: bank_count(rom.prg_rom.len(), 0x2000),
+            prg: [0; 3],
+            chr: [0; 8],
+            irq_counter: 0,
+            audio: audio::N163::new(),
+        }
+    }
+}
+
+impl Mapper for N163 {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x7FFF => return None,
+            0x8000..=0xDFFF => usize::from(self.prg[usize::from((addr - 0x8000) >> 13)]),
+            _ => self.prg_banks - 1,
+        };
+        Some((bank % self.prg_banks) * 0x2000 + usize::from(addr & 0x1FFF))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(self.chr[usize::from(addr >> 10) & 0b111]) * 0x0400 + usize::from(addr & 0x03FF)
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.audio.read()),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        let [lo, hi] = self.irq_counter.to_le_bytes();
+        match addr {
+            0x4800..=0x4FFF => Some(self.audio.peek()),
+            0x5000..=0x57FF => Some(lo),
+            0x5800..=0x5FFF => Some(hi),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write(val),
+            0x5000..=0x57FF => self.irq_counter = (self.irq_counter & 0xFF00) | u16::from(val),
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (u16::from(val) << 8);
+            }
+            0x8000..=0xBFFF => self.chr[usize::from((addr - 0x8000) >> 11)] = val,
+            0xC000..=0xDFFF => log::warn!("ignoring N163 nametable write at: {addr:#02x}"),
+            0xE000..=0xE7FF => {
+                self.prg[0] = val & 0x3F;
+                self.audio.set_disabled(val & 0x40 != 0);
+            }
+            0xE800..=0xEFFF => self.prg[1] = val & 0x3F,
+            0xF000..=0xF7FF => self.prg[2] = val & 0x3F,
+            0xF800..=0xFFFF => self.audio.set_addr(val),
+            _ => log::warn!("ignoring N163 write at: {addr:#02x}"),
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.irq_counter & Self::IRQ_ENABLE != 0 && self.irq_counter & 0x7FFF != 0x7FFF {
+            self.irq_counter += 1;
+        }
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_counter == Self::IRQ_ENABLE | 0x7FFF
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl State for N163 {
+    fields!(prg, chr, irq_counter, audio);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rom::PRG_ROM_PAGE_SIZE,
+        testing::{create_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    fn n163() -> N163 {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x30, 0x10, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 8 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        N163::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn banks() {
+        let mut m = n163();
+        m.write(0xE000, 0x02);
+        m.write(0xE800, 0x03);
+        m.write(0xF000, 0x04);
+        assert_eq!(m.prg_addr(0x8000), Some(2 * 0x2000));
+        assert_eq!(m.prg_addr(0xA000), Some(3 * 0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(4 * 0x2000));
+        assert_eq!(m.prg_addr(0xE000), Some(15 * 0x2000));
+
+        m.write(0xB800, 0x42);
+        assert_eq!(m.chr_addr(0x1C00), 0x42 * 0x0400);
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = n163();
+        m.write(0x5000, 0xFD);
+        m.write(0x5800, 0xFF);
+        assert_eq!(m.read(0x5800), Some(0xFF));
+
+        m.clock();
+        assert!(!m.irq());
+        m.clock();
+        assert!(m.irq());
+        m.clock(); // stops at $7FFF
+        assert!(m.irq());
+
+        m.write(0x5800, 0x7F); // acknowledge
+        assert!(!m.irq());
+    }
+
+    #[test]
+    fn sound_ram() {
+        let mut m = n163();
+        m.write(0xF800, 0x80);
+        m.write(0x4800, 0x12);
+        m.write(0x4800, 0x34);
+        m.write(0xF800, 0x81);
+        assert_eq!(m.read(0x4800), Some(0x34));
+    }
+
+    #[test]
+    fn peek_sound_ram() {
+        let mut m = n163();
+        m.write(0xF800, 0x80);
+        m.write(0x4800, 0x12);
+        m.write(0x4800, 0x34);
+        m.write(0xF800, 0x80);
+        // peeking doesn't move on to the next byte
+        assert_eq!(m.peek(0x4800), Some(0x12));
+        assert_eq!(m.peek(0x4800), Some(0x12));
+        assert_eq!(m.read(0x4800), Some(0x12));
+        assert_eq!(m.read(0x4800), Some(0x34));
+    }
+}
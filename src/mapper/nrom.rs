@@ -0,0 +1,46 @@
+use super::Mapper;
+use crate::rom::Rom;
+use crate::state::{self, Reader, State, Writer};
+
+/// Mapper 0: no bank switching. 16KiB PRG ROMs are mirrored into `$C000..=$FFFF`.
+#[derive(Debug, Clone)]
+pub struct Nrom {
+    prg_len: usize,
+}
+
+impl Nrom {
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            prg_len: rom.prg_rom.len(),
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let addr = usize::from(addr.checked_sub(0x8000)?);
+        Some(if self.prg_len == 0x4000 && addr >= 0x4000 {
+            addr % 0x4000 // mirror, if needed
+        } else {
+            addr
+        })
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(addr)
+    }
+
+    fn write(&mut self, addr: u16, _val: u8) {
+        log::warn!("attempt to write to cartridge ROM: {addr:#02x}");
+    }
+}
+
+// nothing changes while it runs
+impl State for Nrom {
+    fn save(&self, _: &mut Writer) {}
+
+    fn load(&mut self, _: &mut Reader) -> Result<(), state::Error> {
+        Ok(())
+    }
+}
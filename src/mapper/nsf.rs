@@ -0,0 +1,282 @@
+use super::{bank_count, Mapper};
+use crate::state::{self, Reader, State, Writer};
+use crate::{
+    audio::{self, Expansion},
+    rom::{
+        nsf::{self, Chips},
+        Rom,
+    },
+};
+
+/// Mapper 31: the board NSF players run on, with eight 4KiB PRG banks selected at
+/// `$5FF8..=$5FFF`.
+///
+/// When it's running an NSF file, the board also has the expansion audio chips the tune uses.
+/// MMC5 audio isn't emulated.
+///
+/// FDS tunes run from the RAM adapter's PRG-RAM at `$6000..=$DFFF` instead, with only
+/// `$E000..=$FFFF` mapped to ROM. Writing a bank register for the RAM, at `$5FF6..=$5FFD`,
+/// has the [`Bus`](crate::bus::Bus) copy the bank into it; see [`Self::ram_window`].
+#[derive(Debug, Clone)]
+pub struct Nsf {
+    prg_banks: usize,
+    /// The banks at `$6000..=$FFFF`; see [`nsf::Nsf::initial_banks`].
+    banks: [u8; 10],
+    /// Bytes before the start of PRG ROM; NSF data is loaded part way into its first bank.
+    padding: usize,
+    vrc6: Option<audio::Vrc6>,
+    vrc7: Option<audio::Vrc7>,
+    fds: Option<audio::Fds>,
+    n163: Option<audio::N163>,
+    sunsoft5b: Option<audio::Sunsoft5b>,
+}
+
+impl Nsf {
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        let chips = rom.nsf.as_ref().map_or(Chips::empty(), |nsf| nsf.chips);
+        // without bankswitching, the data is loaded in one piece
+        let start = if chips.contains(Chips::FDS) {
+            0x6000
+        } else {
+            0x8000
+        };
+        let (banks, padding) =
+            rom.nsf
+                .as_ref()
+                .map_or(([0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF], 0), |nsf| {
+                    let padding = if nsf.banks.is_some() {
+                        nsf.load_addr & 0x0FFF
+                    } else {
+                        nsf.load_addr.saturating_sub(start)
+                    };
+                    (nsf.initial_banks(), usize::from(padding))
+                });
+        if chips.contains(Chips::MMC5) {
+            log::warn!("MMC5 audio isn't supported");
+        }
+
+        Self {
+            prg_banks: bank_count(rom.prg_rom.len() + padding, 0x1000),
+            banks,
+            padding,
+            vrc6: chips.contains(Chips::VRC6).then(audio::Vrc6::new),
+            vrc7: chips.contains(Chips::VRC7).then(audio::Vrc7::new),
+            fds: chips.contains(Chips::FDS).then(audio::Fds::new),
+            n163: chips.contains(Chips::N163).then(audio::N163::new),
+            sunsoft5b: chips
+                .contains(Chips::SUNSOFT_5B)
+                .then(audio::Sunsoft5b::new),
+        }
+    }
+
+    /// For FDS tunes, the offset into PRG-RAM of the 4KiB a write to the bank register at
+    /// `addr` switches, which the bus fills with [`Self::bank_addr`].
+    #[must_use]
+    pub fn ram_window(&self, addr: u16) -> Option<usize> {
+        (self.fds.is_some() && (0x5FF6..=0x5FFD).contains(&addr))
+            .then(|| usize::from(addr - 0x5FF6) * 0x1000)
+    }
+
+    /// Where a byte of the bank switched into `$6000..=$FFFF` at `window` (from
+    /// [`Self::ram_window`]) is in PRG ROM, or [`None`] if it's before the start of the data.
+    #[must_use]
+    pub fn bank_addr(&self, window: usize, offset: u16) -> Option<usize> {
+        let bank = usize::from(self.banks[window / 0x1000]) % self.prg_banks;
+        (bank * 0x1000 + usize::from(offset & 0x0FFF)).checked_sub(self.padding)
+    }
+}
+
+impl Mapper for Nsf {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let ram_end = if self.fds.is_some() { 0xE000 } else { 0x8000 };
+        if addr < ram_end {
+            return None;
+        }
+        self.bank_addr(usize::from(addr - 0x6000), addr)
+    }
+
+    fn prg_ram_addr(&self, addr: u16) -> Option<usize> {
+        let ram_end = if self.fds.is_some() { 0xDFFF } else { 0x7FFF };
+        (0x6000..=ram_end)
+            .contains(&addr)
+            .then(|| usize::from(addr - 0x6000))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(addr)
+    }
+
+    fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x4097 => self.fds.as_ref()?.read(addr),
+            0x4800..=0x4FFF => Some(self.n163.as_ref()?.read()),
+            _ => None,
+        }
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.n163.as_ref()?.peek()),
+            _ => self.read(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match (addr, self) {
+            (0x5FF6..=0x5FFF, m) => m.banks[usize::from(addr - 0x5FF6)] = val,
+            (0x4040..=0x4097, Self { fds: Some(fds), .. }) => fds.write(addr, val),
+            (
+                0x4800..=0x4FFF,
+                Self {
+                    n163: Some(n163), ..
+                },
+            ) => n163.write(val),
+            (
+                0xF800..=0xFFFF,
+                Self {
+                    n163: Some(n163), ..
+                },
+            ) => n163.set_addr(val),
+            (
+                0x9010,
+                Self {
+                    vrc7: Some(vrc7), ..
+                },
+            ) => vrc7.select(val),
+            (
+                0x9030,
+                Self {
+                    vrc7: Some(vrc7), ..
+                },
+            ) => vrc7.write(val),
+            (
+                0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002,
+                Self {
+                    vrc6: Some(vrc6), ..
+                },
+            ) => vrc6.write(addr, val),
+            (
+                0xC000,
+                Self {
+                    sunsoft5b: Some(psg),
+                    ..
+                },
+            ) => psg.select(val),
+            (
+                0xE000,
+                Self {
+                    sunsoft5b: Some(psg),
+                    ..
+                },
+            ) => psg.write(val),
+            _ => log::debug!("ignoring NSF write at: {addr:#02x}"),
+        }
+    }
+
+    fn clock(&mut self) {
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.clock();
+        }
+        if let Some(vrc7) = &mut self.vrc7 {
+            vrc7.clock();
+        }
+        if let Some(fds) = &mut self.fds {
+            fds.clock();
+        }
+        if let Some(n163) = &mut self.n163 {
+            n163.clock();
+        }
+        if let Some(psg) = &mut self.sunsoft5b {
+            psg.clock();
+        }
+    }
+
+    fn audio(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, Expansion::output)
+            + self.vrc7.as_ref().map_or(0.0, Expansion::output)
+            + self.fds.as_ref().map_or(0.0, Expansion::output)
+            + self.n163.as_ref().map_or(0.0, Expansion::output)
+            + self.sunsoft5b.as_ref().map_or(0.0, Expansion::output)
+    }
+}
+
+// the chips are the ones the ROM asks for, so there's no need to save which there are
+impl State for Nsf {
+    fn save(&self, w: &mut Writer) {
+        self.banks.save(w);
+        self.vrc6.iter().for_each(|chip| chip.save(w));
+        self.vrc7.iter().for_each(|chip| chip.save(w));
+        self.fds.iter().for_each(|chip| chip.save(w));
+        self.n163.iter().for_each(|chip| chip.save(w));
+        self.sunsoft5b.iter().for_each(|chip| chip.save(w));
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), state::Error> {
+        self.banks.load(r)?;
+        self.vrc6.iter_mut().try_for_each(|chip| chip.load(r))?;
+        self.vrc7.iter_mut().try_for_each(|chip| chip.load(r))?;
+        self.fds.iter_mut().try_for_each(|chip| chip.load(r))?;
+        self.n163.iter_mut().try_for_each(|chip| chip.load(r))?;
+        self.sunsoft5b.iter_mut().try_for_each(|chip| chip.load(r))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::nsf::test::nsf;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn banks() {
+        let mut raw = nsf(0x8000, 0x8000, &[0; 0x3000]);
+        raw[0x08..0x0A].copy_from_slice(&0x8100_u16.to_le_bytes());
+        let rom = Rom::new(&raw).unwrap();
+        let m = Nsf::new(&rom);
+        assert_eq!(m.prg_addr(0x80FF), None);
+        assert_eq!(m.prg_addr(0x8100), Some(0));
+        assert_eq!(m.prg_addr(0x9000), Some(0x0F00));
+
+        raw[0x70..0x78].copy_from_slice(&[0, 0, 1, 1, 2, 2, 0, 0]);
+        let rom = Rom::new(&raw).unwrap();
+        let mut m = Nsf::new(&rom);
+        assert_eq!(m.prg_addr(0x8100), Some(0));
+        assert_eq!(m.prg_addr(0xA100), Some(0x1000));
+        m.write(0x5FFA, 2);
+        assert_eq!(m.prg_addr(0xA100), Some(0x2000));
+    }
+
+    #[test]
+    fn fds_banks() {
+        // loaded in one piece from $6000, into RAM up to $DFFF
+        let mut raw = nsf(0x6000, 0x6000, &[0; 0xA000]);
+        raw[0x08..0x0A].copy_from_slice(&0x6000_u16.to_le_bytes());
+        raw[0x7B] = Chips::FDS.bits();
+        let rom = Rom::new(&raw).unwrap();
+        let m = Nsf::new(&rom);
+        assert_eq!(m.prg_addr(0xDFFF), None);
+        assert_eq!(m.prg_ram_addr(0xDFFF), Some(0x7FFF));
+        assert_eq!(m.prg_addr(0xE000), Some(0x8000));
+
+        assert_eq!(m.ram_window(0x5FF6), Some(0x0000));
+        assert_eq!(m.ram_window(0x5FFD), Some(0x7000));
+        assert_eq!(m.ram_window(0x5FFE), None);
+        assert_eq!(m.bank_addr(0x2000, 0x0001), Some(0x2001));
+    }
+
+    #[test]
+    fn chips() {
+        let raw = nsf(0x8000, 0x8000, &[0; 0x1000]);
+        let rom = Rom::new(&raw).unwrap();
+        let mut m = Nsf::new(&rom);
+        assert!(m.vrc6.is_some());
+        assert!(m.fds.is_none());
+
+        m.write(0xF800, 0x80); // auto-increment from 0
+        m.write(0x4800, 0x12);
+        m.write(0xF800, 0x00);
+        assert_eq!(m.read(0x4800), Some(0x12));
+        assert_eq!(m.read(0x4040), None);
+    }
+}
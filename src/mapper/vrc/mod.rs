@@ -0,0 +1,191 @@
+//! Konami's VRC family of boards.
+
+pub mod vrc24;
+pub mod vrc6;
+pub mod vrc7;
+
+pub use vrc24::Vrc24;
+pub use vrc6::Vrc6;
+pub use vrc7::Vrc7;
+
+use crate::rom::Mirroring;
+use crate::state::{fields, State};
+
+/// The IRQ counter shared by the VRC4, VRC6 and VRC7.
+///
+/// In scanline mode, a prescaler divides the CPU clock by 113⅔ (341 / 3) so the counter is
+/// clocked once per scanline; in cycle mode the counter is clocked every CPU cycle.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the control register
+pub struct Irq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl Irq {
+    const PRESCALER_RELOAD: i16 = 341;
+
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            latch: 0,
+            counter: 0,
+            prescaler: Self::PRESCALER_RELOAD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false,
+        }
+    }
+
+    /// Sets the whole reload value.
+    pub const fn write_latch(&mut self, val: u8) {
+        self.latch = val;
+    }
+
+    /// Sets the low 4 bits of the reload value (VRC4).
+    pub const fn write_latch_lo(&mut self, val: u8) {
+        self.latch = (self.latch & 0xF0) | (val & 0x0F);
+    }
+
+    /// Sets the high 4 bits of the reload value (VRC4).
+    pub const fn write_latch_hi(&mut self, val: u8) {
+        self.latch = (self.latch & 0x0F) | (val << 4);
+    }
+
+    /// Writes the control register; `---- -MEA`.
+    ///
+    /// Writing with `E` set reloads the counter and resets the prescaler. Any pending IRQ is acknowledged.
+    pub const fn write_control(&mut self, val: u8) {
+        self.enable_after_ack = val & 0b001 != 0;
+        self.enabled = val & 0b010 != 0;
+        self.cycle_mode = val & 0b100 != 0;
+        self.pending = false;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = Self::PRESCALER_RELOAD;
+        }
+    }
+
+    /// Acknowledges a pending IRQ, copying `A` into `E`.
+    pub const fn ack(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Advances the counter by one CPU cycle.
+    pub const fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += Self::PRESCALER_RELOAD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    const fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    #[must_use]
+    pub const fn pending(&self) -> bool {
+        self.pending
+    }
+}
+
+impl Default for Irq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The mirroring encoding used by the VRC4, VRC6, VRC7 and FME-7: `0` vertical, `1` horizontal, `2` one-screen lower, `3` one-screen upper.
+pub(crate) const fn mirroring(val: u8) -> Mirroring {
+    match val & 0b11 {
+        0 => Mirroring::Vertical,
+        1 => Mirroring::Horizontal,
+        2 => Mirroring::SingleScreenLower,
+        _ => Mirroring::SingleScreenUpper,
+    }
+}
+
+impl State for Irq {
+    fields!(
+        latch,
+        counter,
+        prescaler,
+        enabled,
+        enable_after_ack,
+        cycle_mode,
+        pending
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn irq_cycle_mode() {
+        let mut irq = Irq::new();
+        irq.write_latch(0xFD);
+        irq.write_control(0b110);
+
+        irq.clock(); // FE
+        irq.clock(); // FF
+        assert!(!irq.pending());
+        irq.clock(); // reload
+        assert!(irq.pending());
+        assert_eq!(irq.counter, 0xFD);
+
+        irq.ack();
+        assert!(!irq.pending());
+        assert!(!irq.enabled);
+    }
+
+    #[test]
+    fn irq_scanline_mode() {
+        let mut irq = Irq::new();
+        irq.write_latch(0xFF);
+        irq.write_control(0b011);
+
+        // 113⅔ cycles per scanline; the first scanline is 114 cycles.
+        for _ in 0..113 {
+            irq.clock();
+        }
+        assert!(!irq.pending());
+        irq.clock();
+        assert!(irq.pending());
+
+        // enable_after_ack keeps the counter running
+        irq.ack();
+        assert!(irq.enabled);
+    }
+
+    #[test]
+    fn irq_latch_nibbles() {
+        let mut irq = Irq::new();
+        irq.write_latch_lo(0x1A);
+        irq.write_latch_hi(0x0B);
+        assert_eq!(irq.latch, 0xBA);
+    }
+}
@@ -0,0 +1,257 @@
+use super::{mirroring, Irq};
+use crate::state::{fields, State};
+use crate::{
+    mapper::{bank_count, Mapper},
+    rom::{Mirroring, Rom},
+};
+
+/// Mappers 21, 22, 23 and 25: the VRC2 and VRC4.
+///
+/// The boards differ mostly in which CPU address lines are wired to the chip's two register
+/// select pins. iNES 1.0 headers can't tell the variants of a mapper number apart, so both
+/// candidate lines are combined, which works for every known game.
+///
+/// Mappers 23 and 25 also share a number between the VRC2 and VRC4, which only NES 2.0
+/// submapper 3 tells apart. Without it the board is treated as a VRC4, which VRC2 games run on
+/// unless they set the mirroring bit the VRC2 doesn't have.
+///
+/// | Mapper | Boards         | A0         | A1         |
+/// |--------|----------------|------------|------------|
+/// | 21     | VRC4a, VRC4c   | `A1`, `A6` | `A2`, `A7` |
+/// | 22     | VRC2a          | `A1`       | `A0`       |
+/// | 23     | VRC2b, VRC4e/f | `A0`, `A2` | `A1`, `A3` |
+/// | 25     | VRC2c, VRC4b/d | `A1`, `A3` | `A0`, `A2` |
+#[derive(Debug, Clone)]
+pub struct Vrc24 {
+    a0: u16,
+    a1: u16,
+    /// The VRC2 has no IRQ, a single mirroring bit and no PRG swap mode.
+    vrc2: bool,
+    /// Mapper 22 ignores the low bit of its CHR bank registers.
+    chr_shift: u8,
+
+    prg_banks: usize,
+    prg: [u8; 2],
+    prg_swap: bool,
+    chr: [u16; 8],
+    mirroring: u8,
+    irq: Irq,
+}
+
+impl Vrc24 {
+    /// Creates the board for the mapper number in `rom`; [`None`] if it isn't a VRC2/VRC4 mapper.
+    #[must_use]
+    pub fn new(rom: &Rom) -> Option<Self> {
+        let vrc2 = rom.submapper == 3;
+        let (a0, a1, vrc2) = match rom.mapper {
+            21 => (0x02 | 0x40, 0x04 | 0x80, false),
+            22 => (0x02, 0x01, true),
+            23 => (0x01 | 0x04, 0x02 | 0x08, vrc2),
+            25 => (0x02 | 0x08, 0x01 | 0x04, vrc2),
+            _ => return None,
+        };
+
+        Some(Self {
+            a0,
+            a1,
+            vrc2,
+            chr_shift: u8::from(rom.mapper == 22),
+            prg_banks: bank_count(rom.prg_rom.len(), 0x2000),
+            prg: [0, 1],
+            prg_swap: false,
+            chr: [0; 8],
+            mirroring: 0,
+            irq: Irq::new(),
+        })
+    }
+
+    /// Translates a CPU address into `$X000..=$X003`, according to the board's wiring.
+    fn register(&self, addr: u16) -> u16 {
+        let a0 = u16::from(addr & self.a0 != 0);
+        let a1 = u16::from(addr & self.a1 != 0);
+        (addr & 0xF000) | (a1 << 1) | a0
+    }
+}
+
+impl Mapper for Vrc24 {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        if addr < 0x8000 {
+            return None;
+        }
+        let second_last = self.prg_banks.saturating_sub(2);
+        let last = self.prg_banks - 1;
+
+        let bank = match (addr & 0xE000, self.prg_swap) {
+            (0x8000, false) | (0xC000, true) => usize::from(self.prg[0]),
+            (0xA000, _) => usize::from(self.prg[1]),
+            (0x8000, true) | (0xC000, false) => second_last,
+            _ => last,
+        };
+
+        Some((bank % self.prg_banks) * 0x2000 + usize::from(addr & 0x1FFF))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = self.chr[usize::from(addr >> 10) & 0b111] >> self.chr_shift;
+        usize::from(bank) * 0x0400 + usize::from(addr & 0x03FF)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg[0] = val & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = val & 0b01,
+            0x9000..=0x9001 => self.mirroring = val & 0b11,
+            0x9002..=0x9003 => self.prg_swap = val & 0b10 != 0,
+            0xA000..=0xA003 => self.prg[1] = val & 0x1F,
+            0xB000..=0xEFFF => {
+                let bank = usize::from((reg >> 12) - 0xB) * 2 + usize::from((reg >> 1) & 1);
+                let val = u16::from(val);
+                self.chr[bank] = if reg & 1 == 0 {
+                    (self.chr[bank] & 0x1F0) | (val & 0x0F)
+                } else {
+                    (self.chr[bank] & 0x00F) | ((val & 0x1F) << 4)
+                };
+            }
+            0xF000 if !self.vrc2 => self.irq.write_latch_lo(val),
+            0xF001 if !self.vrc2 => self.irq.write_latch_hi(val),
+            0xF002 if !self.vrc2 => self.irq.write_control(val),
+            0xF003 if !self.vrc2 => self.irq.ack(),
+            _ => log::warn!("ignoring VRC2/VRC4 write at: {addr:#02x}"),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(mirroring(self.mirroring))
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+}
+
+impl State for Vrc24 {
+    fields!(prg, prg_swap, chr, mirroring, irq);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rom::PRG_ROM_PAGE_SIZE,
+        testing::{create_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    fn vrc(mapper: u8) -> Vrc24 {
+        vrc_submapper(mapper, 0)
+    }
+
+    /// A board with an NES 2.0 header, for its submapper.
+    fn vrc_submapper(mapper: u8, submapper: u8) -> Vrc24 {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                0x08,
+                0x00,
+                mapper << 4,
+                (mapper & 0xF0) | u8::from(submapper != 0) << 3,
+                submapper << 4,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 8 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        Vrc24::new(&Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn address_lines() {
+        // VRC4a ($x002) and VRC4c ($x040) both select register 1: bank 0, high
+        let mut m = vrc(21);
+        m.write(0xB002, 0x05);
+        assert_eq!(m.chr[0], 0x50);
+        m.write(0xB040, 0x0A);
+        assert_eq!(m.chr[0], 0xA0);
+
+        // VRC4b has A0 and A1 swapped
+        let mut m = vrc(25);
+        m.write(0xB001, 0x03); // register 2: bank 1, low
+        m.write(0xB003, 0x01); // register 3: bank 1, high
+        assert_eq!(m.chr[1], 0x13);
+    }
+
+    #[test]
+    fn prg_swap() {
+        let mut m = vrc(23);
+        m.write(0x8000, 0x04);
+        m.write(0xA000, 0x05);
+        assert_eq!(m.prg_addr(0x8000), Some(4 * 0x2000));
+        assert_eq!(m.prg_addr(0xA000), Some(5 * 0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(14 * 0x2000));
+        assert_eq!(m.prg_addr(0xE000), Some(15 * 0x2000));
+
+        m.write(0x9002, 0b10);
+        assert_eq!(m.prg_addr(0x8000), Some(14 * 0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(4 * 0x2000));
+    }
+
+    #[test]
+    fn vrc2a_chr() {
+        let mut m = vrc(22);
+        m.write(0xB000, 0x03);
+        assert_eq!(m.chr_addr(0x0000), 0x0400);
+        assert_eq!(m.mirroring(), Some(Mirroring::Vertical));
+        m.write(0x9000, 0b11);
+        assert_eq!(m.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn vrc2_mirroring() {
+        // VRC4: both bits, and $9002 is the PRG swap mode
+        let mut m = vrc(21);
+        m.write(0x9000, 0b10);
+        assert_eq!(m.mirroring(), Some(Mirroring::SingleScreenLower));
+        m.write(0x9004, 0b10);
+        assert!(m.prg_swap);
+
+        // VRC2a: a single bit, at $9000..=$9003
+        let mut m = vrc(22);
+        m.write(0x9000, 0b10);
+        assert_eq!(m.mirroring(), Some(Mirroring::Vertical));
+        m.write(0x9001, 0b11);
+        assert_eq!(m.mirroring(), Some(Mirroring::Horizontal));
+        assert!(!m.prg_swap);
+
+        // VRC2b, which shares mapper 23 with the VRC4e/f
+        let mut m = vrc_submapper(23, 3);
+        m.write(0x9002, 0b10);
+        assert_eq!(m.mirroring(), Some(Mirroring::Vertical));
+        assert!(!m.prg_swap);
+    }
+
+    #[test]
+    fn irq() {
+        let mut m = vrc(25);
+        m.write(0xF000, 0x0F); // latch, low
+        m.write(0xF002, 0x0F); // latch, high: A1 is register select 0 on the VRC4b
+        m.write(0xF001, 0b110); // control
+        assert!(!m.irq());
+        m.clock();
+        assert!(m.irq());
+    }
+}
@@ -0,0 +1,204 @@
+use super::{mirroring, Irq};
+use crate::state::{fields, State};
+use crate::{
+    audio::{self, Expansion},
+    mapper::{bank_count, Mapper},
+    rom::{Mirroring, Rom},
+};
+
+/// Mappers 24 and 26: the VRC6, with two pulse channels and a sawtooth. Mapper 26 has the
+/// `A0` and `A1` register select lines swapped.
+#[derive(Debug, Clone)]
+pub struct Vrc6 {
+    swapped: bool,
+
+    prg_banks: usize,
+    /// 16KiB bank at `$8000`.
+    prg_16k: u8,
+    /// 8KiB bank at `$C000`.
+    prg_8k: u8,
+    chr: [u8; 8],
+    /// `$B003`: `W.PN MMDD`.
+    banking: u8,
+    irq: Irq,
+    audio: audio::Vrc6,
+}
+
+impl Vrc6 {
+    /// Creates the board for the mapper number in `rom`; [`None`] if it isn't a VRC6 mapper.
+    #[must_use]
+    pub fn new(rom: &Rom) -> Option<Self> {
+        let swapped = match rom.mapper {
+            24 => false,
+            26 => true,
+            _ => return None,
+        };
+
+        Some(Self {
+            swapped,
+            prg_banks: bank_count(rom.prg_rom.len(), 0x2000),
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            banking: 0,
+            irq: Irq::new(),
+            audio: audio::Vrc6::new(),
+        })
+    }
+
+    /// Translates a CPU address into `$X000..=$X003`, undoing mapper 26's swapped lines.
+    const fn register(&self, addr: u16) -> u16 {
+        let reg = addr & 0b11;
+        let reg = if self.swapped {
+            ((reg & 1) << 1) | (reg >> 1)
+        } else {
+            reg
+        };
+        (addr & 0xF000) | reg
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x7FFF => return None,
+            0x8000..=0xBFFF => usize::from(self.prg_16k) * 2 + usize::from((addr >> 13) & 1),
+            0xC000..=0xDFFF => usize::from(self.prg_8k),
+            _ => self.prg_banks - 1,
+        };
+        Some((bank % self.prg_banks) * 0x2000 + usize::from(addr & 0x1FFF))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let slot = usize::from(addr >> 10) & 0b111;
+        let a10 = usize::from((addr >> 10) & 1);
+
+        // mode 0 uses eight 1KiB banks; mode 1 uses R0-R3 as four 2KiB banks; modes 2 and 3 use
+        // R0-R3 as 1KiB banks for the first pattern table and R4-R5 as 2KiB banks for the second.
+        let bank = match (self.banking & 0b11, slot) {
+            (0, _) | (2 | 3, 0..=3) => usize::from(self.chr[slot]),
+            (1, _) => usize::from(self.chr[slot >> 1] & 0xFE) | a10,
+            _ => usize::from(self.chr[4 + ((slot - 4) >> 1)] & 0xFE) | a10,
+        };
+        bank * 0x0400 + usize::from(addr & 0x03FF)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let reg = self.register(addr);
+        match reg {
+            0x8000..=0x8003 => self.prg_16k = val & 0x0F,
+            0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => self.audio.write(reg, val),
+            0xB003 => self.banking = val,
+            0xC000..=0xC003 => self.prg_8k = val & 0x1F,
+            0xD000..=0xE003 => {
+                self.chr[usize::from((reg >> 12) - 0xD) * 4 + usize::from(reg & 0b11)] = val;
+            }
+            0xF000 => self.irq.write_latch(val),
+            0xF001 => self.irq.write_control(val),
+            0xF002 => self.irq.ack(),
+            _ => log::warn!("ignoring VRC6 write at: {addr:#02x}"),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(mirroring(self.banking >> 2))
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl State for Vrc6 {
+    fields!(prg_16k, prg_8k, chr, banking, irq, audio);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rom::PRG_ROM_PAGE_SIZE,
+        testing::{create_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    fn vrc6(mapper: u8) -> Vrc6 {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                0x10,
+                0x00,
+                mapper << 4,
+                mapper & 0xF0,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 16 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        Vrc6::new(&Rom::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn prg() {
+        let mut m = vrc6(24);
+        m.write(0x8000, 0x03);
+        m.write(0xC000, 0x11);
+        assert_eq!(m.prg_addr(0x8000), Some(6 * 0x2000));
+        assert_eq!(m.prg_addr(0xA000), Some(7 * 0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(0x11 * 0x2000));
+        assert_eq!(m.prg_addr(0xFFFF), Some(31 * 0x2000 + 0x1FFF));
+    }
+
+    #[test]
+    fn swapped_lines() {
+        let mut m = vrc6(26);
+        m.write(0xD001, 0x05); // R2
+        m.write(0xD002, 0x06); // R1
+        assert_eq!(m.chr[1..3], [0x06, 0x05]);
+
+        m.write(0xB003, 0b0000_0100); // $B003 isn't affected by the swap
+        assert_eq!(m.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut m = vrc6(24);
+        for (i, addr) in [0xD000, 0xD001, 0xD002, 0xD003, 0xE000, 0xE001]
+            .into_iter()
+            .enumerate()
+        {
+            m.write(addr, 0x10 + u8::try_from(i).unwrap());
+        }
+
+        assert_eq!(m.chr_addr(0x0C00), 0x13 * 0x0400);
+
+        m.write(0xB003, 1);
+        assert_eq!(m.chr_addr(0x0400), 0x11 * 0x0400);
+        assert_eq!(m.chr_addr(0x0800), 0x10 * 0x0400);
+
+        m.write(0xB003, 2);
+        assert_eq!(m.chr_addr(0x0C00), 0x13 * 0x0400);
+        assert_eq!(m.chr_addr(0x1800), 0x14 * 0x0400);
+        assert_eq!(m.chr_addr(0x1C00), 0x15 * 0x0400);
+    }
+}
@@ -0,0 +1,161 @@
+use super::{mirroring, Irq};
+use crate::state::{fields, State};
+use crate::{
+    audio::{self, Expansion},
+    mapper::{bank_count, Mapper},
+    rom::{Mirroring, Rom},
+};
+
+/// Mapper 85: the VRC7, with its 6-channel FM synthesizer.
+///
+/// Depending on the board revision, either `A4` or `A3` selects the second register at each
+/// address; both are accepted.
+#[derive(Debug, Clone)]
+pub struct Vrc7 {
+    prg_banks: usize,
+    prg: [u8; 3],
+    chr: [u8; 8],
+    /// `$E000`: `RS.. ..MM`.
+    control: u8,
+    irq: Irq,
+    audio: audio::Vrc7,
+}
+
+impl Vrc7 {
+    #[must_use]
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            prg_banks: bank_count(rom.prg_rom.len(), 0x2000),
+            prg: [0; 3],
+            chr: [0; 8],
+            control: 0,
+            irq: Irq::new(),
+            audio: audio::Vrc7::new(),
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn prg_addr(&self, addr: u16) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x7FFF => return None,
+            0x8000..=0xDFFF => usize::from(self.prg[usize::from((addr - 0x8000) >> 13)]),
+            _ => self.prg_banks - 1,
+        };
+        Some((bank % self.prg_banks) * 0x2000 + usize::from(addr & 0x1FFF))
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        usize::from(self.chr[usize::from(addr >> 10) & 0b111]) * 0x0400 + usize::from(addr & 0x03FF)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let reg = (addr & 0xF000) | if addr & 0x18 == 0 { 0 } else { 0x10 };
+        match reg {
+            0x8000 => self.prg[0] = val & 0x3F,
+            0x8010 => self.prg[1] = val & 0x3F,
+            0x9000 => self.prg[2] = val & 0x3F,
+            // $9030 needs A5, which the A3/A4 check above throws away
+            0x9010 if addr & 0x20 == 0 => self.audio.select(val),
+            0x9010 => self.audio.write(val),
+            0xA000..=0xD010 => {
+                let bank = usize::from((reg >> 12) - 0xA) * 2 + usize::from(reg & 0x10 != 0);
+                self.chr[bank] = val;
+            }
+            0xE000 => {
+                self.control = val;
+                self.audio.set_silenced(val & 0x40 != 0);
+            }
+            0xE010 => self.irq.write_latch(val),
+            0xF000 => self.irq.write_control(val),
+            0xF010 => self.irq.ack(),
+            _ => log::warn!("ignoring VRC7 write at: {addr:#02x}"),
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(mirroring(self.control))
+    }
+
+    fn clock(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn audio(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl State for Vrc7 {
+    fields!(prg, chr, control, irq, audio);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        rom::PRG_ROM_PAGE_SIZE,
+        testing::{create_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    fn vrc7() -> Vrc7 {
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x08, 0x00, 0x50, 0x50, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 8 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        Vrc7::new(&Rom::new(&raw).unwrap())
+    }
+
+    #[test]
+    fn prg() {
+        let mut m = vrc7();
+        m.write(0x8000, 1);
+        m.write(0x8010, 2); // VRC7a
+        m.write(0x9000, 3);
+        assert_eq!(m.prg_addr(0x8000), Some(0x2000));
+        assert_eq!(m.prg_addr(0xA000), Some(2 * 0x2000));
+        assert_eq!(m.prg_addr(0xC000), Some(3 * 0x2000));
+        assert_eq!(m.prg_addr(0xE000), Some(15 * 0x2000));
+
+        m.write(0x8008, 4); // VRC7b
+        assert_eq!(m.prg_addr(0xA000), Some(4 * 0x2000));
+    }
+
+    #[test]
+    fn chr_and_mirroring() {
+        let mut m = vrc7();
+        m.write(0xA000, 1);
+        m.write(0xD010, 2);
+        assert_eq!(m.chr_addr(0x0000), 0x0400);
+        assert_eq!(m.chr_addr(0x1C00), 0x0800);
+
+        m.write(0xE000, 0b11);
+        assert_eq!(m.mirroring(), Some(Mirroring::SingleScreenUpper));
+    }
+
+    #[test]
+    fn audio_registers() {
+        let mut m = vrc7();
+        for (reg, val) in [(0x10, 0xAC), (0x30, 0x30), (0x20, 0x18)] {
+            m.write(0x9010, reg);
+            m.write(0x9030, val);
+        }
+
+        let mut peak = 0.0_f32;
+        for _ in 0..36 * 1000 {
+            m.clock();
+            peak = peak.max(m.audio().abs());
+        }
+        assert!(peak > 0.01);
+    }
+}
@@ -3,6 +3,12 @@ use crate::cpu::{AddressingMode, Cpu, Status};
 fn branch_if(cpu: &mut Cpu, mode: AddressingMode, cond: bool) {
     let addr = cpu.get_op_addr(mode);
     if cond {
+        // a taken branch takes a cycle, and another if it lands on a different page
+        cpu.bus.tick(if cpu.pc & 0xFF00 == addr & 0xFF00 {
+            1
+        } else {
+            2
+        });
         cpu.pc = addr;
     }
 }
@@ -104,10 +104,10 @@ pub static OPCODES: Map<u8, OpCode> = opcodes! {
     0xBA_u8 => (stack::tsx, NoneAddressing, 1, 2),
 
     0x48_u8 => (stack::pha, NoneAddressing, 1, 3),
-    0x68_u8 => (stack::pla, NoneAddressing, 1, 3),
+    0x68_u8 => (stack::pla, NoneAddressing, 1, 4),
 
     0x08_u8 => (stack::php, NoneAddressing, 1, 3),
-    0x28_u8 => (stack::plp, NoneAddressing, 1, 3),
+    0x28_u8 => (stack::plp, NoneAddressing, 1, 4),
 
 
     0xE8_u8 => (inc_dec::inx, NoneAddressing, 1, 2),
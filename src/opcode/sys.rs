@@ -44,17 +44,34 @@ pub fn nop(_cpu: &mut Cpu, _mode: AddressingMode) {
 /// Returns from an interrupt processing routine. Pops the value on the stack into the status register, followed by the program counter.
 ///
 /// # Examples
-/// ```ignore
+/// ```
 /// # use pretty_assertions::assert_eq;
 /// # use fete::{bus::Bus, rom::Rom, testing::test_rom};
-/// use fete::cpu::Cpu;
+/// use fete::cpu::{Cpu, Status};
 ///
 /// # let rom = test_rom();
 /// # let bus = Bus::new(Rom::new(&rom).unwrap());
 /// let mut cpu = Cpu::new(bus);
 ///
-/// todo!();
+/// // LDA #$06
+/// // PHA
+/// // LDA #$0A
+/// // PHA
+/// // LDA #$01
+/// // PHA
+/// // RTI
+/// // BRK
+/// cpu.load_and_run(&[
+///     0xA9, 0x06, 0x48, 0xA9, 0x0A, 0x48, 0xA9, 0x01, 0x48, 0x40, 0x00,
+/// ])
+/// .unwrap();
+///
+/// assert_eq!(cpu.pc, 0x060C);
+/// assert_eq!(cpu.sp, Cpu::STACK_RESET);
+/// assert_eq!(cpu.status, Status::CARRY | Status::BREAK | Status::BREAK2);
 /// ```
-pub fn rti(_cpu: &mut Cpu, _mode: AddressingMode) {
-    todo!("interrupts");
+pub fn rti(cpu: &mut Cpu, _mode: AddressingMode) {
+    let status = cpu.pop();
+    cpu.status = (Status::from_bits_truncate(status) | Status::BREAK2) & !Status::BREAK;
+    cpu.pc = cpu.pop_u16();
 }
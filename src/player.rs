@@ -0,0 +1,278 @@
+//! Plays NSF music files by driving the [`Cpu`] like the player ROM on an NSF cartridge would:
+//! INIT is called once for the song, then PLAY at the rate the file asks for.
+
+use crate::{
+    bus::Bus,
+    cpu::{self, Cpu, Status},
+    rom::{
+        nsf::{Chips, Nsf},
+        Rom, Timing,
+    },
+};
+use snafu::prelude::*;
+
+/// Where INIT and PLAY return to. Nothing is mapped here, and it's never executed; the player
+/// idles while the program counter is on it.
+const RETURN_ADDR: u16 = 0x4100;
+/// How long INIT can run for before the player gives up waiting for it to return.
+const INIT_TIMEOUT: u64 = 1_789_773;
+
+const NTSC_CPU_HZ: u64 = 1_789_773;
+const PAL_CPU_HZ: u64 = 1_662_607;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("not an NSF file"))]
+    NotNsf,
+    #[snafu(display("there are {songs} songs, so there's no song {song}"))]
+    NoSuchSong { song: u8, songs: u8 },
+    #[snafu(context(false), display("CPU error: {source}"))]
+    Cpu { source: cpu::Error },
+}
+
+/// An NSF player.
+///
+/// # Examples
+/// ```no_run
+/// # use fete::{player::Player, rom::Rom};
+/// # let raw = [];
+/// let mut player = Player::new(Rom::new(&raw).unwrap()).unwrap();
+/// player.start(2).unwrap();
+///
+/// let mut samples = Vec::new();
+/// for _ in 0..60 {
+///     player
+///         .frame(|level, cycles| samples.push((level, cycles)))
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Player<'rom> {
+    pub cpu: Cpu<'rom>,
+    nsf: Nsf<'rom>,
+    song: u8,
+    pal: bool,
+    /// CPU cycles between PLAY calls.
+    period: u64,
+    /// The cycle PLAY is next called on.
+    next_play: u64,
+    /// The number of times PLAY has been called.
+    plays: u64,
+}
+
+impl<'rom> Player<'rom> {
+    /// Creates a player for an NSF [`Rom`], and starts the file's starting song.
+    ///
+    /// PAL-only files are played at PAL speed; everything else at NTSC speed.
+    ///
+    /// # Errors
+    /// Errors if the ROM isn't an NSF file, or INIT hits an invalid opcode.
+    #[allow(clippy::large_stack_frames)] // the bus is meant to live on the stack
+    pub fn new(rom: Rom<'rom>) -> Result<Self, Error> {
+        let nsf = rom.nsf.clone().ok_or(Error::NotNsf)?;
+        let pal = nsf.timing == Timing::Pal;
+        let period = if pal {
+            u64::from(nsf.pal_speed) * PAL_CPU_HZ / 1_000_000
+        } else {
+            u64::from(nsf.ntsc_speed) * NTSC_CPU_HZ / 1_000_000
+        };
+
+        let mut player = Self {
+            cpu: Cpu::new(Bus::new(rom)),
+            song: nsf.starting_song,
+            nsf,
+            pal,
+            period: period.max(1),
+            next_play: 0,
+            plays: 0,
+        };
+        player.start(player.song)?;
+        Ok(player)
+    }
+
+    /// The file being played, with its metadata.
+    #[must_use]
+    pub const fn nsf(&self) -> &Nsf<'rom> {
+        &self.nsf
+    }
+
+    /// The song being played, counting from 0.
+    #[must_use]
+    pub const fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Starts a song, counting from 0: clears RAM, resets the banks and runs INIT.
+    ///
+    /// # Errors
+    /// Errors if there's no such song, or INIT hits an invalid opcode.
+    pub fn start(&mut self, song: u8) -> Result<(), Error> {
+        ensure!(
+            song < self.nsf.songs,
+            NoSuchSongSnafu {
+                song,
+                songs: self.nsf.songs
+            }
+        );
+        self.song = song;
+
+        // FDS tunes have RAM up to $DFFF, which the banks are copied into
+        let ram_end = if self.nsf.chips.contains(Chips::FDS) {
+            0xDFFF
+        } else {
+            0x7FFF
+        };
+        self.cpu.bus.vram.fill(0);
+        for addr in 0x6000..=ram_end {
+            self.cpu.bus.mem_write(addr, 0);
+        }
+        for (addr, bank) in (0x5FF6..).zip(self.nsf.initial_banks()) {
+            self.cpu.bus.mem_write(addr, bank);
+        }
+
+        self.cpu.reg_a = song;
+        self.cpu.reg_x = u8::from(self.pal);
+        self.cpu.reg_y = 0;
+        self.cpu.status = Status::default();
+        self.cpu.sp = Cpu::STACK_RESET;
+        self.call(self.nsf.init_addr);
+
+        let timeout = self.cpu.bus.cycles() + INIT_TIMEOUT;
+        while self.cpu.pc != RETURN_ADDR {
+            if self.cpu.bus.cycles() > timeout {
+                log::warn!("INIT didn't return, playing anyway");
+                break;
+            }
+            self.cpu.tick()?;
+        }
+        self.next_play = self.cpu.bus.cycles() + self.period;
+        Ok(())
+    }
+
+    /// Jumps to a subroutine that returns to [`RETURN_ADDR`], pushing it like JSR does.
+    fn call(&mut self, addr: u16) {
+        self.cpu.push_u16(RETURN_ADDR - 1);
+        self.cpu.pc = addr;
+    }
+
+    /// Runs a single instruction, or idles for a cycle while waiting to call PLAY. Returns the
+    /// number of cycles that took.
+    ///
+    /// # Errors
+    /// Errors if the tune hits an invalid opcode.
+    pub fn step(&mut self) -> Result<u64, Error> {
+        let start = self.cpu.bus.cycles();
+        if self.cpu.pc == RETURN_ADDR {
+            self.cpu.bus.tick(1);
+        } else {
+            self.cpu.tick()?;
+        }
+
+        let now = self.cpu.bus.cycles();
+        if self.cpu.pc == RETURN_ADDR && now >= self.next_play {
+            self.next_play += self.period;
+            self.plays += 1;
+            self.call(self.nsf.play_addr);
+        }
+        Ok(now - start)
+    }
+
+    /// Runs until PLAY is next called, passing the audio level after every [step](Self::step)
+    /// to `sample`, along with how many cycles it lasted.
+    ///
+    /// # Errors
+    /// Errors if the tune hits an invalid opcode.
+    pub fn frame(&mut self, mut sample: impl FnMut(f32, u64)) -> Result<(), Error> {
+        let plays = self.plays;
+        while self.plays == plays {
+            let cycles = self.step()?;
+            sample(self.cpu.bus.audio_sample(), cycles);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::nsf::test::nsf;
+    use pretty_assertions::assert_eq;
+
+    fn tune() -> Vec<u8> {
+        nsf(
+            0x8000,
+            0x8003,
+            &[
+                0x85, 0x00, // INIT: STA $00
+                0x60, // RTS
+                0xE6, 0x01, // PLAY: INC $01
+                0x60, // RTS
+            ],
+        )
+    }
+
+    #[test]
+    fn play() {
+        let raw = tune();
+        let mut player = Player::new(Rom::new(&raw).unwrap()).unwrap();
+        assert_eq!(player.song(), 1);
+        assert_eq!(player.cpu.bus.mem_read(0x00), 1);
+
+        player.frame(|_, _| {}).unwrap();
+        assert_eq!(player.cpu.pc, 0x8003);
+        let mut cycles = 0;
+        player.frame(|_, step| cycles += step).unwrap();
+        assert_eq!(cycles, player.period);
+        assert_eq!(player.cpu.bus.mem_read(0x01), 1);
+
+        player.start(2).unwrap();
+        assert_eq!(player.cpu.bus.mem_read(0x00), 2);
+        assert_eq!(player.cpu.bus.mem_read(0x01), 0);
+    }
+
+    #[test]
+    fn fds() {
+        let mut code = vec![
+            0xA9, 0xAB, // INIT: LDA #$AB
+            0x8D, 0x00, 0x90, // STA $9000
+            0xA9, 0x03, // LDA #3
+            0x8D, 0xF6, 0x5F, // STA $5FF6
+            0x60, // RTS
+            0x60, // PLAY: RTS
+        ];
+        code.resize(0x1000, 0);
+        for bank in [0x11, 0x22, 0x33] {
+            code.extend([bank; 0x1000]);
+        }
+        let mut raw = nsf(0x8000, 0x800B, &code);
+        raw[0x70..0x78].copy_from_slice(&[0, 1, 2, 2, 2, 2, 0, 0]);
+        raw[0x7B] = Chips::FDS.bits();
+
+        let player = Player::new(Rom::new(&raw).unwrap()).unwrap();
+        let bus = &player.cpu.bus;
+        // $6000..=$DFFF is RAM, filled from the banks
+        assert_eq!(bus.mem_read(0x9000), 0xAB);
+        assert_eq!(bus.mem_read(0x9001), 0x11);
+        assert_eq!(bus.mem_read(0xA000), 0x22);
+        assert_eq!(bus.mem_read(0x6000), 0x33);
+        assert_eq!(bus.mem_read(0x7000), 0xA9);
+        // and $E000..=$FFFF is ROM
+        assert_eq!(bus.mem_read(0xE000), 0xA9);
+    }
+
+    #[test]
+    fn errors() {
+        let raw = tune();
+        let mut player = Player::new(Rom::new(&raw).unwrap()).unwrap();
+        assert!(matches!(
+            player.start(3),
+            Err(Error::NoSuchSong { song: 3, songs: 3 })
+        ));
+
+        let raw = crate::testing::test_rom();
+        assert!(matches!(
+            Player::new(Rom::new(&raw).unwrap()),
+            Err(Error::NotNsf)
+        ));
+    }
+}
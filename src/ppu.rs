@@ -1,7 +1,8 @@
 use crate::rom::Mirroring;
 
-pub struct Ppu<'rom> {
-    pub chr_rom: &'rom [u8],
+/// Pattern tables aren't stored here: they're on the cartridge, and read through
+/// [`Bus::chr_read`](crate::bus::Bus::chr_read) so mappers can bank them and CHR-RAM can be written.
+pub struct Ppu {
     pub palette_table: [u8; 32],
     pub vram: [u8; 2048],
     pub oam_data: [u8; 256],
@@ -10,10 +11,9 @@ pub struct Ppu<'rom> {
     pub addr: AddrRegister,
 }
 
-impl<'rom> Ppu<'rom> {
-    pub const fn new(chr_rom: &'rom [u8], mirroring: Mirroring) -> Self {
+impl Ppu {
+    pub const fn new(mirroring: Mirroring) -> Self {
         Self {
-            chr_rom,
             vram: [0; 2048],
             oam_data: [0; 256],
             palette_table: [0; 32],
@@ -0,0 +1,552 @@
+//! A profiler, which counts the cycles spent at each address and in each routine as the CPU
+//! runs.
+//!
+//! Routines are found from `JSR` and interrupts, and left with `RTS` and `RTI`, building a call
+//! tree. A return only leaves the routines whose stack frames it pops, so code that jumps with
+//! `RTS` by pushing an address first stays in the routine it's in.
+//!
+//! Results can be written as folded stacks, for tools like `inferno` and `flamegraph.pl`, or as
+//! a report of the routines that took the most cycles.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, rom::Rom};
+//! use fete::{asm, cpu::Cpu, profiler::Profiler};
+//!
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//! cpu.load(&asm!(
+//!     "
+//!         JSR wait
+//!         BRK
+//!     wait:
+//!         NOP
+//!         NOP
+//!         RTS
+//!     "
+//! ));
+//!
+//! let mut profiler = Profiler::new();
+//! while !profiler.tick(&mut cpu).unwrap() {}
+//! assert_eq!(profiler.folded().to_string(), "root 13\nroot;$0604 10\n");
+//! ```
+
+use crate::{
+    bus::{AccessKind, Bus},
+    cpu::{self, Cpu},
+    disasm::{Labels, OperandAddr},
+};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::fmt;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+/// The cycles [`Cpu::interrupt`] takes.
+const INTERRUPT_CYCLES: u64 = 7;
+
+/// How a routine was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Entry {
+    /// Where the profiler started, outside any routine it saw entered.
+    Root,
+    /// A `JSR` to an address.
+    Call(u16),
+    /// An interrupt, whose handler is at an address.
+    Interrupt(u16),
+}
+
+/// A routine, with the cycles spent in it everywhere it's called from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Routine {
+    pub entry: Entry,
+    pub calls: u64,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the routine and the routines it calls.
+    pub total_cycles: u64,
+}
+
+/// A routine in the call tree, entered from a particular caller.
+#[derive(Debug, Clone)]
+struct Node {
+    entry: Entry,
+    parent: usize,
+    calls: u64,
+    cycles: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    /// The cycles spent at each address.
+    cycles: Vec<u64>,
+    /// The call tree; the root is first, and children always come after their parents.
+    nodes: Vec<Node>,
+    children: BTreeMap<(usize, Entry), usize>,
+    /// The routines that haven't returned yet, with the stack pointer from before each was
+    /// entered.
+    stack: Vec<(usize, u8)>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            cycles: vec![0; 0x10000],
+            nodes: vec![Node {
+                entry: Entry::Root,
+                parent: 0,
+                calls: 1,
+                cycles: 0,
+            }],
+            children: BTreeMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Forgets everything profiled so far, treating the current routine as the root.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Runs an instruction with [`Cpu::tick`], counting its cycles.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode.
+    pub fn tick(&mut self, cpu: &mut Cpu) -> Result<bool, cpu::Error> {
+        let (pc, sp) = (cpu.pc, cpu.sp);
+        let opcode = cpu.bus.peek(pc);
+        let target = cpu.bus.peek_u16(pc.wrapping_add(1));
+        let start = cpu.bus.cycles();
+
+        cpu.bus.record_accesses(true);
+        let result = cpu.tick();
+        let interrupted = interrupted(&cpu.bus);
+        cpu.bus.record_accesses(false);
+        let brk = result?;
+
+        // includes taken branches and page crossings, but not the interrupt
+        let interrupt_cycles = if interrupted { INTERRUPT_CYCLES } else { 0 };
+        self.count(pc, cpu.bus.cycles() - start - interrupt_cycles);
+
+        // where the stack was before an interrupt pushed to it
+        let sp_after = if interrupted {
+            cpu.sp.wrapping_add(3)
+        } else {
+            cpu.sp
+        };
+        match opcode {
+            JSR => self.enter(Entry::Call(target), sp),
+            RTS | RTI => {
+                while self
+                    .stack
+                    .last()
+                    .is_some_and(|&(_, entered)| entered <= sp_after)
+                {
+                    self.stack.pop();
+                }
+            }
+            _ => {}
+        }
+        if interrupted {
+            self.enter(Entry::Interrupt(cpu.pc), sp_after);
+            self.count(cpu.pc, interrupt_cycles);
+        }
+        Ok(brk)
+    }
+
+    fn current(&self) -> usize {
+        self.stack.last().map_or(0, |&(node, _)| node)
+    }
+
+    fn count(&mut self, pc: u16, cycles: u64) {
+        self.cycles[usize::from(pc)] += cycles;
+        let node = self.current();
+        self.nodes[node].cycles += cycles;
+    }
+
+    fn enter(&mut self, entry: Entry, sp: u8) {
+        let parent = self.current();
+        let node = *self.children.entry((parent, entry)).or_insert_with(|| {
+            self.nodes.push(Node {
+                entry,
+                parent,
+                calls: 0,
+                cycles: 0,
+            });
+            self.nodes.len() - 1
+        });
+        self.nodes[node].calls += 1;
+        self.stack.push((node, sp));
+    }
+
+    /// The cycles spent running the instruction at an address, and any interrupts entered
+    /// there.
+    #[must_use]
+    pub fn cycles_at(&self, addr: u16) -> u64 {
+        self.cycles[usize::from(addr)]
+    }
+
+    /// The cycles counted so far.
+    #[must_use]
+    pub fn total_cycles(&self) -> u64 {
+        self.nodes.iter().map(|node| node.cycles).sum()
+    }
+
+    /// Every routine that's been entered, and the root, taking the most cycles first.
+    ///
+    /// A recursive routine's cycles are only counted once, from its outermost call.
+    #[must_use]
+    pub fn routines(&self) -> Vec<Routine> {
+        // children come after their parents, so going backwards adds them up first
+        let mut totals: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+        for (i, node) in self.nodes.iter().enumerate().skip(1).rev() {
+            totals[node.parent] += totals[i];
+        }
+
+        let mut routines: BTreeMap<Entry, Routine> = BTreeMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let routine = routines.entry(node.entry).or_insert(Routine {
+                entry: node.entry,
+                calls: 0,
+                self_cycles: 0,
+                total_cycles: 0,
+            });
+            routine.calls += node.calls;
+            routine.self_cycles += node.cycles;
+            if !self.is_recursive(i) {
+                routine.total_cycles += totals[i];
+            }
+        }
+
+        let mut routines: Vec<_> = routines.into_values().collect();
+        routines.sort_by_key(|r| core::cmp::Reverse(r.total_cycles));
+        routines
+    }
+
+    /// Whether a node is inside another call to the same routine.
+    fn is_recursive(&self, mut node: usize) -> bool {
+        let entry = self.nodes[node].entry;
+        while node != 0 {
+            node = self.nodes[node].parent;
+            if self.nodes[node].entry == entry {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The call tree as folded stacks: a line for each place in the tree that took cycles
+    /// itself, with the routines that lead to it separated by `;`, then the cycles.
+    #[must_use]
+    pub const fn folded(&self) -> Folded<'_> {
+        Folded {
+            profiler: self,
+            labels: None,
+        }
+    }
+
+    /// A table of the routines, taking the most cycles first, with the cycles they take per
+    /// frame on average.
+    #[must_use]
+    pub const fn report(&self) -> Report<'_> {
+        Report {
+            profiler: self,
+            labels: None,
+        }
+    }
+}
+
+/// Whether an interrupt was entered after an instruction: its last accesses were pushing the
+/// program counter and status, then reading the IRQ vector.
+fn interrupted(bus: &Bus) -> bool {
+    let mut last = [None; 5];
+    for access in bus.accesses() {
+        last.rotate_left(1);
+        last[4] = Some(access);
+    }
+    let [Some(a), Some(b), Some(c), Some(lo), Some(hi)] = last else {
+        return false;
+    };
+    [a, b, c].iter().all(|a| a.kind == AccessKind::Write)
+        && [lo, hi].iter().all(|a| a.kind == AccessKind::Read)
+        && (lo.addr, hi.addr) == (Cpu::IRQ_VECTOR, Cpu::IRQ_VECTOR + 1)
+}
+
+/// A routine's name: its label, or its address.
+struct Name<'a> {
+    entry: Entry,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl fmt::Display for Name<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.entry {
+            Entry::Root => f.write_str("root"),
+            Entry::Call(addr) => write!(f, "{}", OperandAddr::absolute(addr, self.labels)),
+            Entry::Interrupt(addr) => {
+                write!(f, "{} (irq)", OperandAddr::absolute(addr, self.labels))
+            }
+        }
+    }
+}
+
+/// A [`Profiler`]'s call tree as folded stacks; see [`Profiler::folded`].
+#[derive(Clone, Copy)]
+pub struct Folded<'a> {
+    profiler: &'a Profiler,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl<'a> Folded<'a> {
+    /// Names routines with labels as the labels.
+    #[must_use]
+    pub const fn labels(mut self, labels: &'a dyn Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+}
+
+impl fmt::Display for Folded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nodes = &self.profiler.nodes;
+        let mut path = Vec::new();
+        for (i, node) in nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            path.clear();
+            let mut at = i;
+            while at != 0 {
+                path.push(nodes[at].entry);
+                at = nodes[at].parent;
+            }
+
+            f.write_str("root")?;
+            for &entry in path.iter().rev() {
+                let name = Name {
+                    entry,
+                    labels: self.labels,
+                };
+                write!(f, ";{name}")?;
+            }
+            writeln!(f, " {}", node.cycles)?;
+        }
+        Ok(())
+    }
+}
+
+/// A [`Profiler`]'s routines as a table; see [`Profiler::report`].
+#[derive(Clone, Copy)]
+pub struct Report<'a> {
+    profiler: &'a Profiler,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl<'a> Report<'a> {
+    /// Names routines with labels as the labels.
+    #[must_use]
+    pub const fn labels(mut self, labels: &'a dyn Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.profiler.total_cycles().max(1);
+        // three PPU dots a cycle
+        let frames = (total * 3).div_ceil(Bus::DOTS * Bus::SCANLINES);
+        writeln!(
+            f,
+            "{:>6} {:>10} {:>10} {:>8} {:>9}  routine",
+            "%", "total", "self", "calls", "per frame"
+        )?;
+        for routine in self.profiler.routines() {
+            let name = Name {
+                entry: routine.entry,
+                labels: self.labels,
+            };
+            // in tenths of a percent
+            let permille = routine.total_cycles * 1000 / total;
+            writeln!(
+                f,
+                "{:>4}.{} {:>10} {:>10} {:>8} {:>9}  {name}",
+                permille / 10,
+                permille % 10,
+                routine.total_cycles,
+                routine.self_cycles,
+                routine.calls,
+                routine.total_cycles / frames,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        asm,
+        bus::Bus,
+        rom::{Rom, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE},
+        testing::{create_rom, test_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+    use std::string::ToString;
+
+    fn profile(code: &[u8]) -> Profiler {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.load(code);
+        let mut profiler = Profiler::new();
+        while !profiler.tick(&mut cpu).unwrap() {}
+        profiler
+    }
+
+    #[test]
+    fn call_tree() {
+        let profiler = profile(&asm!(
+            "
+                JSR outer       ; 6
+                JSR inner       ; 6
+                BRK             ; 7
+            outer:
+                JSR inner       ; 6
+                RTS             ; 6
+            inner:
+                NOP             ; 2
+                RTS             ; 6
+            "
+        ));
+        assert_eq!(
+            profiler.folded().to_string(),
+            "root 19\nroot;$0607 12\nroot;$0607;$060B 8\nroot;$060B 8\n"
+        );
+        assert_eq!(profiler.total_cycles(), 47);
+        assert_eq!(profiler.cycles_at(0x060B), 4);
+
+        let routines = profiler.routines();
+        assert_eq!(
+            routines,
+            [
+                Routine {
+                    entry: Entry::Root,
+                    calls: 1,
+                    self_cycles: 19,
+                    total_cycles: 47
+                },
+                Routine {
+                    entry: Entry::Call(0x0607),
+                    calls: 1,
+                    self_cycles: 12,
+                    total_cycles: 20
+                },
+                Routine {
+                    entry: Entry::Call(0x060B),
+                    calls: 2,
+                    self_cycles: 16,
+                    total_cycles: 16
+                },
+            ]
+        );
+
+        let report = profiler.report().to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "     %      total       self    calls per frame  routine",
+                " 100.0         47         19        1        47  root",
+                "  42.5         20         12        1        20  $0607",
+                "  34.0         16         16        2        16  $060B",
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_cycles() {
+        let profiler = profile(&asm!(
+            "
+                LDY #2          ; 2
+            loop:
+                DEY             ; 2
+                BNE loop        ; 3 taken, 2 not
+                LDX #$FF        ; 2
+                LDA $0201,X     ; 5, crossing into $0300
+                BRK             ; 7
+            "
+        ));
+        assert_eq!(profiler.cycles_at(0x0603), 3 + 2);
+        assert_eq!(profiler.cycles_at(0x0607), 5);
+        assert_eq!(profiler.total_cycles(), 2 + 2 * 2 + 3 + 2 + 2 + 5 + 7);
+    }
+
+    #[test]
+    fn rts_jumps() {
+        // pushing an address and returning to it stays in the same routine
+        let profiler = profile(&asm!(
+            "
+                JSR dispatch
+                BRK
+            dispatch:
+                LDA #>before
+                PHA
+                LDA #<before
+                PHA
+                RTS
+            before:
+                NOP             ; RTS returns past the address it pops
+            target:
+                RTS
+            "
+        ));
+        assert_eq!(profiler.routines()[1].entry, Entry::Call(0x0604));
+        assert_eq!(profiler.routines()[1].self_cycles, 2 + 3 + 2 + 3 + 6 + 6);
+    }
+
+    #[test]
+    fn interrupts() {
+        // VRC4b, whose IRQ counter can fire after a few cycles
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x90, 0x10, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![0; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+        });
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.load(&asm!(
+            "
+                LDA #$FF
+                STA $F000       ; latch
+                STA $F002
+                LDA #%110
+                STA $F001       ; start counting cycles
+                CLI
+                NOP
+                BRK
+            "
+        ));
+        // the IRQ vector in the empty ROM points here
+        let handler = asm!(0x0000, "LDA #0\nSTA $F001\nRTI");
+        for (addr, &byte) in (0..).zip(handler.iter()) {
+            cpu.bus.mem_write(addr, byte);
+        }
+
+        let mut profiler = Profiler::new();
+        while !profiler.tick(&mut cpu).unwrap() {}
+        assert_eq!(
+            profiler.folded().to_string(),
+            "root 27\nroot;$0000 (irq) 19\n"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Rewinding: a ring buffer of recent [save states](crate::state), to step the machine
+//! backwards through.
+//!
+//! Only the newest state is kept whole. Each older one is stored as the difference from the
+//! one after it: the two combined with XOR, then run-length encoded. Consecutive frames only
+//! change a little RAM, so most of that difference is zeros and compresses to a few hundred
+//! bytes.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, cpu::Cpu, rewind::Rewind, rom::Rom};
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//! // ten seconds at 60 FPS
+//! let mut rewind = Rewind::new(600);
+//!
+//! for frame in 0..10 {
+//!     rewind.push(&cpu).unwrap();
+//!     cpu.bus.mem_write(0x0000, frame);
+//! }
+//! assert_eq!(rewind.step_back(&mut cpu, 3), Ok(3));
+//! assert_eq!(cpu.bus.mem_read(0x0000), 5);
+//! ```
+
+use crate::{cpu::Cpu, state::Error};
+use alloc::{collections::VecDeque, vec::Vec};
+
+#[derive(Debug, Clone, Default)]
+pub struct Rewind {
+    /// The most states kept, including the newest.
+    capacity: usize,
+    /// The newest state; empty before the first push.
+    current: Vec<u8>,
+    /// Compressed differences from each state to the one before it, oldest first.
+    deltas: VecDeque<Vec<u8>>,
+    /// Where new states are saved before being compared with [`Self::current`].
+    scratch: Vec<u8>,
+}
+
+impl Rewind {
+    /// Creates a buffer that keeps the last `capacity` states.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// The number of states that can be stepped back to, including the newest.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        if self.current.is_empty() {
+            0
+        } else {
+            self.deltas.len() + 1
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty()
+    }
+
+    /// The number of bytes the states take up.
+    #[must_use]
+    pub fn memory(&self) -> usize {
+        self.current.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// Forgets every state.
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.deltas.clear();
+    }
+
+    /// Saves the machine as the newest state, e.g. once a frame. The oldest state is dropped if
+    /// the buffer is full.
+    ///
+    /// # Errors
+    /// Never errors in practice; see [`Cpu::save_state`].
+    pub fn push(&mut self, cpu: &Cpu) -> Result<(), Error> {
+        // the same size as the newest state, unless the machine's changed
+        self.scratch.resize(self.current.len(), 0);
+        let size = match cpu.save_state(&mut self.scratch) {
+            Err(Error::BufferTooSmall { needed, .. }) => {
+                self.scratch.resize(needed, 0);
+                cpu.save_state(&mut self.scratch)?
+            }
+            size => size?,
+        };
+        self.scratch.truncate(size);
+
+        if self.current.len() == self.scratch.len() {
+            // stored against the new state, which is what it's restored from
+            self.deltas
+                .push_back(compress(&self.scratch, &self.current));
+            while self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        } else {
+            // a different machine, whose states can't be diffed against the old ones
+            self.deltas.clear();
+        }
+        core::mem::swap(&mut self.current, &mut self.scratch);
+        Ok(())
+    }
+
+    /// Steps the machine back `frames` states from the newest, dropping the ones after it, and
+    /// returns how far it went; that's less than `frames` when there aren't enough states.
+    /// `0` restores the newest state.
+    ///
+    /// # Errors
+    /// Errors if the states are for another ROM; see [`Cpu::load_state`].
+    pub fn step_back(&mut self, cpu: &mut Cpu, frames: usize) -> Result<usize, Error> {
+        if self.current.is_empty() {
+            return Ok(0);
+        }
+
+        // restored into the scratch buffer, so nothing's dropped if loading fails
+        let frames = frames.min(self.deltas.len());
+        let kept = self.deltas.len() - frames;
+        self.scratch.clone_from(&self.current);
+        for delta in self.deltas.range(kept..).rev() {
+            decompress(delta, &mut self.scratch);
+        }
+        cpu.load_state(&self.scratch)?;
+
+        self.deltas.truncate(kept);
+        core::mem::swap(&mut self.current, &mut self.scratch);
+        Ok(frames)
+    }
+}
+
+/// XORs `a` and `b`, and run-length encodes the result as runs of zeros, each followed by
+/// literal bytes: `zeros literals [literal bytes]`, with both counts as LEB128 varints.
+fn compress(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let diff = |i: usize| a[i] ^ b[i];
+    let len = a.len().min(b.len());
+    let mut out = Vec::new();
+
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && diff(i) == 0 {
+            i += 1;
+        }
+        if i == len {
+            // trailing zeros don't need storing
+            break;
+        }
+
+        // a lone zero costs less as a literal than as the start of a new run
+        let literals_start = i;
+        while i < len && (diff(i) != 0 || (i + 1 < len && diff(i + 1) != 0)) {
+            i += 1;
+        }
+        write_varint(&mut out, literals_start - zeros_start);
+        write_varint(&mut out, i - literals_start);
+        out.extend((literals_start..i).map(diff));
+    }
+    out
+}
+
+/// XORs a delta from [`compress`] back into `state`.
+fn decompress(delta: &[u8], state: &mut [u8]) {
+    let mut delta = delta.iter().copied();
+    let mut pos = 0;
+    while let Some(zeros) = read_varint(&mut delta) {
+        pos += zeros;
+        let literals = read_varint(&mut delta).unwrap_or(0);
+        for (byte, xor) in state[pos..pos + literals].iter_mut().zip(&mut delta) {
+            *byte ^= xor;
+        }
+        pos += literals;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut num: usize) {
+    while num >= 0x80 {
+        out.push(num.to_le_bytes()[0] | 0x80);
+        num >>= 7;
+    }
+    out.push(num.to_le_bytes()[0]);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut num = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        num |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(num);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        bus::Bus,
+        rom::{Rom, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE},
+        testing::{create_rom, test_rom, TestRom},
+    };
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn compression() {
+        let a = [0, 0, 0, 1, 2, 0, 3, 0, 0, 4, 0, 0];
+        let b = [0; 12];
+        let delta = compress(&a, &b);
+        assert_eq!(delta, [3, 4, 1, 2, 0, 3, 2, 1, 4]);
+
+        let mut state = b;
+        decompress(&delta, &mut state);
+        assert_eq!(state, a);
+        decompress(&delta, &mut state);
+        assert_eq!(state, b);
+
+        assert!(compress(&b, &b).is_empty());
+        let big = [0xFF; 300];
+        let delta = compress(&big, &[0; 300]);
+        assert_eq!(delta[..3], [0, 0xAC, 0x02]);
+    }
+
+    #[test]
+    fn step_back() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        let mut rewind = Rewind::new(4);
+        assert_eq!(rewind.step_back(&mut cpu, 1), Ok(0));
+
+        for frame in 0..6 {
+            cpu.bus.mem_write(0x0000, frame);
+            rewind.push(&cpu).unwrap();
+        }
+        assert_eq!(rewind.len(), 4);
+        assert!(rewind.memory() < cpu.state_size() + 4 * 64);
+
+        cpu.bus.mem_write(0x0000, 0xFF);
+        assert_eq!(rewind.step_back(&mut cpu, 0), Ok(0));
+        assert_eq!(cpu.bus.mem_read(0x0000), 5);
+        assert_eq!(rewind.step_back(&mut cpu, 2), Ok(2));
+        assert_eq!(cpu.bus.mem_read(0x0000), 3);
+        assert_eq!(rewind.step_back(&mut cpu, 5), Ok(1));
+        assert_eq!(cpu.bus.mem_read(0x0000), 2);
+        assert_eq!(rewind.len(), 1);
+
+        // carries on from where it stepped back to
+        cpu.bus.mem_write(0x0000, 7);
+        rewind.push(&cpu).unwrap();
+        assert_eq!(rewind.step_back(&mut cpu, 1), Ok(1));
+        assert_eq!(cpu.bus.mem_read(0x0000), 2);
+    }
+
+    #[test]
+    #[allow(clippy::large_stack_frames)] // two buses, which are meant to live on the stack
+    fn step_back_wrong_rom() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        let mut rewind = Rewind::new(4);
+        for frame in 0..3 {
+            cpu.bus.mem_write(0x0000, frame);
+            rewind.push(&cpu).unwrap();
+        }
+
+        let other_raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let mut other = Cpu::new(Bus::new(Rom::new(&other_raw).unwrap()));
+        assert!(matches!(
+            rewind.step_back(&mut other, 2),
+            Err(Error::WrongRom { .. })
+        ));
+
+        // the states are all still there
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.step_back(&mut cpu, 2), Ok(2));
+        assert_eq!(cpu.bus.mem_read(0x0000), 0);
+    }
+}
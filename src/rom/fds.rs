@@ -0,0 +1,319 @@
+//! Famicom Disk System images: either raw disk sides, one after another, or fwNES `.fds`
+//! files, which add a 16-byte header.
+//!
+//! Each side is a sequence of blocks: the disk info block, the file amount block, then a
+//! file header block and a file data block for every file.
+
+use super::{Error, Shared};
+
+pub const FDS_TAG: [u8; 4] = [0x46, 0x44, 0x53, 0x1A]; // FDS^Z
+/// Size of a side in an image, without the gaps and CRCs on the real disk.
+pub const SIDE_SIZE: usize = 65500;
+/// Size of the BIOS ROM mapped at `$E000`.
+pub const BIOS_SIZE: usize = 0x2000;
+
+const DISK_INFO_MAGIC: &[u8] = b"*NINTENDO-HVC*";
+
+/// A disk image, made of one or more sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disk<'a> {
+    data: Shared<'a>,
+}
+
+impl<'a> Disk<'a> {
+    /// Parses a `.fds` file, or a raw image if it doesn't have the `.fds` header.
+    ///
+    /// # Errors
+    /// Errors if the image isn't a whole number of sides, or the first side doesn't start with
+    /// a disk info block.
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        let data = if raw.starts_with(&FDS_TAG) {
+            let sides = usize::from(*raw.get(4).ok_or(Error::UnexpectedEOI)?);
+            raw.get(16..16 + sides * SIDE_SIZE)
+                .ok_or(Error::UnexpectedEOI)?
+        } else {
+            raw
+        };
+
+        if data.is_empty() || data.len() % SIDE_SIZE != 0 {
+            return Err(Error::UnexpectedEOI);
+        }
+        if data.get(1..=DISK_INFO_MAGIC.len()) != Some(DISK_INFO_MAGIC) {
+            return Err(Error::InvalidMagicBytes);
+        }
+
+        Ok(Self { data: data.into() })
+    }
+
+    /// Copies the image out of the file; see [`Rom::into_owned`](super::Rom::into_owned).
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn into_owned(self) -> Disk<'static> {
+        Disk {
+            data: self.data.into_owned(),
+        }
+    }
+
+    /// Every side, one after another, without the `.fds` header.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The number of sides in the image.
+    #[must_use]
+    pub fn sides(&self) -> usize {
+        self.data.len() / SIDE_SIZE
+    }
+
+    /// One side of the image, counting from 0 for disk 1 side A.
+    #[must_use]
+    pub fn side(&self, side: usize) -> Option<Side<'_>> {
+        self.data
+            .get(side * SIDE_SIZE..(side + 1) * SIDE_SIZE)
+            .map(|data| Side { data })
+    }
+}
+
+/// A single side of a disk; [`SIDE_SIZE`] bytes of blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Side<'a> {
+    pub data: &'a [u8],
+}
+
+impl<'a> Side<'a> {
+    /// The disk info block at the start of the side.
+    #[must_use]
+    pub fn info(&self) -> Option<DiskInfo> {
+        let block = self.data.get(..Block::DiskInfo.len(0))?;
+        if block[0] != 1 || &block[1..=DISK_INFO_MAGIC.len()] != DISK_INFO_MAGIC {
+            return None;
+        }
+
+        Some(DiskInfo {
+            manufacturer: block[15],
+            name: [block[16], block[17], block[18]],
+            game_type: block[19],
+            revision: block[20],
+            side: block[21],
+            disk: block[22],
+            boot_file: block[25],
+        })
+    }
+
+    /// The number of files the BIOS loads, from the file amount block. Games may hide more
+    /// files after these; [`Self::files`] lists those too.
+    #[must_use]
+    pub fn file_amount(&self) -> Option<u8> {
+        let start = Block::DiskInfo.len(0);
+        match self.data.get(start..start + Block::FileAmount.len(0))? {
+            [2, amount] => Some(*amount),
+            _ => None,
+        }
+    }
+
+    /// Every file on the side.
+    #[must_use]
+    pub const fn files(&self) -> Files<'a> {
+        Files {
+            data: self.data,
+            pos: Block::DiskInfo.len(0) + Block::FileAmount.len(0),
+        }
+    }
+}
+
+/// The disk info block (block 1) of a side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskInfo {
+    /// Licensee code.
+    pub manufacturer: u8,
+    /// Three-letter game code, in ASCII.
+    pub name: [u8; 3],
+    pub game_type: u8,
+    pub revision: u8,
+    /// `0` for side A, `1` for side B.
+    pub side: u8,
+    /// The disk number, from `0`.
+    pub disk: u8,
+    /// Files with an ID up to this are loaded at boot.
+    pub boot_file: u8,
+}
+
+/// What a file is loaded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// CPU memory.
+    Prg,
+    /// Pattern tables.
+    Chr,
+    /// Nametables.
+    Nametable,
+}
+
+/// A file on a disk side, from its header block (block 3) and data block (block 4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct File<'a> {
+    /// The file's position on the side.
+    pub number: u8,
+    /// The ID the BIOS compares against the boot file and load lists.
+    pub id: u8,
+    /// Eight characters, in ASCII.
+    pub name: [u8; 8],
+    /// Where the file is loaded.
+    pub addr: u16,
+    pub kind: FileKind,
+    pub data: &'a [u8],
+}
+
+/// An iterator over the files on a side, from [`Side::files`].
+#[derive(Debug, Clone)]
+pub struct Files<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Files<'a> {
+    type Item = File<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self
+            .data
+            .get(self.pos..self.pos + Block::FileHeader.len(0))?;
+        if header[0] != 3 {
+            return None;
+        }
+        let size = u16::from_le_bytes([header[13], header[14]]);
+
+        let data_start = self.pos + header.len();
+        let block = self
+            .data
+            .get(data_start..data_start + Block::FileData.len(size))?;
+        if block[0] != 4 {
+            return None;
+        }
+        self.pos = data_start + block.len();
+
+        Some(File {
+            number: header[1],
+            id: header[2],
+            name: header[3..11].try_into().unwrap_or_default(),
+            addr: u16::from_le_bytes([header[11], header[12]]),
+            kind: match header[15] {
+                0 => FileKind::Prg,
+                1 => FileKind::Chr,
+                _ => FileKind::Nametable,
+            },
+            data: &block[1..],
+        })
+    }
+}
+
+/// The types of block on a side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Block {
+    DiskInfo = 1,
+    FileAmount = 2,
+    FileHeader = 3,
+    FileData = 4,
+}
+
+impl Block {
+    pub(crate) const fn from_type(ty: u8) -> Option<Self> {
+        match ty {
+            1 => Some(Self::DiskInfo),
+            2 => Some(Self::FileAmount),
+            3 => Some(Self::FileHeader),
+            4 => Some(Self::FileData),
+            _ => None,
+        }
+    }
+
+    /// The length of the block, including its type byte. File data blocks take the size from
+    /// the file header before them.
+    pub(crate) const fn len(self, file_size: u16) -> usize {
+        match self {
+            Self::DiskInfo => 56,
+            Self::FileAmount => 2,
+            Self::FileHeader => 16,
+            Self::FileData => 1 + file_size as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A side with two files: `PRG` at `$6000` and `CHR` at `$0000`.
+    pub fn side() -> Vec<u8> {
+        let mut side = vec![1];
+        side.extend(DISK_INFO_MAGIC);
+        side.extend([0xA4, b'T', b'S', b'T', 0, 1, 0, 0, 0, 0, 0x0F]);
+        side.resize(56, 0);
+        side.extend([2, 1]);
+
+        side.extend([3, 0, 0x0F]);
+        side.extend(b"PRG-FILE");
+        side.extend([0x00, 0x60, 0x04, 0x00, 0]);
+        side.extend([4, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+        side.extend([3, 1, 0x10]);
+        side.extend(b"CHR-FILE");
+        side.extend([0x00, 0x00, 0x02, 0x00, 1]);
+        side.extend([4, 0x12, 0x34]);
+
+        side.resize(SIDE_SIZE, 0);
+        side
+    }
+
+    #[test]
+    fn fds_header() {
+        let mut raw = FDS_TAG.to_vec();
+        raw.extend([2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        raw.extend(side());
+        raw.extend(side());
+
+        let disk = Disk::new(&raw).unwrap();
+        assert_eq!(disk.sides(), 2);
+        assert!(disk.side(1).is_some());
+        assert!(disk.side(2).is_none());
+
+        assert_eq!(Disk::new(&raw[..raw.len() - 1]), Err(Error::UnexpectedEOI));
+        assert_eq!(Disk::new(&raw[16..]).unwrap().sides(), 2);
+        assert_eq!(
+            Disk::new(&vec![0; SIDE_SIZE]),
+            Err(Error::InvalidMagicBytes)
+        );
+    }
+
+    #[test]
+    fn files() {
+        let raw = side();
+        let disk = Disk::new(&raw).unwrap();
+        let side = disk.side(0).unwrap();
+
+        let info = side.info().unwrap();
+        assert_eq!(&info.name, b"TST");
+        assert_eq!(info.manufacturer, 0xA4);
+        assert_eq!(info.revision, 1);
+        assert_eq!(info.boot_file, 0x0F);
+        assert_eq!(side.file_amount(), Some(1));
+
+        let files: Vec<_> = side.files().collect();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            File {
+                number: 0,
+                id: 0x0F,
+                name: *b"PRG-FILE",
+                addr: 0x6000,
+                kind: FileKind::Prg,
+                data: &[0xDE, 0xAD, 0xBE, 0xEF],
+            }
+        );
+        assert_eq!(files[1].kind, FileKind::Chr);
+        assert_eq!(files[1].data, &[0x12, 0x34]);
+    }
+}
@@ -0,0 +1,900 @@
+use crate::mapper::Board;
+use core::fmt;
+
+pub mod fds;
+pub mod nsf;
+pub mod patch;
+pub mod shared;
+pub mod unif;
+
+pub use shared::Shared;
+
+pub const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A]; // NES^Z
+pub const PRG_ROM_PAGE_SIZE: usize = 16384; // 16KiB
+pub const CHR_ROM_PAGE_SIZE: usize = 8192; // 8KiB
+pub const PRG_RAM_PAGE_SIZE: usize = 8192; // 8KiB
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("invalid magic bytes"))]
+    InvalidMagicBytes,
+    #[snafu(display("unsupported NES format"))]
+    UnsupportedFormat,
+    #[snafu(display("unsupported UNIF board"))]
+    UnsupportedBoard,
+    #[snafu(display("unexpected end of input"))]
+    UnexpectedEOI,
+}
+impl From<untrusted::EndOfInput> for Error {
+    fn from(_: untrusted::EndOfInput) -> Self {
+        Self::UnexpectedEOI
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    /// Every nametable shows the first page of VRAM; only selectable by mappers and UNIF.
+    SingleScreenLower,
+    /// Every nametable shows the second page of VRAM; only selectable by mappers and UNIF.
+    SingleScreenUpper,
+}
+/// The version of the header format a ROM was parsed as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    /// iNES 0.7: only the mapper's low nibble and `flags_6` are used, everything after is
+    /// ignored. Used when the rest of the header has garbage in it.
+    ArchaicInes,
+    Ines,
+    Nes2,
+    /// UNIF; see [`unif`].
+    Unif,
+    /// A Famicom Disk System image, with the BIOS as PRG ROM; see [`Rom::fds`].
+    Fds,
+    /// An `.nsf` or `.nsfe` music file, with the tune as PRG ROM; see [`nsf`].
+    Nsf,
+}
+
+bitflags::bitflags! {
+    /// Problems found in a ROM that didn't stop it from loading.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Warnings: u8 {
+        /// Bytes 7-15 of the header had garbage in them (e.g. `DiskDude!`), so the ROM was
+        /// parsed as archaic iNES.
+        const DIRTY_HEADER       = 0b0000_0001;
+        /// There's data after the end of CHR ROM.
+        const TRAILING_DATA      = 0b0000_0010;
+        /// There's no PRG ROM.
+        const NO_PRG_ROM         = 0b0000_0100;
+        /// The mapper isn't supported, and the ROM will run as NROM.
+        const UNSUPPORTED_MAPPER = 0b0000_1000;
+        /// The header disagreed with the ROM database, and was corrected.
+        const HEADER_CORRECTED   = 0b0001_0000;
+    }
+}
+
+/// The CPU/PPU timing the game was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Timing {
+    /// RP2C02; North America, Japan, South Korea and Taiwan.
+    Ntsc,
+    /// RP2C07; Western Europe and Australia.
+    Pal,
+    /// Runs on either.
+    MultiRegion,
+    /// UMC 6527P; Eastern Europe, Russia, China and India.
+    Dendy,
+}
+
+/// The kind of console the game was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Console {
+    /// A regular NES or Famicom.
+    Nes,
+    /// The arcade Vs. System, with its PPU and hardware types as numbered by NES 2.0 (`0` for
+    /// iNES files, which don't say).
+    VsSystem { ppu: u8, hardware: u8 },
+    /// The arcade Playchoice 10.
+    Playchoice10,
+    /// One of NES 2.0's extended console types, e.g. `3` for the VT01 famiclones.
+    Extended(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rom<'rom> {
+    pub prg_rom: Shared<'rom>,
+    pub chr_rom: Shared<'rom>,
+    pub format: Format,
+    /// 512 bytes of code some dumps patch the game with, loaded into `$7000..=$71FF` before
+    /// reset.
+    pub trainer: Option<Shared<'rom>>,
+    /// The 12-bit mapper number; iNES files only have 8 bits.
+    pub mapper: u16,
+    /// The NES 2.0 submapper number, `0` if there isn't one.
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    /// Size of the volatile PRG-RAM at `$6000..=$7FFF`, in bytes.
+    pub prg_ram_size: usize,
+    /// Size of the battery-backed PRG-RAM, in bytes.
+    pub prg_nvram_size: usize,
+    /// Whether the cartridge has a battery, or some other form of persistent memory.
+    pub battery: bool,
+    /// Size of the volatile CHR-RAM the pattern tables are mapped to, in bytes; only used when
+    /// there's no CHR ROM.
+    pub chr_ram_size: usize,
+    /// Size of the battery-backed CHR-RAM, in bytes.
+    pub chr_nvram_size: usize,
+    pub timing: Timing,
+    pub console: Console,
+    /// The number of miscellaneous ROMs after CHR ROM (NES 2.0 only).
+    pub misc_roms: u8,
+    /// The default expansion device, numbered as in NES 2.0; `0` if unspecified.
+    pub expansion_device: u8,
+    /// Problems found while parsing.
+    pub warnings: Warnings,
+    /// The disk image, for the Famicom Disk System.
+    pub disk: Option<fds::Disk<'rom>>,
+    /// The music file's header, for NSF files.
+    pub nsf: Option<nsf::Nsf<'rom>>,
+    /// See [`Self::crc32`].
+    crc32: u32,
+}
+
+impl<'a> Rom<'a> {
+    /// Parses an iNES, NES 2.0, UNIF or NSF file. With the `romdb` feature, if the game is in
+    /// the ROM database, the header's mapper, mirroring, battery and timing are corrected from
+    /// it; see [`Warnings::HEADER_CORRECTED`].
+    ///
+    /// # Errors
+    /// Errors if the file is invalid.
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        let rom = Self::from_header(raw)?;
+        #[cfg(feature = "romdb")]
+        let rom = rom.corrected();
+        Ok(rom)
+    }
+
+    /// Parses an iNES, NES 2.0, UNIF or NSF file, trusting its header.
+    ///
+    /// Headers with garbage in the bytes iNES 1.0 leaves unused are assumed to predate those
+    /// bytes being defined, and are parsed as archaic iNES; see [`Warnings::DIRTY_HEADER`].
+    ///
+    /// # Errors
+    /// Errors if the file is invalid, or is a UNIF file with a board that isn't supported, or
+    /// split ROM chunks without the `alloc` feature.
+    pub fn from_header(raw: &'a [u8]) -> Result<Self, Error> {
+        if raw.starts_with(&unif::UNIF_TAG) {
+            return unif::parse(raw);
+        }
+        if raw.starts_with(&nsf::NSF_TAG) || raw.starts_with(&nsf::NSFE_TAG) {
+            return nsf::parse(raw);
+        }
+
+        let mut reader = untrusted::Reader::new(untrusted::Input::from(raw));
+
+        if reader.read_bytes(4)?.as_slice_less_safe() != NES_TAG {
+            return Err(Error::InvalidMagicBytes);
+        };
+
+        let mut flags = [0; 12];
+        for flag in &mut flags {
+            *flag = reader.read_byte()?;
+        }
+        let [_, _, flags_6, flags_7, flags_8, .., flags_12, flags_13, flags_14, flags_15] = flags;
+
+        let format = match (flags_7 >> 2) & 0b11 {
+            0b10 => Format::Nes2,
+            0b00 if flags_12 | flags_13 | flags_14 | flags_15 == 0 => Format::Ines,
+            _ => Format::ArchaicInes,
+        };
+        let mut warnings = Warnings::empty();
+        if format == Format::ArchaicInes && flags[3..].iter().any(|&b| b != 0) {
+            log::warn!("garbage in the header, ignoring bytes 7-15");
+            warnings |= Warnings::DIRTY_HEADER;
+        }
+
+        let four_screen = flags_6 & 0b1000 != 0;
+        let vert_mirroring = flags_6 & 0x0001 != 0;
+        let mirroring = match (four_screen, vert_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (_, true) => Mirroring::Vertical,
+            (_, false) => Mirroring::Horizontal,
+        };
+        let battery = flags_6 & 0b0010 != 0;
+        let mapper = u16::from(flags_6 >> 4);
+
+        let (mapper, parsed) = match format {
+            Format::ArchaicInes => {
+                let mut flags = flags;
+                flags[3..].fill(0);
+                (mapper, Header::ines(flags))
+            }
+            Format::Ines => (mapper | u16::from(flags_7 & 0xF0), Header::ines(flags)),
+            Format::Nes2 => (
+                mapper | u16::from(flags_7 & 0xF0) | u16::from(flags_8 & 0x0F) << 8,
+                Header::nes2(flags)?,
+            ),
+            Format::Unif | Format::Fds | Format::Nsf => unreachable!("not an iNES format"),
+        };
+
+        let trainer = if flags_6 & 0b0100 == 0 {
+            None
+        } else {
+            Some(reader.read_bytes(512)?.as_slice_less_safe().into())
+        };
+
+        log::debug!("PRG ROM size: {:#X}", parsed.prg_rom_size);
+        log::debug!("CHR ROM size: {:#X}", parsed.chr_rom_size);
+
+        let prg_rom = reader.read_bytes(parsed.prg_rom_size)?.as_slice_less_safe();
+        let chr_rom = reader.read_bytes(parsed.chr_rom_size)?.as_slice_less_safe();
+
+        if prg_rom.is_empty() {
+            warnings |= Warnings::NO_PRG_ROM;
+        }
+        // NES 2.0 misc ROMs are stored after CHR ROM
+        if !reader.at_end() && parsed.misc_roms == 0 {
+            warnings |= Warnings::TRAILING_DATA;
+        }
+
+        Ok(Self {
+            prg_rom: prg_rom.into(),
+            chr_rom: chr_rom.into(),
+            format,
+            trainer,
+            mapper,
+            submapper: parsed.submapper,
+            mirroring,
+            prg_ram_size: parsed.prg_ram_size,
+            prg_nvram_size: parsed.prg_nvram_size,
+            battery,
+            chr_ram_size: parsed.chr_ram_size,
+            chr_nvram_size: parsed.chr_nvram_size,
+            timing: parsed.timing,
+            console: parsed.console,
+            misc_roms: parsed.misc_roms,
+            expansion_device: parsed.expansion_device,
+            warnings,
+            disk: None,
+            nsf: None,
+            crc32: crate::crc::crc32(&[prg_rom, chr_rom]),
+        })
+    }
+
+    /// Loads a Famicom Disk System image, with the BIOS mapped at `$E000` as PRG ROM.
+    ///
+    /// The RAM adapter's 32KiB of PRG-RAM and 8KiB of CHR-RAM are reported as such, and the
+    /// mapper is set to 20, the number iNES reserved for it.
+    ///
+    /// # Errors
+    /// Errors if the BIOS isn't 8KiB, or if the image is invalid; see [`fds::Disk::new`].
+    pub fn fds(bios: &'a [u8], image: &'a [u8]) -> Result<Self, Error> {
+        if bios.len() != fds::BIOS_SIZE {
+            return Err(Error::UnsupportedFormat);
+        }
+        let disk = fds::Disk::new(image)?;
+
+        Ok(Self {
+            prg_rom: bios.into(),
+            chr_rom: Shared::default(),
+            format: Format::Fds,
+            trainer: None,
+            mapper: 20,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            prg_ram_size: 0x8000,
+            prg_nvram_size: 0,
+            battery: false,
+            chr_ram_size: CHR_ROM_PAGE_SIZE,
+            chr_nvram_size: 0,
+            timing: Timing::Ntsc,
+            console: Console::Nes,
+            misc_roms: 0,
+            expansion_device: 0,
+            warnings: Warnings::empty(),
+            crc32: crate::crc::crc32(&[bios, disk.data()]),
+            disk: Some(disk),
+            nsf: None,
+        })
+    }
+
+    /// Corrects the header from the ROM database, if the game is in it.
+    #[cfg(feature = "romdb")]
+    fn corrected(self) -> Self {
+        match crate::romdb::lookup(crate::romdb::crc32(&[&self.prg_rom, &self.chr_rom])) {
+            Some(entry) => self.with_entry(entry),
+            None => self,
+        }
+    }
+
+    #[cfg(feature = "romdb")]
+    fn with_entry(mut self, entry: &crate::romdb::Entry) -> Self {
+        let before = (
+            self.mapper,
+            self.submapper,
+            self.mirroring,
+            self.battery,
+            self.timing,
+        );
+
+        self.mapper = entry.mapper;
+        self.submapper = entry.submapper;
+        self.mirroring = entry.mirroring.unwrap_or(self.mirroring);
+        self.timing = entry.timing;
+        if entry.battery != self.battery {
+            self.battery = entry.battery;
+            let (ram, nvram) = (self.prg_ram_size, self.prg_nvram_size);
+            if entry.battery {
+                (self.prg_ram_size, self.prg_nvram_size) = (0, ram + nvram);
+            } else {
+                (self.prg_ram_size, self.prg_nvram_size) = (ram + nvram, 0);
+            }
+        }
+
+        if before
+            != (
+                self.mapper,
+                self.submapper,
+                self.mirroring,
+                self.battery,
+                self.timing,
+            )
+        {
+            log::info!("corrected the header from the ROM database");
+            self.warnings |= Warnings::HEADER_CORRECTED;
+        }
+        self
+    }
+
+    /// The CRC32 of the ROM's contents: PRG ROM, CHR ROM and any disk image, as they were
+    /// parsed. Identifies the ROM in [save states](crate::state).
+    #[must_use]
+    pub const fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Summarizes the ROM, for displaying or linting it before running it.
+    #[must_use]
+    pub fn info(&self) -> RomInfo {
+        let mut warnings = self.warnings;
+        if Board::new(self).is_none() {
+            warnings |= Warnings::UNSUPPORTED_MAPPER;
+        }
+
+        RomInfo {
+            format: self.format,
+            mapper: self.mapper,
+            submapper: self.submapper,
+            prg_rom_size: self.prg_rom.len(),
+            chr_rom_size: self.chr_rom.len(),
+            prg_ram_size: self.prg_ram_size,
+            prg_nvram_size: self.prg_nvram_size,
+            chr_ram_size: self.chr_ram_size,
+            chr_nvram_size: self.chr_nvram_size,
+            mirroring: self.mirroring,
+            battery: self.battery,
+            trainer: self.trainer.is_some(),
+            timing: self.timing,
+            console: self.console,
+            warnings,
+        }
+    }
+
+    /// Copies everything the ROM borrows from the file, so the file can be dropped and the ROM
+    /// (and the [`Cpu`](crate::cpu::Cpu) running it) can be `'static`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use fete::{bus::Bus, cpu::Cpu, rom::Rom};
+    /// # let raw = fete::testing::test_rom();
+    /// let cpu: Cpu<'static> = Cpu::new(Bus::new(Rom::new(&raw).unwrap().into_owned()));
+    /// drop(raw);
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn into_owned(self) -> Rom<'static> {
+        Rom {
+            prg_rom: self.prg_rom.into_owned(),
+            chr_rom: self.chr_rom.into_owned(),
+            trainer: self.trainer.map(Shared::into_owned),
+            disk: self.disk.map(fds::Disk::into_owned),
+            nsf: self.nsf.map(nsf::Nsf::into_owned),
+            ..self
+        }
+    }
+}
+
+/// A report on a ROM's header, from [`Rom::info`]. Its [`Display`](fmt::Display) impl lists
+/// each field on its own line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)] // mirrors the header
+#[allow(clippy::module_name_repetitions)]
+pub struct RomInfo {
+    pub format: Format,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console: Console,
+    pub warnings: Warnings,
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            Format::ArchaicInes => "archaic iNES",
+            Format::Ines => "iNES",
+            Format::Nes2 => "NES 2.0",
+            Format::Unif => "UNIF",
+            Format::Fds => "FDS",
+            Format::Nsf => "NSF",
+        };
+        writeln!(f, "format: {format}")?;
+        writeln!(f, "mapper: {}.{}", self.mapper, self.submapper)?;
+        writeln!(f, "PRG ROM: {}", Size(self.prg_rom_size))?;
+        writeln!(f, "CHR ROM: {}", Size(self.chr_rom_size))?;
+        writeln!(
+            f,
+            "PRG RAM: {} + {} battery-backed",
+            Size(self.prg_ram_size),
+            Size(self.prg_nvram_size)
+        )?;
+        writeln!(
+            f,
+            "CHR RAM: {} + {} battery-backed",
+            Size(self.chr_ram_size),
+            Size(self.chr_nvram_size)
+        )?;
+        writeln!(f, "mirroring: {:?}", self.mirroring)?;
+        writeln!(f, "battery: {}", self.battery)?;
+        writeln!(f, "trainer: {}", self.trainer)?;
+        writeln!(f, "timing: {:?}", self.timing)?;
+        writeln!(f, "console: {:?}", self.console)?;
+
+        for warning in self.warnings {
+            let warning = match warning {
+                Warnings::DIRTY_HEADER => "garbage in header bytes 7-15; parsed as archaic iNES",
+                Warnings::TRAILING_DATA => "data after the end of CHR ROM",
+                Warnings::NO_PRG_ROM => "no PRG ROM",
+                Warnings::HEADER_CORRECTED => "header corrected from the ROM database",
+                _ => "unsupported mapper",
+            };
+            writeln!(f, "warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a byte count in KiB where possible.
+struct Size(usize);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 != 0 && self.0.trailing_zeros() >= 10 {
+            write!(f, "{}KiB", self.0 / 1024)
+        } else {
+            write!(f, "{}B", self.0)
+        }
+    }
+}
+
+/// The parts of the header that are encoded differently in iNES and NES 2.0.
+struct Header {
+    prg_rom_size: usize,
+    chr_rom_size: usize,
+    submapper: u8,
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
+    timing: Timing,
+    console: Console,
+    misc_roms: u8,
+    expansion_device: u8,
+}
+
+impl Header {
+    /// Decodes bytes 4-15 of an iNES header.
+    fn ines(flags: [u8; 12]) -> Self {
+        let [prg_rom, chr_rom, flags_6, flags_7, flags_8, flags_9, ..] = flags;
+        let chr_rom_size = usize::from(chr_rom) * CHR_ROM_PAGE_SIZE;
+        // 0 means 8KiB, for compatibility with ROMs from before this byte was defined.
+        let prg_ram_size = usize::from(flags_8.max(1)) * PRG_RAM_PAGE_SIZE;
+        let battery = flags_6 & 0b0010 != 0;
+
+        Self {
+            prg_rom_size: usize::from(prg_rom) * PRG_ROM_PAGE_SIZE,
+            chr_rom_size,
+            submapper: 0,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            // boards without CHR ROM have 8KiB of CHR-RAM instead
+            chr_ram_size: if chr_rom_size == 0 {
+                CHR_ROM_PAGE_SIZE
+            } else {
+                0
+            },
+            chr_nvram_size: 0,
+            timing: if flags_9 & 1 == 0 {
+                Timing::Ntsc
+            } else {
+                Timing::Pal
+            },
+            console: if flags_7 & 0b01 != 0 {
+                Console::VsSystem {
+                    ppu: 0,
+                    hardware: 0,
+                }
+            } else if flags_7 & 0b10 != 0 {
+                Console::Playchoice10
+            } else {
+                Console::Nes
+            },
+            misc_roms: 0,
+            expansion_device: 0,
+        }
+    }
+
+    /// Decodes bytes 4-15 of a NES 2.0 header.
+    fn nes2(flags: [u8; 12]) -> Result<Self, Error> {
+        let [prg_rom, chr_rom, _, flags_7, flags_8, flags_9, flags_10, flags_11, flags_12, flags_13, flags_14, flags_15] =
+            flags;
+        let ram_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+
+        Ok(Self {
+            prg_rom_size: nes2_rom_size(prg_rom, flags_9 & 0x0F, PRG_ROM_PAGE_SIZE)?,
+            chr_rom_size: nes2_rom_size(chr_rom, flags_9 >> 4, CHR_ROM_PAGE_SIZE)?,
+            submapper: flags_8 >> 4,
+            prg_ram_size: ram_size(flags_10 & 0x0F),
+            prg_nvram_size: ram_size(flags_10 >> 4),
+            chr_ram_size: ram_size(flags_11 & 0x0F),
+            chr_nvram_size: ram_size(flags_11 >> 4),
+            timing: match flags_12 & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultiRegion,
+                _ => Timing::Dendy,
+            },
+            console: match flags_7 & 0b11 {
+                0 => Console::Nes,
+                1 => Console::VsSystem {
+                    ppu: flags_13 & 0x0F,
+                    hardware: flags_13 >> 4,
+                },
+                2 => Console::Playchoice10,
+                _ => Console::Extended(flags_13 & 0x0F),
+            },
+            misc_roms: flags_14 & 0b11,
+            expansion_device: flags_15 & 0x3F,
+        })
+    }
+}
+
+/// Decodes a NES 2.0 ROM size: either a 12-bit page count, or, if the top nibble is `F`, an
+/// exponent and multiplier (`2^E * (MM * 2 + 1)` bytes, from `EEEE EEMM`).
+fn nes2_rom_size(low: u8, high: u8, page_size: usize) -> Result<usize, Error> {
+    if high == 0x0F {
+        let multiplier = usize::from(low & 0b11) * 2 + 1;
+        1usize
+            .checked_shl(u32::from(low >> 2))
+            .and_then(|size| size.checked_mul(multiplier))
+            // too big to be in the file
+            .ok_or(Error::UnexpectedEOI)
+    } else {
+        Ok((usize::from(high) << 8 | usize::from(low)) * page_size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{create_rom, TestRom};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert!(*rom.prg_rom == vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert!(*rom.chr_rom == vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_ram_size, PRG_RAM_PAGE_SIZE);
+        assert!(!rom.battery);
+        assert_eq!(rom.chr_ram_size, 0);
+    }
+
+    #[test]
+    fn test_battery() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x02, 00, 0x04, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 4 * PRG_RAM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x20, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_with_trainer() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                0x02,
+                0x01,
+                0x31 | 0b100,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: Some(vec![3; 512]),
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom: Rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.trainer.as_deref(), Some(&[3; 512][..]));
+
+        assert!(*rom.prg_rom == vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert!(*rom.chr_rom == vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_nes2() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x52, 0x49, 0x31, 00, 0x70, 0x07, 0x01, 0x21,
+                0x01, 0x03,
+            ],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.mapper, 0x145);
+        assert_eq!(rom.submapper, 3);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 0x2000);
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.chr_nvram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(
+            rom.console,
+            Console::VsSystem {
+                ppu: 1,
+                hardware: 2
+            }
+        );
+        assert_eq!(rom.misc_roms, 1);
+        assert_eq!(rom.expansion_device, 3);
+    }
+
+    #[test]
+    fn test_nes2_rom_size() {
+        assert_eq!(
+            nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE),
+            Ok(0x102 * PRG_ROM_PAGE_SIZE)
+        );
+        // 2^3 * 3
+        assert_eq!(nes2_rom_size(0b0000_1101, 0x0F, PRG_ROM_PAGE_SIZE), Ok(24));
+        assert_eq!(
+            nes2_rom_size(0xFF, 0x0F, PRG_ROM_PAGE_SIZE),
+            Err(Error::UnexpectedEOI)
+        );
+
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E,
+                0x45,
+                0x53,
+                0x1A,
+                0b0000_1101,
+                00,
+                00,
+                0x08,
+                00,
+                0x0F,
+                00,
+                00,
+                00,
+                00,
+                00,
+                00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 24],
+            chr_rom: vec![],
+        });
+        assert_eq!(Rom::new(&test_rom).unwrap().prg_rom.len(), 24);
+    }
+
+    #[test]
+    fn test_diskdude() {
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x12];
+        header.extend(b"DiskDude!");
+        let test_rom = create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&test_rom).unwrap();
+
+        assert_eq!(rom.format, Format::ArchaicInes);
+        assert_eq!(rom.mapper, 1);
+        assert!(rom.battery);
+        assert_eq!(rom.timing, Timing::Ntsc);
+        assert_eq!(rom.console, Console::Nes);
+        assert_eq!(rom.warnings, Warnings::DIRTY_HEADER);
+    }
+
+    #[test]
+    fn test_info() {
+        let mut test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x51, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+        test_rom.push(0);
+
+        let info = Rom::new(&test_rom).unwrap().info();
+
+        assert_eq!(info.format, Format::Ines);
+        assert_eq!(
+            info.warnings,
+            Warnings::TRAILING_DATA | Warnings::UNSUPPORTED_MAPPER
+        );
+        assert_eq!(
+            info.to_string(),
+            "format: iNES
+mapper: 5.0
+PRG ROM: 32KiB
+CHR ROM: 0B
+PRG RAM: 8KiB + 0B battery-backed
+CHR RAM: 8KiB + 0B battery-backed
+mirroring: Vertical
+battery: false
+trainer: false
+timing: Ntsc
+console: Nes
+warning: data after the end of CHR ROM
+warning: unsupported mapper
+"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "romdb")]
+    fn test_romdb_correction() {
+        let test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::from_header(&test_rom).unwrap();
+        let entry = crate::romdb::Entry {
+            crc32: 0,
+            mapper: 4,
+            submapper: 1,
+            mirroring: None,
+            battery: true,
+            timing: Timing::Pal,
+        };
+
+        let rom = rom.with_entry(&entry);
+
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.submapper, 1);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, PRG_RAM_PAGE_SIZE);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.warnings, Warnings::HEADER_CORRECTED);
+    }
+
+    #[test]
+    #[cfg(feature = "romdb")]
+    fn test_romdb_known_game() {
+        let mut raw = include_bytes!("../../tests/nestest/nestest.nes").to_vec();
+        raw[6] = 0x41; // mapper 4, vertical mirroring
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+        assert_eq!(rom.warnings, Warnings::HEADER_CORRECTED);
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn into_owned() {
+        const fn send<T: Send + 'static>(val: T) -> T {
+            val
+        }
+
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0b0100, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: Some(vec![3; 512]),
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        let rom = Rom::new(&raw).unwrap();
+        let owned = send(rom.clone().into_owned());
+        drop(raw);
+
+        assert!(matches!(owned.prg_rom, Shared::Owned(_)));
+        assert_eq!(*owned.prg_rom, [1; PRG_ROM_PAGE_SIZE]);
+        assert_eq!(*owned.chr_rom, [2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(owned.trainer.as_deref(), Some(&[3; 512][..]));
+        let cpu = send(crate::cpu::Cpu::new(crate::bus::Bus::new(owned)));
+        assert_eq!(cpu.bus.mem_read(0x8000), 1);
+    }
+}
@@ -0,0 +1,445 @@
+//! NSF music files: a 128-byte header, followed by the tune's code and data.
+//!
+//! A player loads the data at a fixed address, and drives it by calling its INIT and PLAY
+//! routines; see [`Player`](crate::player::Player). `.nsfe` files carry the same information
+//! in tagged chunks, along with names, lengths and fades for each track.
+
+use super::{
+    unif::read_u32, Console, Error, Format, Mirroring, Rom, Shared, Timing, Warnings,
+    CHR_ROM_PAGE_SIZE, PRG_RAM_PAGE_SIZE,
+};
+use core::time::Duration;
+
+pub const NSF_TAG: [u8; 5] = *b"NESM\x1A";
+pub const NSFE_TAG: [u8; 4] = *b"NSFE";
+/// The mapper number of the board NSF players run on.
+pub const MAPPER: u16 = 31;
+
+/// Microseconds between PLAY calls when an `.nsfe` file doesn't say.
+const NTSC_SPEED: u16 = 16639;
+const PAL_SPEED: u16 = 19997;
+
+bitflags::bitflags! {
+    /// The expansion audio chips a tune uses.
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub struct Chips: u8 {
+        const VRC6       = 0b0000_0001;
+        const VRC7       = 0b0000_0010;
+        const FDS        = 0b0000_0100;
+        const MMC5       = 0b0000_1000;
+        const N163       = 0b0001_0000;
+        const SUNSOFT_5B = 0b0010_0000;
+    }
+}
+
+/// An `.nsf` or `.nsfe` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsf<'a> {
+    /// The tune's code and data, loaded at [`Self::load_addr`].
+    pub data: Shared<'a>,
+    pub songs: u8,
+    /// The song to play first, counting from 0.
+    pub starting_song: u8,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: Option<Shared<'a, str>>,
+    pub artist: Option<Shared<'a, str>>,
+    pub copyright: Option<Shared<'a, str>>,
+    /// Who ripped the tune; `.nsfe` only.
+    pub ripper: Option<Shared<'a, str>>,
+    /// The 4KiB banks at `$8000..=$FFFF` before INIT, or [`None`] if the tune doesn't
+    /// bankswitch.
+    pub banks: Option<[u8; 8]>,
+    /// Microseconds between PLAY calls on NTSC.
+    pub ntsc_speed: u16,
+    /// Microseconds between PLAY calls on PAL.
+    pub pal_speed: u16,
+    /// [`Timing::Ntsc`], [`Timing::Pal`] or [`Timing::MultiRegion`].
+    pub timing: Timing,
+    pub chips: Chips,
+    /// The order to play the songs in, if it isn't just their numbers; `.nsfe` only.
+    pub playlist: Option<Shared<'a>>,
+    labels: Shared<'a>,
+    lengths: Shared<'a>,
+    fades: Shared<'a>,
+}
+
+/// Metadata for a single song; only `.nsfe` files have it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Track<'a> {
+    pub label: Option<&'a str>,
+    /// How long the song plays before fading out.
+    pub length: Option<Duration>,
+    pub fade: Option<Duration>,
+}
+
+impl<'a> Nsf<'a> {
+    /// Parses an `.nsf` or `.nsfe` file.
+    ///
+    /// # Errors
+    /// Errors if the file is invalid, or is an `.nsfe` file with a required chunk that isn't
+    /// understood.
+    pub fn new(raw: &'a [u8]) -> Result<Self, Error> {
+        if raw.starts_with(&NSF_TAG) {
+            Self::parse_nsf(raw)
+        } else if raw.starts_with(&NSFE_TAG) {
+            Self::parse_nsfe(raw)
+        } else {
+            Err(Error::InvalidMagicBytes)
+        }
+    }
+
+    fn parse_nsf(raw: &'a [u8]) -> Result<Self, Error> {
+        let header = raw.get(..0x80).ok_or(Error::UnexpectedEOI)?;
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+
+        // NSF2 can give the data's length, so metadata can follow it
+        let len = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]);
+        let data = if header[5] >= 2 && len != 0 {
+            let len = usize::try_from(len).map_err(|_| Error::UnexpectedEOI)?;
+            raw.get(0x80..0x80 + len).ok_or(Error::UnexpectedEOI)?
+        } else {
+            &raw[0x80..]
+        };
+
+        let banks: [u8; 8] = header[0x70..0x78].try_into().unwrap_or_default();
+        Ok(Self {
+            data: data.into(),
+            songs: header[6],
+            starting_song: header[7].saturating_sub(1),
+            load_addr: u16_at(0x08),
+            init_addr: u16_at(0x0A),
+            play_addr: u16_at(0x0C),
+            title: text(&header[0x0E..0x2E]).map(Shared::from),
+            artist: text(&header[0x2E..0x4E]).map(Shared::from),
+            copyright: text(&header[0x4E..0x6E]).map(Shared::from),
+            ripper: None,
+            banks: banks.iter().any(|&bank| bank != 0).then_some(banks),
+            ntsc_speed: u16_at(0x6E),
+            pal_speed: u16_at(0x78),
+            timing: timing(header[0x7A]),
+            chips: Chips::from_bits_truncate(header[0x7B]),
+            playlist: None,
+            labels: Shared::default(),
+            lengths: Shared::default(),
+            fades: Shared::default(),
+        })
+    }
+
+    fn parse_nsfe(raw: &'a [u8]) -> Result<Self, Error> {
+        let mut reader = untrusted::Reader::new(untrusted::Input::from(&raw[NSFE_TAG.len()..]));
+        let mut nsf = Self {
+            data: Shared::default(),
+            songs: 1,
+            starting_song: 0,
+            load_addr: 0,
+            init_addr: 0,
+            play_addr: 0,
+            title: None,
+            artist: None,
+            copyright: None,
+            ripper: None,
+            banks: None,
+            ntsc_speed: NTSC_SPEED,
+            pal_speed: PAL_SPEED,
+            timing: Timing::Ntsc,
+            chips: Chips::empty(),
+            playlist: None,
+            labels: Shared::default(),
+            lengths: Shared::default(),
+            fades: Shared::default(),
+        };
+        let mut info = None;
+        let mut data = None;
+
+        loop {
+            let len = usize::try_from(read_u32(&mut reader)?).map_err(|_| Error::UnexpectedEOI)?;
+            let id = reader.read_bytes(4)?.as_slice_less_safe();
+            let chunk = reader.read_bytes(len)?.as_slice_less_safe();
+
+            match id {
+                b"INFO" => info = Some(chunk),
+                b"DATA" => data = Some(chunk),
+                b"NEND" => break,
+                b"BANK" => {
+                    let mut banks = [0; 8];
+                    for (bank, &val) in banks.iter_mut().zip(chunk) {
+                        *bank = val;
+                    }
+                    nsf.banks = Some(banks);
+                }
+                b"RATE" => {
+                    if let [ntsc_lo, ntsc_hi, rest @ ..] = chunk {
+                        nsf.ntsc_speed = u16::from_le_bytes([*ntsc_lo, *ntsc_hi]);
+                        if let [pal_lo, pal_hi, ..] = rest {
+                            nsf.pal_speed = u16::from_le_bytes([*pal_lo, *pal_hi]);
+                        }
+                    }
+                }
+                b"auth" => {
+                    nsf.title = nth_text(chunk, 0).map(Shared::from);
+                    nsf.artist = nth_text(chunk, 1).map(Shared::from);
+                    nsf.copyright = nth_text(chunk, 2).map(Shared::from);
+                    nsf.ripper = nth_text(chunk, 3).map(Shared::from);
+                }
+                b"plst" => nsf.playlist = Some(chunk.into()),
+                b"tlbl" => nsf.labels = chunk.into(),
+                b"time" => nsf.lengths = chunk.into(),
+                b"fade" => nsf.fades = chunk.into(),
+                // chunks starting with a capital letter must be understood to play the file
+                [b'A'..=b'Z', ..] => {
+                    log::warn!("unknown NSFe chunk: {:?}", core::str::from_utf8(id));
+                    return Err(Error::UnsupportedFormat);
+                }
+                _ => log::debug!("skipping NSFe chunk {:?}", core::str::from_utf8(id)),
+            }
+        }
+
+        let info = info.ok_or(Error::UnexpectedEOI)?;
+        let [load_lo, load_hi, init_lo, init_hi, play_lo, play_hi, region, chips, rest @ ..] = info
+        else {
+            return Err(Error::UnexpectedEOI);
+        };
+        nsf.load_addr = u16::from_le_bytes([*load_lo, *load_hi]);
+        nsf.init_addr = u16::from_le_bytes([*init_lo, *init_hi]);
+        nsf.play_addr = u16::from_le_bytes([*play_lo, *play_hi]);
+        nsf.timing = timing(*region);
+        nsf.chips = Chips::from_bits_truncate(*chips);
+        if let Some(&songs) = rest.first() {
+            nsf.songs = songs;
+        }
+        if let Some(&start) = rest.get(1) {
+            nsf.starting_song = start;
+        }
+        nsf.data = data.ok_or(Error::UnexpectedEOI)?.into();
+
+        Ok(nsf)
+    }
+
+    /// The 4KiB banks at `$6000..=$FFFF` when a song starts, as written to `$5FF6..=$5FFF`.
+    ///
+    /// Only FDS tunes bank `$6000..=$7FFF`, with the same banks as `$E000..=$FFFF`. Tunes that
+    /// don't bankswitch are numbered as if they did, from `$6000` for FDS tunes and `$8000`
+    /// otherwise.
+    #[must_use]
+    pub fn initial_banks(&self) -> [u8; 10] {
+        let mut banks = [0; 10];
+        if let Some(b) = self.banks {
+            banks[..2].copy_from_slice(&b[6..]);
+            banks[2..].copy_from_slice(&b);
+        } else {
+            let first = if self.chips.contains(Chips::FDS) {
+                0
+            } else {
+                2
+            };
+            for (bank, n) in banks[first..].iter_mut().zip(0..) {
+                *bank = n;
+            }
+        }
+        banks
+    }
+
+    /// A song's metadata, counting from 0. [`None`] if there's no such song.
+    #[must_use]
+    pub fn track(&self, song: u8) -> Option<Track<'_>> {
+        (song < self.songs).then(|| Track {
+            label: nth_text(&self.labels, usize::from(song)),
+            length: duration(&self.lengths, song),
+            fade: duration(&self.fades, song),
+        })
+    }
+
+    /// Copies everything the NSF borrows from the file; see
+    /// [`Rom::into_owned`](super::Rom::into_owned).
+    #[cfg(feature = "alloc")]
+    #[must_use]
+    pub fn into_owned(self) -> Nsf<'static> {
+        Nsf {
+            data: self.data.into_owned(),
+            title: self.title.map(Shared::into_owned),
+            artist: self.artist.map(Shared::into_owned),
+            copyright: self.copyright.map(Shared::into_owned),
+            ripper: self.ripper.map(Shared::into_owned),
+            playlist: self.playlist.map(Shared::into_owned),
+            labels: self.labels.into_owned(),
+            lengths: self.lengths.into_owned(),
+            fades: self.fades.into_owned(),
+            ..self
+        }
+    }
+
+    /// Converts the NSF into a [`Rom`] for mapper 31, with the tune as PRG ROM.
+    #[must_use]
+    pub fn into_rom(self) -> Rom<'a> {
+        Rom {
+            crc32: crate::crc::crc32(&[&self.data]),
+            prg_rom: self.data.clone(),
+            chr_rom: Shared::default(),
+            format: Format::Nsf,
+            trainer: None,
+            mapper: MAPPER,
+            submapper: 0,
+            mirroring: Mirroring::Vertical,
+            // the FDS RAM adapter's, at `$6000..=$DFFF`
+            prg_ram_size: if self.chips.contains(Chips::FDS) {
+                0x8000
+            } else {
+                PRG_RAM_PAGE_SIZE
+            },
+            prg_nvram_size: 0,
+            battery: false,
+            chr_ram_size: CHR_ROM_PAGE_SIZE,
+            chr_nvram_size: 0,
+            timing: self.timing,
+            console: Console::Nes,
+            misc_roms: 0,
+            expansion_device: 0,
+            warnings: if self.data.is_empty() {
+                Warnings::NO_PRG_ROM
+            } else {
+                Warnings::empty()
+            },
+            disk: None,
+            nsf: Some(self),
+        }
+    }
+}
+
+/// Parses an `.nsf` or `.nsfe` file.
+pub(super) fn parse(raw: &[u8]) -> Result<Rom<'_>, Error> {
+    Ok(Nsf::new(raw)?.into_rom())
+}
+
+const fn timing(region: u8) -> Timing {
+    match region & 0b11 {
+        0 => Timing::Ntsc,
+        1 => Timing::Pal,
+        _ => Timing::MultiRegion,
+    }
+}
+
+/// A NUL-padded string; [`None`] if it's empty, unknown (`<?>`) or not UTF-8.
+fn text(bytes: &[u8]) -> Option<&str> {
+    let bytes = bytes.split(|&b| b == 0).next().unwrap_or_default();
+    core::str::from_utf8(bytes)
+        .ok()
+        .filter(|text| !text.is_empty() && *text != "<?>")
+}
+
+/// The `n`th string in a chunk of NUL-terminated strings.
+fn nth_text(chunk: &[u8], n: usize) -> Option<&str> {
+    text(chunk.split(|&b| b == 0).nth(n)?)
+}
+
+/// The `n`th duration in a chunk of milliseconds; negative ones are unknown.
+fn duration(chunk: &[u8], n: u8) -> Option<Duration> {
+    let start = usize::from(n) * 4;
+    let ms = i32::from_le_bytes(chunk.get(start..start + 4)?.try_into().ok()?);
+    Some(Duration::from_millis(u64::try_from(ms).ok()?))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// An NSF with three songs, loaded at `$8000`, with the given code.
+    pub fn nsf(init: u16, play: u16, code: &[u8]) -> Vec<u8> {
+        let mut raw = NSF_TAG.to_vec();
+        raw.extend([1, 3, 2]);
+        raw.extend(0x8000_u16.to_le_bytes());
+        raw.extend(init.to_le_bytes());
+        raw.extend(play.to_le_bytes());
+        raw.extend(b"Title");
+        raw.resize(0x2E, 0);
+        raw.extend(b"<?>");
+        raw.resize(0x6E, 0);
+        raw.extend(16639_u16.to_le_bytes());
+        raw.resize(0x78, 0);
+        raw.extend(19997_u16.to_le_bytes());
+        raw.extend([0b10, 0b0001_0001]);
+        raw.resize(0x80, 0);
+        raw.extend(code);
+        raw
+    }
+
+    fn chunk(id: [u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = u32::try_from(data.len()).unwrap().to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(data);
+        chunk
+    }
+
+    #[test]
+    fn parse_nsf() {
+        let raw = nsf(0x8000, 0x8003, &[0x60; 4]);
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(*nsf.data, [0x60; 4]);
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.load_addr, 0x8000);
+        assert_eq!(nsf.play_addr, 0x8003);
+        assert_eq!(nsf.title.as_deref(), Some("Title"));
+        assert_eq!(nsf.artist.as_deref(), None);
+        assert_eq!(nsf.banks, None);
+        assert_eq!(nsf.timing, Timing::MultiRegion);
+        assert_eq!(nsf.chips, Chips::VRC6 | Chips::N163);
+        assert_eq!(nsf.track(0).unwrap().label, None);
+        assert_eq!(nsf.track(3), None);
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.format, Format::Nsf);
+        assert_eq!(rom.mapper, MAPPER);
+        assert_eq!(rom.nsf, Some(nsf));
+
+        assert_eq!(Nsf::new(&raw[..0x7F]), Err(Error::UnexpectedEOI));
+    }
+
+    #[test]
+    fn parse_nsfe() {
+        let mut raw = NSFE_TAG.to_vec();
+        raw.extend(chunk(
+            *b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 1, 0, 2],
+        ));
+        raw.extend(chunk(*b"BANK", &[0, 1, 2]));
+        raw.extend(chunk(*b"RATE", &[0x0A, 0x41]));
+        raw.extend(chunk(*b"auth", b"Game\0Artist\0\0Ripper\0"));
+        raw.extend(chunk(*b"tlbl", b"Intro\0Level 1\0"));
+        raw.extend(chunk(*b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        raw.extend(chunk(*b"zzzz", b"skipped"));
+        raw.extend(chunk(*b"DATA", &[0x60; 4]));
+        raw.extend(chunk(*b"NEND", &[]));
+
+        let nsf = Nsf::new(&raw).unwrap();
+        assert_eq!(*nsf.data, [0x60; 4]);
+        assert_eq!(nsf.songs, 2);
+        assert_eq!(nsf.play_addr, 0x8003);
+        assert_eq!(nsf.timing, Timing::Pal);
+        assert_eq!(nsf.banks, Some([0, 1, 2, 0, 0, 0, 0, 0]));
+        assert_eq!(nsf.ntsc_speed, 0x410A);
+        assert_eq!(nsf.pal_speed, PAL_SPEED);
+        assert_eq!(nsf.title.as_deref(), Some("Game"));
+        assert_eq!(nsf.artist.as_deref(), Some("Artist"));
+        assert_eq!(nsf.copyright.as_deref(), None);
+        assert_eq!(nsf.ripper.as_deref(), Some("Ripper"));
+        assert_eq!(
+            nsf.track(0),
+            Some(Track {
+                label: Some("Intro"),
+                length: Some(Duration::from_secs(10)),
+                fade: None,
+            })
+        );
+        assert_eq!(nsf.track(1).unwrap().label, Some("Level 1"));
+        assert_eq!(nsf.track(1).unwrap().length, None);
+
+        let end = raw.len() - 8;
+        let mut unknown = raw[..end].to_vec();
+        unknown.extend(chunk(*b"ZZZZ", &[]));
+        assert_eq!(Nsf::new(&unknown), Err(Error::UnsupportedFormat));
+        assert_eq!(Nsf::new(&raw[..end]), Err(Error::UnexpectedEOI));
+    }
+}
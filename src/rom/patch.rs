@@ -0,0 +1,509 @@
+//! IPS, UPS and BPS patches, applied to a ROM file before it's parsed.
+//!
+//! There's no allocator, so the patched file is written to a buffer the caller provides;
+//! [`target_size`] says how big it needs to be.
+//!
+//! # Examples
+//! ```no_run
+//! use fete::rom::{patch, Rom};
+//!
+//! # let (raw, ips) = (Vec::new(), Vec::new());
+//! let mut patched = vec![0; patch::target_size(&ips, &raw).unwrap()];
+//! let len = patch::apply(&ips, &raw, &mut patched).unwrap();
+//! let rom = Rom::new(&patched[..len]).unwrap();
+//! ```
+
+use crate::crc::crc32;
+use snafu::prelude::*;
+
+pub const IPS_TAG: [u8; 5] = *b"PATCH";
+pub const UPS_TAG: [u8; 4] = *b"UPS1";
+pub const BPS_TAG: [u8; 4] = *b"BPS1";
+
+/// The offset that ends an IPS patch: `EOF` in ASCII.
+const IPS_EOF: usize = 0x45_4F46;
+/// UPS and BPS patches end with the CRC32s of the source, target and patch.
+const FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("not an IPS, UPS or BPS patch"))]
+    UnknownFormat,
+    #[snafu(display("unexpected end of patch"))]
+    UnexpectedEOI,
+    #[snafu(display("patched ROM needs {needed} bytes, but the buffer only has {actual}"))]
+    BufferTooSmall { needed: usize, actual: usize },
+    #[snafu(display("patch is for a {expected}-byte ROM, not {actual} bytes"))]
+    SourceSize { expected: usize, actual: usize },
+    #[snafu(display("patch is for a ROM with CRC32 {expected:08X}, not {actual:08X}"))]
+    SourceChecksum { expected: u32, actual: u32 },
+    #[snafu(display("patched ROM should have CRC32 {expected:08X}, but has {actual:08X}"))]
+    TargetChecksum { expected: u32, actual: u32 },
+    #[snafu(display("patch should have CRC32 {expected:08X}, but has {actual:08X}"))]
+    PatchChecksum { expected: u32, actual: u32 },
+    #[snafu(display("patch reads or writes outside the ROM"))]
+    OutOfBounds,
+}
+impl From<untrusted::EndOfInput> for Error {
+    fn from(_: untrusted::EndOfInput) -> Self {
+        Self::UnexpectedEOI
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    /// International Patching System: records overwriting the file at 24-bit offsets.
+    Ips,
+    /// Universal Patching System: the bytes that differ from the source.
+    Ups,
+    /// Binary Patching System: the target built from copies of the source, target and patch.
+    Bps,
+}
+
+impl Format {
+    /// Detects a patch's format from its magic bytes.
+    ///
+    /// # Errors
+    /// Errors if the patch isn't IPS, UPS or BPS.
+    pub fn detect(patch: &[u8]) -> Result<Self, Error> {
+        if patch.starts_with(&IPS_TAG) {
+            Ok(Self::Ips)
+        } else if patch.starts_with(&UPS_TAG) {
+            Ok(Self::Ups)
+        } else if patch.starts_with(&BPS_TAG) {
+            Ok(Self::Bps)
+        } else {
+            Err(Error::UnknownFormat)
+        }
+    }
+}
+
+/// The size of `source` after applying `patch`.
+///
+/// # Errors
+/// Errors if the patch is invalid.
+pub fn target_size(patch: &[u8], source: &[u8]) -> Result<usize, Error> {
+    match Format::detect(patch)? {
+        Format::Ips => ips(patch, source, None),
+        Format::Ups | Format::Bps => Ok(Header::new(patch)?.target_size),
+    }
+}
+
+/// Applies `patch` to `source`, writing the patched file to the start of `target`. Returns its
+/// size.
+///
+/// UPS and BPS patches are checked against the CRC32s they store of the patch, the source and
+/// the target.
+///
+/// # Errors
+/// Errors if the patch is invalid, `target` is smaller than [`target_size`], or the checksums
+/// don't match.
+pub fn apply(patch: &[u8], source: &[u8], target: &mut [u8]) -> Result<usize, Error> {
+    let needed = target_size(patch, source)?;
+    ensure!(
+        needed <= target.len(),
+        BufferTooSmallSnafu {
+            needed,
+            actual: target.len()
+        }
+    );
+    let target = &mut target[..needed];
+
+    match Format::detect(patch)? {
+        Format::Ips => {
+            let copied = source.len().min(needed);
+            target[..copied].copy_from_slice(&source[..copied]);
+            target[copied..].fill(0);
+            ips(patch, source, Some(target))?;
+        }
+        Format::Ups => ups(patch, source, target)?,
+        Format::Bps => bps(patch, source, target)?,
+    }
+    Ok(needed)
+}
+
+/// Walks an IPS patch, applying its records to `target` if there is one, and returns the size
+/// of the patched file.
+fn ips(patch: &[u8], source: &[u8], mut target: Option<&mut [u8]>) -> Result<usize, Error> {
+    let mut reader = untrusted::Reader::new(untrusted::Input::from(&patch[IPS_TAG.len()..]));
+    let mut size = source.len();
+
+    loop {
+        let offset = read_u24(&mut reader)?;
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let len = usize::from(read_u16(&mut reader)?);
+        let record = if len == 0 {
+            // run-length encoded
+            let len = usize::from(read_u16(&mut reader)?);
+            (len, None, reader.read_byte()?)
+        } else {
+            (len, Some(reader.read_bytes(len)?.as_slice_less_safe()), 0)
+        };
+
+        let (len, data, val) = record;
+        size = size.max(offset + len);
+        if let Some(target) = target.as_deref_mut() {
+            // anything past a truncated end is dropped
+            let end = (offset + len).min(target.len());
+            match (target.get_mut(offset..end), data) {
+                (Some(dest), Some(data)) => dest.copy_from_slice(&data[..dest.len()]),
+                (Some(dest), None) => dest.fill(val),
+                (None, _) => {}
+            }
+        }
+    }
+
+    // an extension some patchers use to shrink or grow the file
+    if !reader.at_end() {
+        size = read_u24(&mut reader)?;
+    }
+    Ok(size)
+}
+
+/// The sizes at the start of UPS and BPS patches, and the checksums at the end.
+struct Header<'a> {
+    /// The patch after the sizes (and BPS metadata), up to the checksums.
+    body: untrusted::Reader<'a>,
+    source_size: usize,
+    target_size: usize,
+    source_crc: u32,
+    target_crc: u32,
+}
+
+impl<'a> Header<'a> {
+    fn new(patch: &'a [u8]) -> Result<Self, Error> {
+        let format = Format::detect(patch)?;
+        let footer_start = patch
+            .len()
+            .checked_sub(FOOTER_SIZE)
+            .filter(|&start| start >= UPS_TAG.len())
+            .ok_or(Error::UnexpectedEOI)?;
+        let crc_at = |i: usize| {
+            let start = footer_start + i * 4;
+            u32::from_le_bytes([
+                patch[start],
+                patch[start + 1],
+                patch[start + 2],
+                patch[start + 3],
+            ])
+        };
+
+        let expected = crc_at(2);
+        let actual = crc32(&[&patch[..patch.len() - 4]]);
+        if expected != actual {
+            return Err(Error::PatchChecksum { expected, actual });
+        }
+
+        let mut body =
+            untrusted::Reader::new(untrusted::Input::from(&patch[UPS_TAG.len()..footer_start]));
+        let source_size = read_varint(&mut body)?;
+        let target_size = read_varint(&mut body)?;
+        if format == Format::Bps {
+            let metadata_size = read_varint(&mut body)?;
+            body.read_bytes(metadata_size)?;
+        }
+
+        Ok(Self {
+            body,
+            source_size,
+            target_size,
+            source_crc: crc_at(0),
+            target_crc: crc_at(1),
+        })
+    }
+
+    /// Checks the source is the one the patch was made for.
+    fn check_source(&self, source: &[u8]) -> Result<(), Error> {
+        if source.len() != self.source_size {
+            return Err(Error::SourceSize {
+                expected: self.source_size,
+                actual: source.len(),
+            });
+        }
+        let actual = crc32(&[source]);
+        if actual != self.source_crc {
+            return Err(Error::SourceChecksum {
+                expected: self.source_crc,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_target(&self, target: &[u8]) -> Result<(), Error> {
+        let actual = crc32(&[target]);
+        if actual != self.target_crc {
+            return Err(Error::TargetChecksum {
+                expected: self.target_crc,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn ups(patch: &[u8], source: &[u8], target: &mut [u8]) -> Result<(), Error> {
+    let mut header = Header::new(patch)?;
+    header.check_source(source)?;
+
+    let copied = source.len().min(target.len());
+    target[..copied].copy_from_slice(&source[..copied]);
+    target[copied..].fill(0);
+
+    let mut pos = 0;
+    while !header.body.at_end() {
+        pos += read_varint(&mut header.body)?;
+        // XORed bytes, up to and including a zero
+        loop {
+            let xor = header.body.read_byte()?;
+            if let Some(byte) = target.get_mut(pos) {
+                *byte ^= xor;
+            } else if xor != 0 {
+                return Err(Error::OutOfBounds);
+            }
+            pos += 1;
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+
+    header.check_target(target)
+}
+
+fn bps(patch: &[u8], source: &[u8], target: &mut [u8]) -> Result<(), Error> {
+    let mut header = Header::new(patch)?;
+    header.check_source(source)?;
+
+    let mut pos = 0;
+    let mut source_pos = 0;
+    let mut target_pos = 0;
+    while !header.body.at_end() {
+        let action = read_varint(&mut header.body)?;
+        let len = (action >> 2) + 1;
+        let dest = pos..pos + len;
+        if dest.end > target.len() {
+            return Err(Error::OutOfBounds);
+        }
+
+        match action & 0b11 {
+            // source read: the same bytes as the source
+            0 => {
+                let src = source.get(dest.clone()).ok_or(Error::OutOfBounds)?;
+                target[dest].copy_from_slice(src);
+            }
+            // target read: bytes from the patch
+            1 => target[dest].copy_from_slice(header.body.read_bytes(len)?.as_slice_less_safe()),
+            // source copy: bytes from anywhere in the source
+            2 => {
+                source_pos = relative(source_pos, read_varint(&mut header.body)?)?;
+                let src = source
+                    .get(source_pos..source_pos + len)
+                    .ok_or(Error::OutOfBounds)?;
+                target[dest].copy_from_slice(src);
+                source_pos += len;
+            }
+            // target copy: bytes already written, which may overlap the ones being written
+            _ => {
+                target_pos = relative(target_pos, read_varint(&mut header.body)?)?;
+                if target_pos >= pos {
+                    return Err(Error::OutOfBounds);
+                }
+                for i in dest {
+                    target[i] = target[target_pos];
+                    target_pos += 1;
+                }
+            }
+        }
+        pos += len;
+    }
+
+    header.check_target(target)
+}
+
+/// Moves a BPS copy offset by a signed amount, stored as a magnitude with the sign in bit 0.
+fn relative(pos: usize, offset: usize) -> Result<usize, Error> {
+    if offset & 1 == 0 {
+        pos.checked_add(offset >> 1)
+    } else {
+        pos.checked_sub(offset >> 1)
+    }
+    .ok_or(Error::OutOfBounds)
+}
+
+fn read_u16(reader: &mut untrusted::Reader) -> Result<u16, Error> {
+    Ok(u16::from_be_bytes([
+        reader.read_byte()?,
+        reader.read_byte()?,
+    ]))
+}
+
+fn read_u24(reader: &mut untrusted::Reader) -> Result<usize, Error> {
+    let bytes = [
+        0,
+        reader.read_byte()?,
+        reader.read_byte()?,
+        reader.read_byte()?,
+    ];
+    usize::try_from(u32::from_be_bytes(bytes)).map_err(|_| Error::OutOfBounds)
+}
+
+/// Reads a UPS/BPS number: seven bits per byte, least significant first, with bit 7 set on the
+/// last byte. Each continuation adds one, so every number has a single encoding.
+fn read_varint(reader: &mut untrusted::Reader) -> Result<usize, Error> {
+    let mut num: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let byte = reader.read_byte()?;
+        num = usize::from(byte & 0x7F)
+            .checked_mul(shift)
+            .and_then(|bits| num.checked_add(bits))
+            .ok_or(Error::OutOfBounds)?;
+        if byte & 0x80 != 0 {
+            return Ok(num);
+        }
+        shift = shift.checked_mul(0x80).ok_or(Error::OutOfBounds)?;
+        num = num.checked_add(shift).ok_or(Error::OutOfBounds)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn varint(mut num: usize) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let bits = u8::try_from(num & 0x7F).unwrap();
+            num >>= 7;
+            if num == 0 {
+                out.push(bits | 0x80);
+                return out;
+            }
+            out.push(bits);
+            num -= 1;
+        }
+    }
+
+    /// Adds the header and checksums to a UPS or BPS patch.
+    fn wrap(tag: &[u8], source: &[u8], target: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut patch = tag.to_vec();
+        patch.extend(varint(source.len()));
+        patch.extend(varint(target.len()));
+        patch.extend(body);
+        patch.extend(crc32(&[source]).to_le_bytes());
+        patch.extend(crc32(&[target]).to_le_bytes());
+        patch.extend(crc32(&[&patch]).to_le_bytes());
+        patch
+    }
+
+    fn patched(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut target = vec![0xAA; target_size(patch, source)?];
+        let len = apply(patch, source, &mut target)?;
+        target.truncate(len);
+        Ok(target)
+    }
+
+    #[test]
+    fn varints() {
+        for num in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 0x12_3456] {
+            let bytes = varint(num);
+            let mut reader = untrusted::Reader::new(untrusted::Input::from(&bytes));
+            assert_eq!(read_varint(&mut reader), Ok(num));
+        }
+    }
+
+    #[test]
+    fn ips_patch() {
+        let mut patch = IPS_TAG.to_vec();
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0xAB, 0xCD]);
+        patch.extend([0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xEE]); // RLE
+        patch.extend(b"EOF");
+        assert_eq!(
+            patched(&patch, &[0; 4]),
+            Ok(vec![0, 0xAB, 0xCD, 0, 0, 0, 0xEE, 0xEE, 0xEE])
+        );
+
+        patch.extend([0x00, 0x00, 0x02]);
+        assert_eq!(patched(&patch, &[0; 4]), Ok(vec![0, 0xAB]));
+
+        assert_eq!(
+            apply(&patch, &[0; 4], &mut [0; 1]),
+            Err(Error::BufferTooSmall {
+                needed: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(patched(&patch[..12], &[0; 4]), Err(Error::UnexpectedEOI));
+        assert_eq!(patched(b"PAT", &[0; 4]), Err(Error::UnknownFormat));
+    }
+
+    #[test]
+    fn ups_patch() {
+        let source = [1, 2, 3, 4];
+        let target = [1, 5, 3, 4, 9, 9];
+        let mut body = varint(1);
+        body.extend([2 ^ 5, 0]);
+        body.extend(varint(1));
+        body.extend([9, 9, 0]);
+        let patch = wrap(&UPS_TAG, &source, &target, &body);
+        assert_eq!(patched(&patch, &source), Ok(target.to_vec()));
+
+        assert_eq!(
+            patched(&patch, &[1, 2, 3, 5]),
+            Err(Error::SourceChecksum {
+                expected: crc32(&[&source]),
+                actual: crc32(&[&[1, 2, 3, 5]]),
+            })
+        );
+        assert_eq!(
+            patched(&patch, &[1, 2, 3]),
+            Err(Error::SourceSize {
+                expected: 4,
+                actual: 3
+            })
+        );
+
+        let mut corrupt = patch;
+        corrupt[7] ^= 1;
+        assert!(matches!(
+            patched(&corrupt, &source),
+            Err(Error::PatchChecksum { .. })
+        ));
+
+        // a patch whose target checksum is wrong
+        let patch = wrap(&UPS_TAG, &source, &[1, 5, 3, 4, 9, 8], &body);
+        assert!(matches!(
+            patched(&patch, &source),
+            Err(Error::TargetChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn bps_patch() {
+        let source = *b"ABCDEFGH";
+        let target = *b"ABCxyzEFGHABABABA";
+        let mut body = varint(0); // no metadata
+        body.extend(varint((3 - 1) << 2)); // source read: ABC
+        body.extend(varint(((3 - 1) << 2) | 1)); // target read: xyz
+        body.extend(b"xyz");
+        body.extend(varint(((4 - 1) << 2) | 2)); // source copy from 4: EFGH
+        body.extend(varint(4 << 1));
+        body.extend(varint(((2 - 1) << 2) | 2)); // source copy from 0: AB
+        body.extend(varint((8 << 1) | 1));
+        body.extend(varint(((5 - 1) << 2) | 3)); // target copy from 10, overlapping: ABABA
+        body.extend(varint(10 << 1));
+        body.extend(varint(3)); // target copy of 1 byte, from past the end
+        body.extend(varint(2 << 1));
+        let patch = wrap(&BPS_TAG, &source, &target, &body);
+
+        assert_eq!(patched(&patch, &source), Err(Error::OutOfBounds));
+        let end = body.len() - 2;
+        let patch = wrap(&BPS_TAG, &source, &target, &body[..end]);
+        assert_eq!(patched(&patch, &source), Ok(target.to_vec()));
+    }
+}
@@ -0,0 +1,80 @@
+//! Storage for the data a [`Rom`](super::Rom) is made of.
+
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+use core::{fmt, ops::Deref};
+
+/// Immutable ROM data, which derefs to the data itself.
+///
+/// Parsing borrows straight from the file. With the `alloc` feature, the data can instead be
+/// owned (see [`Rom::into_owned`](super::Rom::into_owned)), so a ROM, and the
+/// [`Cpu`](crate::cpu::Cpu) running it, can be `'static`. Owned data is reference counted, so
+/// cloning a ROM is cheap either way.
+pub enum Shared<'a, T: ?Sized + 'a = [u8]> {
+    Borrowed(&'a T),
+    #[cfg(feature = "alloc")]
+    Owned(Arc<T>),
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized + 'static> Shared<'_, T>
+where
+    for<'b> Arc<T>: From<&'b T>,
+{
+    /// Copies borrowed data, so it no longer borrows anything.
+    #[must_use]
+    pub fn into_owned(self) -> Shared<'static, T> {
+        match self {
+            Self::Borrowed(data) => Shared::Owned(Arc::from(data)),
+            Self::Owned(data) => Shared::Owned(data),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Shared<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Self::Borrowed(data) => data,
+            #[cfg(feature = "alloc")]
+            Self::Owned(data) => data,
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Shared<'_, T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Borrowed(data) => Self::Borrowed(data),
+            #[cfg(feature = "alloc")]
+            Self::Owned(data) => Self::Owned(Arc::clone(data)),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> From<&'a T> for Shared<'a, T> {
+    fn from(data: &'a T) -> Self {
+        Self::Borrowed(data)
+    }
+}
+
+impl<T> Default for Shared<'_, [T]> {
+    fn default() -> Self {
+        Self::Borrowed(&[])
+    }
+}
+
+// compares the data, whether it's borrowed or owned
+impl<T: ?Sized + PartialEq> PartialEq for Shared<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+impl<T: ?Sized + Eq> Eq for Shared<'_, T> {}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Shared<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
@@ -0,0 +1,359 @@
+//! The UNIF format: a 32-byte header followed by tagged chunks, with the board named by a
+//! string rather than a mapper number.
+//!
+//! PRG and CHR ROM can be split across up to 16 chunks each (`PRG0`..`PRGF`, `CHR0`..`CHRF`),
+//! which are joined in index order. A ROM with a single chunk of each is borrowed from the
+//! file; joining split chunks copies them, so it needs the `alloc` feature.
+//!
+//! Boards are translated to mapper numbers with [`board_mapper`], and files whose board isn't
+//! known are rejected with [`Error::UnsupportedBoard`]. A known board whose mapper isn't emulated
+//! loads like an iNES file with that mapper would: running as NROM, with
+//! [`Warnings::UNSUPPORTED_MAPPER`] in its [`RomInfo`](super::RomInfo).
+
+use super::{
+    Console, Error, Format, Mirroring, Rom, Shared, Timing, Warnings, CHR_ROM_PAGE_SIZE,
+    PRG_RAM_PAGE_SIZE,
+};
+
+pub const UNIF_TAG: [u8; 4] = *b"UNIF";
+
+/// Parses a UNIF file.
+pub(super) fn parse(raw: &[u8]) -> Result<Rom<'_>, Error> {
+    let mut reader = untrusted::Reader::new(untrusted::Input::from(raw));
+
+    if reader.read_bytes(4)?.as_slice_less_safe() != UNIF_TAG {
+        return Err(Error::InvalidMagicBytes);
+    }
+    let revision = read_u32(&mut reader)?;
+    let _padding = reader.read_bytes(24)?;
+    log::debug!("UNIF revision {revision}");
+
+    let mut board = None;
+    let mut prg_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut chr_chunks: [Option<&[u8]>; 16] = [None; 16];
+    let mut mirroring = Mirroring::Horizontal;
+    let mut battery = false;
+    let mut vram_override = false;
+    let mut timing = Timing::Ntsc;
+
+    while !reader.at_end() {
+        let id = reader.read_bytes(4)?.as_slice_less_safe();
+        let len = usize::try_from(read_u32(&mut reader)?).map_err(|_| Error::UnexpectedEOI)?;
+        let data = reader.read_bytes(len)?.as_slice_less_safe();
+
+        match id {
+            b"MAPR" => board = Some(data.split(|&b| b == 0).next().unwrap_or_default()),
+            [b'P', b'R', b'G', n] | [b'C', b'H', b'R', n] => {
+                let chunks = if id.starts_with(b"PRG") {
+                    &mut prg_chunks
+                } else {
+                    &mut chr_chunks
+                };
+                if let Some(i) = chunk_index(*n) {
+                    chunks[i] = Some(data);
+                } else {
+                    log::debug!("skipping UNIF chunk {:?}", core::str::from_utf8(id));
+                }
+            }
+            b"MIRR" => {
+                mirroring = match data.first() {
+                    Some(1) => Mirroring::Vertical,
+                    Some(2) => Mirroring::SingleScreenLower,
+                    Some(3) => Mirroring::SingleScreenUpper,
+                    Some(4) => Mirroring::FourScreen,
+                    // 0, or 5 for mapper-controlled
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            b"BATR" => battery = true,
+            b"VROR" => vram_override = true,
+            b"TVCI" => {
+                timing = match data.first() {
+                    Some(1) => Timing::Pal,
+                    Some(2) => Timing::MultiRegion,
+                    _ => Timing::Ntsc,
+                }
+            }
+            _ => log::debug!("skipping UNIF chunk {:?}", core::str::from_utf8(id)),
+        }
+    }
+
+    let board = board.ok_or(Error::UnexpectedEOI)?;
+    let mapper = core::str::from_utf8(board)
+        .ok()
+        .and_then(board_mapper)
+        .ok_or_else(|| {
+            log::warn!("unknown UNIF board: {:?}", core::str::from_utf8(board));
+            Error::UnsupportedBoard
+        })?;
+    if prg_chunks.iter().all(Option::is_none) {
+        return Err(Error::UnexpectedEOI);
+    }
+    let prg_rom = join(&prg_chunks)?;
+    let chr_rom = join(&chr_chunks)?;
+    let chr_ram_size = if vram_override || chr_rom.is_empty() {
+        CHR_ROM_PAGE_SIZE
+    } else {
+        0
+    };
+    let warnings = if prg_rom.is_empty() {
+        Warnings::NO_PRG_ROM
+    } else {
+        Warnings::empty()
+    };
+
+    Ok(Rom {
+        crc32: crate::crc::crc32(&[&prg_rom, &chr_rom]),
+        prg_rom,
+        chr_rom,
+        format: Format::Unif,
+        trainer: None,
+        mapper,
+        submapper: 0,
+        mirroring,
+        prg_ram_size: if battery { 0 } else { PRG_RAM_PAGE_SIZE },
+        prg_nvram_size: if battery { PRG_RAM_PAGE_SIZE } else { 0 },
+        battery,
+        chr_ram_size,
+        chr_nvram_size: 0,
+        timing,
+        console: Console::Nes,
+        misc_roms: 0,
+        expansion_device: 0,
+        warnings,
+        disk: None,
+        nsf: None,
+    })
+}
+
+/// The index of a `PRGn` or `CHRn` chunk, from its last byte: an uppercase hex digit.
+const fn chunk_index(n: u8) -> Option<usize> {
+    match n {
+        b'0'..=b'9' => Some((n - b'0') as usize),
+        b'A'..=b'F' => Some((n - b'A' + 10) as usize),
+        _ => None,
+    }
+}
+
+/// Joins split chunks in index order, borrowing the data if there's only one.
+#[cfg_attr(feature = "alloc", allow(clippy::unnecessary_wraps))] // errors without `alloc`
+fn join<'a>(chunks: &[Option<&'a [u8]>; 16]) -> Result<Shared<'a>, Error> {
+    let mut present = chunks.iter().flatten();
+    match (present.next(), present.next()) {
+        (None, _) => Ok(Shared::default()),
+        (Some(&data), None) => Ok(data.into()),
+        #[cfg(feature = "alloc")]
+        _ => Ok(Shared::Owned(
+            chunks
+                .iter()
+                .flatten()
+                .flat_map(|c| c.iter().copied())
+                .collect(),
+        )),
+        #[cfg(not(feature = "alloc"))]
+        _ => {
+            log::warn!("joining split PRG or CHR chunks needs the `alloc` feature");
+            Err(Error::UnsupportedFormat)
+        }
+    }
+}
+
+pub(super) fn read_u32(reader: &mut untrusted::Reader) -> Result<u32, Error> {
+    let bytes = reader.read_bytes(4)?.as_slice_less_safe();
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Translates a UNIF board name into the iNES mapper number that implements it.
+///
+/// Nintendo's boards are listed with and without their `NES-`/`HVC-` prefix. Unlicensed
+/// (`UNL-`) and multicart (`BMC-`) boards keep theirs, since it's part of the name.
+#[must_use]
+pub fn board_mapper(name: &str) -> Option<u16> {
+    let name = name
+        .strip_prefix("NES-")
+        .or_else(|| name.strip_prefix("HVC-"))
+        .unwrap_or(name);
+
+    Some(match name {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SGROM" | "SKROM" | "SLROM" | "SL1ROM"
+        | "SNROM" | "SOROM" | "SUROM" | "SXROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TR1ROM" | "TSROM"
+        | "TVROM" | "HKROM" => 4,
+        "EKROM" | "ELROM" | "ETROM" | "EWROM" => 5,
+        "AMROM" | "ANROM" | "AN1ROM" | "AOROM" => 7,
+        "PNROM" => 9,
+        "FJROM" | "FKROM" => 10,
+        "CPROM" => 13,
+        "BNROM" => 34,
+        "GNROM" | "MHROM" => 66,
+        "JLROM" | "JSROM" | "BTR" => 69,
+        // unlicensed
+        "UNL-SL1632" => 14,
+        "UNL-CC-21" => 27,
+        "UNL-TEK90" => 90,
+        "UNL-H2288" => 123,
+        "UNL-22211" => 132,
+        "UNL-Sachen-8259D" => 137,
+        "UNL-Sachen-8259B" => 138,
+        "UNL-Sachen-8259C" => 139,
+        "UNL-Sachen-8259A" => 141,
+        "UNL-KS7032" => 142,
+        "UNL-SA-NROM" => 143,
+        "UNL-SA-72007" => 145,
+        "UNL-TC-U01-1.5M" => 147,
+        "UNL-SA-0037" => 148,
+        "UNL-SA-0036" => 149,
+        "UNL-Sachen-74LS374N" => 150,
+        "UNL-8237" => 215,
+        "UNL-603-5052" => 238,
+        "UNL-KOF97" => 263,
+        // multicarts
+        "BMC-SuperHIK8in1" => 45,
+        "BMC-Supervision16in1" => 53,
+        "BMC-D1038" => 59,
+        "BMC-Super24in1SC03" | "BMC-FK23C" | "BMC-FK23CA" => 176,
+        "BMC-NovelDiamond9999999in1" => 201,
+        "BMC-42in1ResetSwitch" => 233,
+        "BMC-70in1" | "BMC-70in1B" => 236,
+        "BMC-810544-C-A1" => 261,
+        "BMC-T-262" => 265,
+        "BMC-GS-2004" | "BMC-GS-2013" => 283,
+        "BMC-A65AS" => 285,
+        "BMC-BS-5" => 286,
+        "BMC-411120-C" => 287,
+        "BMC-NTD-03" => 290,
+        "BMC-11160" => 299,
+        "BMC-190in1" => 300,
+        "BMC-8157" => 301,
+        "BMC-64in1NoRepeat" => 314,
+        "BMC-12-IN-1" => 331,
+        "BMC-WS" => 332,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::Board;
+    use pretty_assertions::assert_eq;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend(u32::try_from(data.len()).unwrap().to_le_bytes());
+        chunk.extend(data);
+        chunk
+    }
+
+    fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut raw = UNIF_TAG.to_vec();
+        raw.extend(7u32.to_le_bytes());
+        raw.extend([0; 24]);
+        for c in chunks {
+            raw.extend(c);
+        }
+        raw
+    }
+
+    #[test]
+    fn parse_unif() {
+        let raw = unif(&[
+            chunk(b"NAME", b"Test\0"),
+            chunk(b"MAPR", b"NES-NROM-256\0"),
+            chunk(b"PRG0", &[1; 0x8000]),
+            chunk(b"CHR0", &[2; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"BATR", &[1]),
+            chunk(b"TVCI", &[1]),
+        ]);
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, Format::Unif);
+        assert_eq!(rom.mapper, 0);
+        assert_eq!(*rom.prg_rom, [1; 0x8000]);
+        assert_eq!(*rom.chr_rom, [2; 0x2000]);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_nvram_size, PRG_RAM_PAGE_SIZE);
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+    }
+
+    #[test]
+    fn chr_ram() {
+        let raw = unif(&[chunk(b"MAPR", b"NROM-128"), chunk(b"PRG0", &[1; 0x4000])]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 0);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.chr_ram_size, CHR_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn split_chunks() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NROM"),
+            chunk(b"PRG1", &[2; 0x2000]),
+            chunk(b"PRG0", &[1; 0x2000]),
+            chunk(b"CHR0", &[3; 0x2000]),
+        ]);
+        let rom = Rom::new(&raw);
+
+        #[cfg(feature = "alloc")]
+        {
+            let rom = rom.unwrap();
+            assert_eq!(*rom.prg_rom, [[1; 0x2000], [2; 0x2000]].concat());
+            assert_eq!(*rom.chr_rom, [3; 0x2000]);
+        }
+        #[cfg(not(feature = "alloc"))]
+        assert_eq!(rom.unwrap_err(), Error::UnsupportedFormat);
+    }
+
+    #[test]
+    fn boards() {
+        assert_eq!(board_mapper("NES-TLROM"), Some(4));
+        assert_eq!(board_mapper("TLROM"), Some(4));
+        assert_eq!(board_mapper("NES-BTR"), Some(69));
+        assert_eq!(board_mapper("UNL-Sachen-8259A"), Some(141));
+        assert_eq!(board_mapper("BMC-FK23C"), Some(176));
+        assert_eq!(board_mapper("Sachen-8259A"), None);
+    }
+
+    #[test]
+    fn emulated_board() {
+        let raw = unif(&[
+            chunk(b"MAPR", b"NES-JLROM\0"),
+            chunk(b"PRG0", &[1; 0x8000]),
+            chunk(b"CHR0", &[2; 0x2000]),
+        ]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 69);
+        assert!(matches!(Board::new(&rom), Some(Board::Fme7(_))));
+        assert_eq!(rom.info().warnings, Warnings::empty());
+    }
+
+    #[test]
+    fn unsupported_board() {
+        // known, but not emulated: runs as NROM, like an iNES file with the same mapper
+        let raw = unif(&[chunk(b"MAPR", b"NES-TLROM"), chunk(b"PRG0", &[1; 0x8000])]);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.info().warnings, Warnings::UNSUPPORTED_MAPPER);
+    }
+
+    #[test]
+    fn errors() {
+        let unknown = unif(&[chunk(b"MAPR", b"UNL-Mystery"), chunk(b"PRG0", &[1])]);
+        assert_eq!(Rom::new(&unknown).unwrap_err(), Error::UnsupportedBoard);
+
+        let no_prg = unif(&[chunk(b"MAPR", b"NROM"), chunk(b"CHR0", &[1])]);
+        assert_eq!(Rom::new(&no_prg).unwrap_err(), Error::UnexpectedEOI);
+
+        let mut truncated = unif(&[chunk(b"MAPR", b"NROM"), chunk(b"PRG0", &[1; 16])]);
+        truncated.truncate(truncated.len() - 1);
+        assert_eq!(Rom::new(&truncated).unwrap_err(), Error::UnexpectedEOI);
+    }
+}
@@ -0,0 +1,13 @@
+// @generated by `cargo xtask romdb`; do not edit.
+
+#![allow(clippy::unreadable_literal)]
+
+#[allow(unused_imports)]
+use crate::rom::{Mirroring, Timing};
+
+use super::Entry;
+
+#[rustfmt::skip]
+pub(super) static ENTRIES: &[Entry] = &[
+    Entry { crc32: 0x158B0388, mapper: 0, submapper: 0, mirroring: Some(Mirroring::Horizontal), battery: false, timing: Timing::Ntsc },
+];
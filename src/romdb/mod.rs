@@ -0,0 +1,64 @@
+//! A database of known-good header values, used to correct the many dumps with wrong mapper
+//! or mirroring bits.
+//!
+//! Entries are keyed by the CRC32 of PRG ROM followed by CHR ROM, and are generated from the
+//! NES 2.0 XML database with `cargo xtask romdb <nes20db.xml>`. The table checked in is
+//! generated from `vetted.xml`, a few games checked against known-good dumps, instead.
+
+pub use crate::crc::crc32;
+use crate::rom::{Mirroring, Timing};
+
+mod entries;
+
+/// The header values a game is known to need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// CRC32 of PRG ROM followed by CHR ROM.
+    pub crc32: u32,
+    pub mapper: u16,
+    pub submapper: u8,
+    /// [`None`] if the mapper controls mirroring.
+    pub mirroring: Option<Mirroring>,
+    pub battery: bool,
+    pub timing: Timing,
+}
+
+/// Looks up a game by the CRC32 of its PRG and CHR ROM.
+#[must_use]
+pub fn lookup(crc32: u32) -> Option<&'static Entry> {
+    lookup_in(entries::ENTRIES, crc32)
+}
+
+/// Looks up an entry in a table sorted by CRC32.
+fn lookup_in(entries: &[Entry], crc32: u32) -> Option<&Entry> {
+    entries
+        .binary_search_by_key(&crc32, |e| e.crc32)
+        .ok()
+        .map(|i| &entries[i])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sorted_lookup() {
+        let entry = |crc32| Entry {
+            crc32,
+            mapper: 4,
+            submapper: 0,
+            mirroring: None,
+            battery: false,
+            timing: Timing::Ntsc,
+        };
+        let entries = [entry(1), entry(5), entry(9)];
+        assert_eq!(lookup_in(&entries, 5), Some(&entries[1]));
+        assert_eq!(lookup_in(&entries, 6), None);
+    }
+
+    #[test]
+    fn entries_are_sorted() {
+        assert!(entries::ENTRIES.windows(2).all(|w| w[0].crc32 < w[1].crc32));
+    }
+}
@@ -0,0 +1,473 @@
+//! Save states: snapshots of the whole machine, in a compact binary format.
+//!
+//! A state starts with a header:
+//!
+//! | Offset | Size | Contents                                              |
+//! |--------|------|-------------------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                             |
+//! | 4      | 2    | [`VERSION`], little-endian                            |
+//! | 6      | 4    | [`Rom::crc32`](crate::rom::Rom::crc32) of the ROM     |
+//! | 10     | 4    | Size of the body                                      |
+//! | 14     | 4    | CRC32 of the body                                     |
+//!
+//! The body is every component's [`State`], one after another, with numbers stored
+//! little-endian. It isn't self-describing: it only makes sense to the same version of the
+//! emulator, running the same ROM, which is what the header checks.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, cpu::Cpu, rom::Rom};
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//!
+//! let mut state = vec![0; cpu.state_size()];
+//! cpu.save_state(&mut state).unwrap();
+//! cpu.reg_a = 0x12;
+//! cpu.load_state(&state).unwrap();
+//! assert_eq!(cpu.reg_a, 0);
+//! ```
+
+use crate::{cpu::Cpu, crc::crc32, rom::Mirroring};
+use core::cell::Cell;
+
+pub const MAGIC: [u8; 4] = *b"FETE";
+/// Bumped whenever the layout of the body changes.
+///
+/// Version 1 covers the CPU, the console and cartridge RAM, and the mapper. The PPU and APU
+/// aren't emulated yet, so they aren't saved either; adding them will bump the version.
+pub const VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, snafu::Snafu)]
+pub enum Error {
+    #[snafu(display("invalid magic bytes"))]
+    InvalidMagicBytes,
+    #[snafu(display("state is version {version}, but only version {VERSION} is supported"))]
+    UnsupportedVersion { version: u16 },
+    #[snafu(display("state is for the ROM with CRC32 {expected:08X}, not {actual:08X}"))]
+    WrongRom { expected: u32, actual: u32 },
+    #[snafu(display("unexpected end of state"))]
+    UnexpectedEOI,
+    #[snafu(display("state needs {needed} bytes, but the buffer only has {actual}"))]
+    BufferTooSmall { needed: usize, actual: usize },
+    #[snafu(display("state is corrupt"))]
+    Corrupt,
+}
+impl From<untrusted::EndOfInput> for Error {
+    fn from(_: untrusted::EndOfInput) -> Self {
+        Self::UnexpectedEOI
+    }
+}
+
+/// A component that can be saved to, and restored from, a save state.
+///
+/// A state only has what changes while the machine runs; anything that comes from the ROM is
+/// left out, since states are only loaded into machines running the same ROM.
+pub trait State {
+    fn save(&self, w: &mut Writer);
+
+    /// Restores the component from a state written by [`Self::save`].
+    ///
+    /// # Errors
+    /// Errors if the state ends early or holds an impossible value. The component may be
+    /// partly restored when that happens.
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error>;
+}
+
+/// Implements [`State`] for a struct by saving and loading the given fields in order.
+///
+/// Fields that index into arrays can be checked after loading, so a crafted state can't make
+/// the emulator panic: `fields!(a, b; valid |m| usize::from(m.a) < 8)`.
+macro_rules! fields {
+    ($($field:ident),* $(,)? $(; valid |$m:ident| $valid:expr)?) => {
+        fn save(&self, w: &mut $crate::state::Writer) {
+            $($crate::state::State::save(&self.$field, w);)*
+        }
+
+        fn load(
+            &mut self,
+            r: &mut $crate::state::Reader,
+        ) -> Result<(), $crate::state::Error> {
+            $($crate::state::State::load(&mut self.$field, r)?;)*
+            $(
+                let $m = &*self;
+                if !$valid {
+                    return Err($crate::state::Error::Corrupt);
+                }
+            )?
+            Ok(())
+        }
+    };
+}
+pub(crate) use fields;
+
+/// Writes a state into a buffer. Writing past the end of the buffer only counts the bytes, so
+/// the size of a state can be found by writing it into an empty one.
+#[derive(Debug)]
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub const fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// The number of bytes written, including any that didn't fit.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.pos
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        let end = self.pos + bytes.len();
+        if let Some(dest) = self.buf.get_mut(self.pos..end) {
+            dest.copy_from_slice(bytes);
+        }
+        self.pos = end;
+    }
+}
+
+/// Reads a state written by a [`Writer`].
+#[derive(Debug)]
+pub struct Reader<'a>(untrusted::Reader<'a>);
+
+impl<'a> Reader<'a> {
+    #[must_use]
+    pub fn new(state: &'a [u8]) -> Self {
+        Self(untrusted::Reader::new(untrusted::Input::from(state)))
+    }
+
+    /// Fills `out` with the next bytes of the state.
+    ///
+    /// # Errors
+    /// Errors if there aren't enough bytes left.
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        out.copy_from_slice(self.0.read_bytes(out.len())?.as_slice_less_safe());
+        Ok(())
+    }
+
+    /// Reads an array of bytes.
+    ///
+    /// # Errors
+    /// Errors if there aren't enough bytes left.
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut out = [0; N];
+        self.bytes(&mut out)?;
+        Ok(out)
+    }
+
+    #[must_use]
+    pub fn at_end(&self) -> bool {
+        self.0.at_end()
+    }
+}
+
+macro_rules! numbers {
+    ($($ty:ty),*) => {$(
+        impl State for $ty {
+            fn save(&self, w: &mut Writer) {
+                w.bytes(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+                *self = Self::from_le_bytes(r.array()?);
+                Ok(())
+            }
+        }
+    )*};
+}
+numbers!(u8, u16, u32, u64, i8, i16, i32);
+
+// saved as a u64, so states don't depend on the platform
+impl State for usize {
+    fn save(&self, w: &mut Writer) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = Self::try_from(u64::from_le_bytes(r.array()?)).map_err(|_| Error::Corrupt)?;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, w: &mut Writer) {
+        u8::from(*self).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.array()? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(Error::Corrupt),
+        };
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, w: &mut Writer) {
+        for item in self {
+            item.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.iter_mut().try_for_each(|item| item.load(r))
+    }
+}
+
+// always the same size, so the size of a state doesn't change as the machine runs
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, w: &mut Writer) {
+        self.is_some().save(w);
+        match self {
+            Some(val) => val.save(w),
+            None => T::default().save(w),
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut some = false;
+        some.load(r)?;
+        let mut val = T::default();
+        val.load(r)?;
+        *self = some.then_some(val);
+        Ok(())
+    }
+}
+
+impl<T: State + Copy> State for Cell<T> {
+    fn save(&self, w: &mut Writer) {
+        self.get().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.get_mut().load(r)
+    }
+}
+
+impl State for Mirroring {
+    fn save(&self, w: &mut Writer) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.array()? {
+            [0] => Self::Vertical,
+            [1] => Self::Horizontal,
+            [2] => Self::FourScreen,
+            [3] => Self::SingleScreenLower,
+            [4] => Self::SingleScreenUpper,
+            _ => return Err(Error::Corrupt),
+        };
+        Ok(())
+    }
+}
+
+impl Cpu<'_> {
+    /// The size of this machine's save state, in bytes.
+    #[must_use]
+    pub fn state_size(&self) -> usize {
+        let mut w = Writer::new(&mut []);
+        State::save(self, &mut w);
+        HEADER_SIZE + w.len()
+    }
+
+    /// Saves the whole machine to `out`. Returns the size of the state; see
+    /// [`Self::state_size`].
+    ///
+    /// # Errors
+    /// Errors if `out` is too small.
+    pub fn save_state(&self, out: &mut [u8]) -> Result<usize, Error> {
+        let actual = out.len();
+        let (header, body) = out.split_at_mut(HEADER_SIZE.min(actual));
+        let mut w = Writer::new(body);
+        State::save(self, &mut w);
+
+        let needed = HEADER_SIZE + w.len();
+        if actual < needed {
+            return Err(Error::BufferTooSmall { needed, actual });
+        }
+        let body = &body[..needed - HEADER_SIZE];
+
+        let mut w = Writer::new(header);
+        w.bytes(&MAGIC);
+        VERSION.save(&mut w);
+        self.bus.rom.crc32().save(&mut w);
+        u32::try_from(body.len())
+            .map_err(|_| Error::Corrupt)?
+            .save(&mut w);
+        crc32(&[body]).save(&mut w);
+        Ok(needed)
+    }
+
+    /// Restores the whole machine from a state written by [`Self::save_state`].
+    ///
+    /// The header is checked before anything is restored, so states from other ROMs, other
+    /// versions, or that have been corrupted, leave the machine as it was.
+    ///
+    /// # Errors
+    /// Errors if the state is from another ROM or version, or is corrupt.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Error> {
+        let mut r = Reader::new(state);
+        if r.array()? != MAGIC {
+            return Err(Error::InvalidMagicBytes);
+        }
+        let version = u16::from_le_bytes(r.array()?);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        let (expected, actual) = (u32::from_le_bytes(r.array()?), self.bus.rom.crc32());
+        if expected != actual {
+            return Err(Error::WrongRom { expected, actual });
+        }
+        let size = u32::from_le_bytes(r.array()?);
+        let body_crc = u32::from_le_bytes(r.array()?);
+
+        let body = &state[HEADER_SIZE..];
+        let size_matches = usize::try_from(size).is_ok_and(|size| size == body.len());
+        if !size_matches || self.state_size() != state.len() || crc32(&[body]) != body_crc {
+            return Err(Error::Corrupt);
+        }
+
+        let mut r = Reader::new(body);
+        State::load(self, &mut r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bus::Bus, mapper::Mapper, rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn numbers() {
+        let mut buf = [0; 16];
+        let mut w = Writer::new(&mut buf);
+        0x1234_u16.save(&mut w);
+        true.save(&mut w);
+        Some(-2_i8).save(&mut w);
+        None::<u8>.save(&mut w);
+        7_usize.save(&mut w);
+        assert_eq!(w.len(), 15);
+        assert_eq!(buf[..7], [0x34, 0x12, 1, 1, 0xFE, 0, 0]);
+
+        let mut r = Reader::new(&buf);
+        let (mut word, mut flag, mut some, mut none, mut size) =
+            (0_u16, false, None::<i8>, Some(1_u8), 0_usize);
+        word.load(&mut r).unwrap();
+        flag.load(&mut r).unwrap();
+        some.load(&mut r).unwrap();
+        none.load(&mut r).unwrap();
+        size.load(&mut r).unwrap();
+        assert_eq!(
+            (word, flag, some, none, size),
+            (0x1234, true, Some(-2), None, 7)
+        );
+
+        assert_eq!(false.load(&mut Reader::new(&[2])), Err(Error::Corrupt));
+        assert_eq!(
+            0_u32.load(&mut Reader::new(&[0; 3])),
+            Err(Error::UnexpectedEOI)
+        );
+
+        let mut w = Writer::new(&mut []);
+        0_u64.save(&mut w);
+        assert_eq!(w.len(), 8);
+    }
+
+    #[test]
+    #[allow(clippy::large_stack_frames)] // two buses, which are meant to live on the stack
+    fn save_load() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.reg_x = 3;
+        cpu.bus.mem_write(0x0010, 0x55);
+        cpu.bus.mem_write(0x6000, 0x66);
+        cpu.bus.tick(5);
+
+        let mut state = vec![0; cpu.state_size()];
+        assert_eq!(cpu.save_state(&mut state), Ok(state.len()));
+        assert_eq!(
+            cpu.save_state(&mut [0; HEADER_SIZE]),
+            Err(Error::BufferTooSmall {
+                needed: state.len(),
+                actual: HEADER_SIZE
+            })
+        );
+
+        let mut other = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        other.load_state(&state).unwrap();
+        assert_eq!(other.reg_x, 3);
+        assert_eq!(other.bus.mem_read(0x0010), 0x55);
+        assert_eq!(other.bus.mem_read(0x6000), 0x66);
+        assert_eq!(other.bus.cycles(), 5);
+    }
+
+    #[test]
+    #[allow(clippy::large_stack_frames)] // two buses, which are meant to live on the stack
+    fn mapper() {
+        let raw = crate::rom::nsf::test::nsf(0x8000, 0x8000, &[0; 0x2000]);
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.bus.mem_write(0x5FF9, 0); // bank 0 at $9000
+        cpu.bus.mem_write(0xF800, 0x80); // N163 RAM, auto-incrementing from 0
+        cpu.bus.mem_write(0x4800, 0x12);
+        cpu.bus.mem_write(0x4800, 0x34);
+
+        let mut state = vec![0; cpu.state_size()];
+        cpu.save_state(&mut state).unwrap();
+
+        let mut other = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        other.load_state(&state).unwrap();
+        assert_eq!(other.bus.mem_read(0x4800), 0x00); // the address was saved too
+        other.bus.mem_write(0xF800, 0x81);
+        assert_eq!(other.bus.mem_read(0x4800), 0x34);
+        assert_eq!(
+            other.bus.mapper.prg_addr(0x9000),
+            cpu.bus.mapper.prg_addr(0x9000)
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        let mut state = vec![0; cpu.state_size()];
+        cpu.save_state(&mut state).unwrap();
+        cpu.reg_a = 1;
+
+        let mut bad = state.clone();
+        bad[0] = b'X';
+        assert_eq!(cpu.load_state(&bad), Err(Error::InvalidMagicBytes));
+
+        let mut bad = state.clone();
+        bad[4] = 0xFF;
+        assert_eq!(
+            cpu.load_state(&bad),
+            Err(Error::UnsupportedVersion { version: 0x00FF })
+        );
+
+        let mut bad = state.clone();
+        bad[6] ^= 1;
+        assert!(matches!(cpu.load_state(&bad), Err(Error::WrongRom { .. })));
+
+        let mut bad = state.clone();
+        bad[HEADER_SIZE] ^= 1;
+        assert_eq!(cpu.load_state(&bad), Err(Error::Corrupt));
+        assert_eq!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(Error::Corrupt)
+        );
+        assert_eq!(cpu.load_state(&state[..3]), Err(Error::UnexpectedEOI));
+        assert_eq!(cpu.reg_a, 1);
+    }
+}
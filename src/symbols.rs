@@ -0,0 +1,584 @@
+//! Debug symbols: the labels, source lines and scopes of a program's addresses, loaded from the
+//! debug info ld65 writes with `--dbgfile` or from FCEUX's `.nl` label files.
+//!
+//! Addresses in PRG ROM are kept as offsets into it, so a label in one bank isn't shown for
+//! another bank that's switched into the same addresses. Looking them up
+//! [on a bus](Symbols::on) uses the banks it has switched in; looking them up on the
+//! [`Symbols`] themselves takes the first label for the address in any bank.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, cpu::Cpu, rom::Rom};
+//! use fete::{disasm::Instruction, symbols::Symbols};
+//!
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//! cpu.load(&[0x20, 0xF5, 0xC5]);
+//!
+//! let mut symbols = Symbols::default();
+//! symbols
+//!     .load_nl("game.nes.1.nl", "$C5F5#UpdatePlayer#moves the player\n")
+//!     .unwrap();
+//!
+//! let jsr = Instruction::read(&cpu.bus, 0x0600);
+//! let labels = symbols.on(&cpu.bus);
+//! assert_eq!(jsr.to_string(), "jsr $C5F5");
+//! assert_eq!(
+//!     jsr.display(Default::default()).labels(&labels).to_string(),
+//!     "jsr UpdatePlayer"
+//! );
+//! ```
+
+use crate::{bus::Bus, disasm::Labels};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use snafu::prelude::*;
+
+/// The size of the banks in FCEUX's `.nl` files.
+const NL_BANK_SIZE: usize = 0x4000;
+/// The size of the iNES header, which ld65's output offsets include.
+const INES_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Snafu)]
+pub enum Error {
+    #[snafu(display("syntax error on line {line}"))]
+    Syntax { line: usize },
+    #[snafu(display("line {line} refers to a {kind} that doesn't exist"))]
+    Missing { line: usize, kind: &'static str },
+    #[snafu(display("label files must be named `*.ram.nl` or `*.<bank>.nl`"))]
+    FileName,
+}
+
+/// Where a symbol is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Location {
+    /// An address on the CPU's bus outside PRG ROM: RAM, PRG RAM or a register.
+    Ram(u16),
+    /// An offset into PRG ROM.
+    Rom(usize),
+}
+
+impl Location {
+    /// Where an address is, with the banks the bus has switched in.
+    #[must_use]
+    pub fn on(bus: &Bus, addr: u16) -> Self {
+        bus.prg_offset(addr).map_or(Self::Ram(addr), Self::Rom)
+    }
+
+    /// Whether `other` is within `size` bytes from here.
+    fn contains(self, size: usize, other: Self) -> bool {
+        match (self, other) {
+            (Self::Ram(start), Self::Ram(addr)) => {
+                usize::from(addr).wrapping_sub(start.into()) < size
+            }
+            (Self::Rom(start), Self::Rom(offset)) => offset.wrapping_sub(start) < size,
+            _ => false,
+        }
+    }
+
+    const fn offset(self, by: usize) -> Self {
+        match self {
+            #[allow(clippy::cast_possible_truncation)] // addresses wrap around
+            Self::Ram(addr) => Self::Ram(addr.wrapping_add(by as u16)),
+            Self::Rom(offset) => Self::Rom(offset + by),
+        }
+    }
+}
+
+/// A line of source code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine<'a> {
+    pub file: &'a str,
+    pub line: u32,
+}
+
+impl fmt::Display for SourceLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineSpan {
+    file: usize,
+    line: u32,
+    size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Scope {
+    start: Location,
+    size: usize,
+    name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<Location, String>,
+    /// The location of the first label at each address, for looking up without a bus.
+    addrs: BTreeMap<u16, Location>,
+    /// Where each line's code starts.
+    lines: BTreeMap<Location, LineSpan>,
+    files: Vec<String>,
+    scopes: Vec<Scope>,
+}
+
+impl Symbols {
+    /// Adds a label, unless there's already one at the location.
+    pub fn add_label(&mut self, addr: u16, location: Location, name: &str) {
+        self.labels
+            .entry(location)
+            .or_insert_with(|| name.to_string());
+        self.addrs.entry(addr).or_insert(location);
+    }
+
+    /// The label at a location.
+    #[must_use]
+    pub fn label(&self, location: Location) -> Option<&str> {
+        self.labels.get(&location).map(String::as_str)
+    }
+
+    /// The line of source code that assembled to a location.
+    #[must_use]
+    pub fn line(&self, location: Location) -> Option<SourceLine<'_>> {
+        let (&start, line) = self.lines.range(..=location).next_back()?;
+        start.contains(line.size, location).then(|| SourceLine {
+            file: &self.files[line.file],
+            line: line.line,
+        })
+    }
+
+    /// The name of the innermost scope, like a `.proc`, around a location.
+    #[must_use]
+    pub fn scope(&self, location: Location) -> Option<&str> {
+        self.scopes
+            .iter()
+            .filter(|scope| scope.start.contains(scope.size, location))
+            .min_by_key(|scope| scope.size)
+            .map(|scope| scope.name.as_str())
+    }
+
+    /// Looks up labels with the banks the bus has switched in.
+    #[must_use]
+    pub const fn on<'a, 'rom>(&'a self, bus: &'a Bus<'rom>) -> OnBus<'a, 'rom> {
+        OnBus { symbols: self, bus }
+    }
+
+    /// Loads the debug info ld65 writes with `--dbgfile`, for a program linked into an iNES
+    /// file.
+    ///
+    /// Only labels are loaded from the symbols, not constants.
+    ///
+    /// # Errors
+    /// Errors if the file is malformed.
+    pub fn load_dbg(&mut self, text: &str) -> Result<(), Error> {
+        let mut dbg = Dbg::default();
+        for (i, record) in text.lines().enumerate() {
+            dbg.parse(i + 1, record)?;
+        }
+
+        // where each file's name will be in `self.files`
+        let files: BTreeMap<usize, usize> = dbg
+            .files
+            .keys()
+            .enumerate()
+            .map(|(i, &id)| (id, self.files.len() + i))
+            .collect();
+        self.files
+            .extend(dbg.files.values().map(ToString::to_string));
+        let span = |line, id| {
+            dbg.spans
+                .get(&id)
+                .context(MissingSnafu { line, kind: "span" })
+        };
+
+        for (line, record) in dbg.lines {
+            let &file = files
+                .get(&record.file)
+                .context(MissingSnafu { line, kind: "file" })?;
+            for id in record.spans {
+                let &(start, size) = span(line, id)?;
+                self.lines.insert(
+                    start,
+                    LineSpan {
+                        file,
+                        line: record.line,
+                        size,
+                    },
+                );
+            }
+        }
+
+        for (line, name, spans) in dbg.scopes {
+            for id in spans {
+                let &(start, size) = span(line, id)?;
+                self.scopes.push(Scope {
+                    start,
+                    size,
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        for (line, name, val, seg) in dbg.syms {
+            let location = match seg {
+                Some(id) => {
+                    let seg = dbg.segs.get(&id).context(MissingSnafu {
+                        line,
+                        kind: "segment",
+                    })?;
+                    seg.location(0, val).context(SyntaxSnafu { line })?
+                }
+                None => Location::Ram(val),
+            };
+            self.add_label(val, location, name);
+        }
+        Ok(())
+    }
+
+    /// Loads one of FCEUX's label files, which are named after the ROM: `game.nes.ram.nl` for
+    /// RAM, and `game.nes.0.nl`, `game.nes.1.nl` and so on for each 16 KiB bank of PRG ROM,
+    /// numbered in hex.
+    ///
+    /// # Errors
+    /// Errors if the file name isn't one of those, or if the file is malformed.
+    pub fn load_nl(&mut self, name: &str, text: &str) -> Result<(), Error> {
+        let bank = name
+            .strip_suffix(".nl")
+            .and_then(|name| name.rsplit_once('.'))
+            .context(FileNameSnafu)?
+            .1;
+        let bank = if bank.eq_ignore_ascii_case("ram") {
+            None
+        } else {
+            Some(usize::from_str_radix(bank, 16).map_err(|_| Error::FileName)?)
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line_num = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // `$C000#Label#Comment`, where the address can have a size: `$0300/10`
+            let mut fields = line.trim_end().splitn(3, '#');
+            let addr = fields
+                .next()
+                .and_then(|addr| addr.strip_prefix('$'))
+                .map(|addr| addr.split_once('/').map_or(addr, |(addr, _)| addr))
+                .and_then(|addr| u16::from_str_radix(addr, 16).ok())
+                .context(SyntaxSnafu { line: line_num })?;
+            let label = fields.next().context(SyntaxSnafu { line: line_num })?;
+            if label.is_empty() {
+                continue;
+            }
+
+            let location = bank.map_or(Location::Ram(addr), |bank| {
+                Location::Rom(bank * NL_BANK_SIZE + usize::from(addr) % NL_BANK_SIZE)
+            });
+            self.add_label(addr, location, label);
+        }
+        Ok(())
+    }
+}
+
+impl Labels for Symbols {
+    fn label(&self, addr: u16) -> Option<&str> {
+        self.label(*self.addrs.get(&addr)?)
+    }
+}
+
+/// [`Symbols`] looked up with the banks a bus has switched in; see [`Symbols::on`].
+#[derive(Clone, Copy)]
+pub struct OnBus<'a, 'rom> {
+    symbols: &'a Symbols,
+    bus: &'a Bus<'rom>,
+}
+
+impl OnBus<'_, '_> {
+    /// The line of source code at an address.
+    #[must_use]
+    pub fn line(&self, addr: u16) -> Option<SourceLine<'_>> {
+        self.symbols.line(Location::on(self.bus, addr))
+    }
+
+    /// The innermost scope around an address.
+    #[must_use]
+    pub fn scope(&self, addr: u16) -> Option<&str> {
+        self.symbols.scope(Location::on(self.bus, addr))
+    }
+}
+
+impl Labels for OnBus<'_, '_> {
+    fn label(&self, addr: u16) -> Option<&str> {
+        self.symbols.label(Location::on(self.bus, addr))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Seg {
+    start: u16,
+    /// Where the segment is in the output file, if it's written to it.
+    ooffs: Option<usize>,
+}
+
+impl Seg {
+    /// Where something `offset` bytes into the segment, at `addr`, is.
+    fn location(self, offset: usize, addr: u16) -> Option<Location> {
+        let offset = offset + usize::from(addr.checked_sub(self.start)?);
+        Some(match self.ooffs {
+            Some(ooffs) => Location::Rom(ooffs.checked_sub(INES_HEADER_SIZE)? + offset),
+            None => Location::Ram(self.start).offset(offset),
+        })
+    }
+}
+
+struct DbgLine {
+    file: usize,
+    line: u32,
+    spans: Vec<usize>,
+}
+
+/// The records of a debug info file, which can refer to each other in any order, before
+/// they're resolved. Each is kept with the line it's on, for errors.
+#[derive(Default)]
+struct Dbg<'a> {
+    files: BTreeMap<usize, &'a str>,
+    segs: BTreeMap<usize, Seg>,
+    /// The location and size of each span.
+    spans: BTreeMap<usize, (Location, usize)>,
+    lines: Vec<(usize, DbgLine)>,
+    scopes: Vec<(usize, &'a str, Vec<usize>)>,
+    syms: Vec<(usize, &'a str, u16, Option<usize>)>,
+    /// Spans that were read before their segment.
+    pending_spans: Vec<(usize, usize, usize, usize, usize)>,
+}
+
+impl<'a> Dbg<'a> {
+    fn parse(&mut self, line: usize, record: &'a str) -> Result<(), Error> {
+        let Some((kind, fields)) = record.split_once(char::is_whitespace) else {
+            return Ok(());
+        };
+        let fields = Fields(fields.trim());
+        let syntax = || Error::Syntax { line };
+        let num = |key| fields.num(key).ok_or_else(syntax);
+        let spans = || {
+            fields.get("span").map_or_else(
+                || Ok(Vec::new()),
+                |spans| {
+                    spans
+                        .split('+')
+                        .map(|id| parse_num(id).ok_or_else(syntax))
+                        .collect()
+                },
+            )
+        };
+
+        match kind {
+            "file" => {
+                let id = num("id")?;
+                self.files
+                    .insert(id, fields.get("name").ok_or_else(syntax)?);
+            }
+            "seg" => {
+                let seg = Seg {
+                    start: u16::try_from(num("start")?).map_err(|_| syntax())?,
+                    ooffs: fields.num("ooffs"),
+                };
+                let id = num("id")?;
+                self.segs.insert(id, seg);
+                self.resolve_spans();
+            }
+            "span" => {
+                self.pending_spans.push((
+                    line,
+                    num("id")?,
+                    num("seg")?,
+                    num("start")?,
+                    num("size")?,
+                ));
+                self.resolve_spans();
+            }
+            // macro expansions are shown as the line that invoked them
+            "line" if fields.num("type") == Some(2) => {}
+            "line" => self.lines.push((
+                line,
+                DbgLine {
+                    file: num("file")?,
+                    line: u32::try_from(num("line")?).map_err(|_| syntax())?,
+                    spans: spans()?,
+                },
+            )),
+            "scope" => {
+                let name = fields.get("name").ok_or_else(syntax)?;
+                if !name.is_empty() {
+                    self.scopes.push((line, name, spans()?));
+                }
+            }
+            "sym" if fields.get("type") == Some("lab") => {
+                let val = u16::try_from(num("val")?).map_err(|_| syntax())?;
+                self.syms.push((
+                    line,
+                    fields.get("name").ok_or_else(syntax)?,
+                    val,
+                    fields.num("seg"),
+                ));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn resolve_spans(&mut self) {
+        self.pending_spans.retain(|&(_, id, seg, start, size)| {
+            let Some(seg) = self.segs.get(&seg) else {
+                return true;
+            };
+            if let Some(location) = seg.location(start, seg.start) {
+                self.spans.insert(id, (location, size));
+            }
+            false
+        });
+    }
+}
+
+/// The `key=value` fields of a debug info record, separated by commas.
+#[derive(Clone, Copy)]
+struct Fields<'a>(&'a str);
+
+impl<'a> Fields<'a> {
+    fn get(self, key: &str) -> Option<&'a str> {
+        let mut rest = self.0;
+        while !rest.is_empty() {
+            let (k, after) = rest.split_once('=')?;
+            // quoted values can have commas in them
+            let (val, after) = if let Some(quoted) = after.strip_prefix('"') {
+                let (val, after) = quoted.split_once('"')?;
+                (val, after.strip_prefix(',').unwrap_or(after))
+            } else {
+                after.split_once(',').unwrap_or((after, ""))
+            };
+            if k == key {
+                return Some(val);
+            }
+            rest = after;
+        }
+        None
+    }
+
+    fn num(self, key: &str) -> Option<usize> {
+        parse_num(self.get(key)?)
+    }
+}
+
+/// Parses a number in decimal, or in hex after `0x`.
+fn parse_num(s: &str) -> Option<usize> {
+    s.strip_prefix("0x")
+        .map_or_else(|| s.parse().ok(), |hex| usize::from_str_radix(hex, 16).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        disasm::{disassemble, Syntax},
+        rom::Rom,
+        testing::test_rom,
+    };
+    use pretty_assertions::assert_eq;
+    use std::string::ToString;
+
+    const DBG: &str = r#"version	major=2,minor=0
+info	csym=0,file=1,lib=0,line=3,mod=1,scope=2,seg=2,span=4,sym=3,type=4
+file	id=0,name="src/main, with a comma.s",size=300,mtime=0x5A0B1C2D,mod=0
+line	id=0,file=0,line=10,span=0
+line	id=1,file=0,line=12,span=1
+line	id=2,file=0,line=40,type=2,span=1
+mod	id=0,name="main.o",file=0
+seg	id=0,name="CODE",start=0x00C000,size=0x0010,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+seg	id=1,name="BSS",start=0x000300,size=0x0010,addrsize=absolute,type=rw
+span	id=0,seg=0,start=0,size=3
+span	id=1,seg=0,start=5,size=2
+span	id=2,seg=0,start=0,size=16
+span	id=3,seg=0,start=5,size=4
+scope	id=0,name="",mod=0,size=16,span=2
+scope	id=1,name="UpdatePlayer",mod=0,type=scope,size=4,parent=0,span=3
+sym	id=0,name="Reset",addrsize=absolute,scope=0,def=0,val=0xC000,seg=0,type=lab
+sym	id=1,name="UpdatePlayer",addrsize=absolute,size=4,scope=0,def=1,val=0xC005,seg=0,type=lab
+sym	id=2,name="PPUCTRL",addrsize=absolute,scope=0,def=2,val=0x2000,type=equ
+sym	id=3,name="player_x",addrsize=absolute,scope=0,def=2,val=0x0302,seg=1,type=lab
+"#;
+
+    #[test]
+    fn dbg() {
+        let mut symbols = Symbols::default();
+        symbols.load_dbg(DBG).unwrap();
+
+        assert_eq!(symbols.label(Location::Rom(0)), Some("Reset"));
+        assert_eq!(symbols.label(Location::Rom(5)), Some("UpdatePlayer"));
+        assert_eq!(symbols.label(Location::Ram(0x0302)), Some("player_x"));
+        assert_eq!(Labels::label(&symbols, 0x2000), None);
+
+        let line = |offset| symbols.line(Location::Rom(offset)).map(|l| l.to_string());
+        assert_eq!(line(1).as_deref(), Some("src/main, with a comma.s:10"));
+        assert_eq!(line(3), None);
+        assert_eq!(line(6).as_deref(), Some("src/main, with a comma.s:12"));
+
+        assert_eq!(symbols.scope(Location::Rom(6)), Some("UpdatePlayer"));
+        assert_eq!(symbols.scope(Location::Rom(2)), None);
+
+        let code = [0x20, 0x05, 0xC0, 0x8D, 0x02, 0x03];
+        let lines: Vec<_> = disassemble(0xC000, &code)
+            .map(|i| i.display(Syntax::Ca65).labels(&symbols).to_string())
+            .collect();
+        assert_eq!(lines, ["jsr UpdatePlayer", "sta player_x"]);
+
+        assert_eq!(
+            Symbols::default()
+                .load_dbg("span\tid=0,seg=0,start=0,size=3\nscope\tid=0,name=\"a\",span=0"),
+            Err(Error::Missing {
+                line: 2,
+                kind: "span"
+            })
+        );
+        assert_eq!(
+            Symbols::default().load_dbg("file\tid=0,name=\"a.s\"\nline\tid=0,file=0,line=x"),
+            Err(Error::Syntax { line: 2 })
+        );
+    }
+
+    #[test]
+    fn nl() {
+        let mut symbols = Symbols::default();
+        symbols
+            .load_nl("game.nes.ram.nl", "$0300/10#buffer#\n$0310##no label\n")
+            .unwrap();
+        symbols.load_nl("game.nes.0.nl", "$C005#Title#").unwrap();
+        symbols
+            .load_nl("game.nes.1.nl", "$C005#UpdatePlayer#moves the player\n\n")
+            .unwrap();
+
+        assert_eq!(symbols.label(Location::Ram(0x0300)), Some("buffer"));
+        assert_eq!(symbols.label(Location::Ram(0x0310)), None);
+        assert_eq!(symbols.label(Location::Rom(0x0005)), Some("Title"));
+        assert_eq!(symbols.label(Location::Rom(0x4005)), Some("UpdatePlayer"));
+
+        // the test ROM has bank 1 at $C000
+        let raw = test_rom();
+        let bus = Bus::new(Rom::new(&raw).unwrap());
+        let on = symbols.on(&bus);
+        assert_eq!(on.label(0x0300), Some("buffer"));
+        assert_eq!(on.label(0xC005), Some("UpdatePlayer"));
+        assert_eq!(Labels::label(&symbols, 0xC005), Some("Title"));
+
+        assert_eq!(symbols.load_nl("game.nes", ""), Err(Error::FileName));
+        assert_eq!(symbols.load_nl("game.nes.x.nl", ""), Err(Error::FileName));
+        assert_eq!(
+            symbols.load_nl("game.nes.0.nl", "C000#Reset#"),
+            Err(Error::Syntax { line: 1 })
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Traces each instruction the CPU runs to pluggable [`Sink`]s, as lines in the format of
+//! nestest's, FCEUX's or Mesen's logs (see [`Format`]).
+//!
+//! A [`Tracer`] wraps [`Cpu::tick`], and sends a [`TraceOp`] to its sink before each
+//! instruction that passes its [`Filter`]. The sinks here are:
+//! - [`Ring`], which keeps the last `N` lines in a fixed-size buffer, and dumps them to the log
+//!   when the CPU errors, like on an invalid opcode;
+//! - [`Stream`], which writes each line to a [`core::fmt::Write`], and with the `std` feature
+//!   [`IoStream`], which writes to a [`std::io::Write`];
+//! - [`Log`], which sends each line to [`log::trace!`].
+//!
+//! Sinks can be combined as a tuple, which sends each line to both.
+//!
+//! # Examples
+//! ```
+//! # use fete::{bus::Bus, rom::Rom};
+//! use fete::{
+//!     asm,
+//!     cpu::{trace::Format, Cpu},
+//!     tracer::{Filter, Ring, Tracer},
+//! };
+//!
+//! # let raw = fete::testing::test_rom();
+//! let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+//! cpu.load(&asm!("LDA #$01\nLDX #$02\nLDY #$03\nBRK"));
+//! cpu.pc = 0x0600;
+//!
+//! let mut tracer = Tracer::new(Ring::<2>::new())
+//!     .with_filter(Filter::default().pc(0x0602..0x0700))
+//!     .with_format(Format::Fceux);
+//! while !tracer.tick(&mut cpu).unwrap() {}
+//!
+//! let lines: Vec<_> = tracer.sink.lines().collect();
+//! assert_eq!(lines.len(), 2);
+//! assert!(
+//!     lines[0].ends_with("$0604:A0 03     LDY #$03"),
+//!     "{}",
+//!     lines[0]
+//! );
+//! assert!(lines[1].ends_with("$0606:00        BRK"), "{}", lines[1]);
+//! ```
+
+use crate::{
+    cpu::{
+        self,
+        trace::{Format, TraceOp},
+        Cpu,
+    },
+    disasm::Labels,
+};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+};
+
+/// The longest line a [`Ring`] keeps; longer ones are cut short.
+pub const LINE_LEN: usize = 160;
+
+/// Somewhere to send traced instructions.
+pub trait Sink {
+    /// Takes the line for an instruction, before it runs.
+    fn trace(&mut self, line: &TraceOp);
+
+    /// Called when the CPU errors, after the line for the last instruction that ran.
+    fn error(&mut self, _err: cpu::Error) {}
+}
+
+impl<A: Sink, B: Sink> Sink for (A, B) {
+    fn trace(&mut self, line: &TraceOp) {
+        self.0.trace(line);
+        self.1.trace(line);
+    }
+
+    fn error(&mut self, err: cpu::Error) {
+        self.0.error(err);
+        self.1.error(err);
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn trace(&mut self, line: &TraceOp) {
+        (**self).trace(line);
+    }
+
+    fn error(&mut self, err: cpu::Error) {
+        (**self).error(err);
+    }
+}
+
+/// Keeps the last `N` lines, without allocating.
+#[derive(Debug, Clone)]
+pub struct Ring<const N: usize> {
+    lines: [Line; N],
+    /// Where the next line goes, which is the oldest one once it's full.
+    next: usize,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl Line {
+    const EMPTY: Self = Self {
+        buf: [0; LINE_LEN],
+        len: 0,
+    };
+
+    fn as_str(&self) -> &str {
+        // only ever cut on char boundaries
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = LINE_LEN - self.len;
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+impl<const N: usize> Ring<N> {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            lines: [Line::EMPTY; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// The lines it's kept, oldest first.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        let start = (self.next + N - self.len) % N.max(1);
+        (0..self.len).map(move |i| self.lines[(start + i) % N].as_str())
+    }
+
+    pub const fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Writes the lines it's kept, oldest first, one per line.
+    ///
+    /// # Errors
+    /// Errors if `out` does.
+    pub fn dump(&self, out: &mut impl Write) -> fmt::Result {
+        self.lines().try_for_each(|line| writeln!(out, "{line}"))
+    }
+}
+
+impl<const N: usize> Default for Ring<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Sink for Ring<N> {
+    fn trace(&mut self, line: &TraceOp) {
+        if N == 0 {
+            return;
+        }
+        let slot = &mut self.lines[self.next];
+        slot.len = 0;
+        // a `Line` never errors; it cuts the line short instead
+        let _ = write!(slot, "{line}");
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+
+    fn error(&mut self, err: cpu::Error) {
+        log::error!("{err}, after:");
+        for line in self.lines() {
+            log::error!("{line}");
+        }
+    }
+}
+
+/// Writes each line to a [`core::fmt::Write`], such as a `String`.
+///
+/// Errors writing are ignored, so a full or closed output doesn't stop the CPU.
+#[derive(Debug, Clone, Default)]
+pub struct Stream<W>(pub W);
+
+impl<W: Write> Sink for Stream<W> {
+    fn trace(&mut self, line: &TraceOp) {
+        let _ = writeln!(self.0, "{line}");
+    }
+}
+
+/// Writes each line to a [`std::io::Write`], such as a file.
+///
+/// Errors writing are ignored, so a full or closed output doesn't stop the CPU.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct IoStream<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Sink for IoStream<W> {
+    fn trace(&mut self, line: &TraceOp) {
+        let _ = writeln!(self.0, "{line}");
+    }
+}
+
+/// Sends each line to [`log::trace!`], and errors to [`log::error!`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Log;
+
+impl Sink for Log {
+    fn trace(&mut self, line: &TraceOp) {
+        log::trace!("{line}");
+    }
+
+    fn error(&mut self, err: cpu::Error) {
+        log::error!("{err}");
+    }
+}
+
+/// Which instructions are traced. Each part that's set has to match; the default traces
+/// everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter {
+    pc: Option<Range<u16>>,
+    /// The bank, and its size in bytes.
+    bank: Option<(usize, usize)>,
+    frames: Option<Range<u64>>,
+}
+
+impl Filter {
+    /// Only traces instructions at these addresses.
+    #[must_use]
+    pub const fn pc(mut self, range: Range<u16>) -> Self {
+        self.pc = Some(range);
+        self
+    }
+
+    /// Only traces instructions in a bank of PRG ROM, numbered in banks of `size` bytes from
+    /// the start of it, whichever window it's switched into.
+    #[must_use]
+    pub const fn bank(mut self, bank: usize, size: usize) -> Self {
+        self.bank = Some((bank, size));
+        self
+    }
+
+    /// Only traces instructions run during these frames, counted from power-on.
+    #[must_use]
+    pub const fn frames(mut self, range: Range<u64>) -> Self {
+        self.frames = Some(range);
+        self
+    }
+
+    /// Whether the instruction at the CPU's `pc` should be traced.
+    #[must_use]
+    pub fn matches(&self, cpu: &Cpu) -> bool {
+        self.pc.as_ref().map_or(true, |pc| pc.contains(&cpu.pc))
+            && self.bank.map_or(true, |(bank, size)| {
+                cpu.bus
+                    .prg_offset(cpu.pc)
+                    .is_some_and(|offset| offset / size.max(1) == bank)
+            })
+            && self.frames.as_ref().map_or(true, |frames| {
+                frames.contains(&cpu.bus.ppu_position().frame)
+            })
+    }
+}
+
+/// Runs the CPU, tracing instructions to a [`Sink`].
+pub struct Tracer<'a, S> {
+    pub sink: S,
+    pub filter: Filter,
+    pub format: Format,
+    labels: Option<&'a dyn Labels>,
+}
+
+impl<'a, S: Sink> Tracer<'a, S> {
+    /// Traces everything, in nestest's format.
+    #[must_use]
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            filter: Filter::default(),
+            format: Format::Nestest,
+            labels: None,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Writes operand addresses with labels as the labels.
+    #[must_use]
+    pub const fn with_labels(mut self, labels: &'a dyn Labels) -> Self {
+        self.labels = Some(labels);
+        self
+    }
+
+    /// Runs an instruction with [`Cpu::tick`], tracing it first if it passes the filter.
+    ///
+    /// # Errors
+    /// Errors if the CPU hits an invalid opcode, after passing the error to the sink.
+    pub fn tick(&mut self, cpu: &mut Cpu) -> Result<bool, cpu::Error> {
+        if self.filter.matches(cpu) {
+            if let Some(line) = TraceOp::new(cpu) {
+                let line = line.with_format(self.format);
+                let line = match self.labels {
+                    Some(labels) => line.with_labels(labels),
+                    None => line,
+                };
+                self.sink.trace(&line);
+            }
+        }
+        cpu.tick().inspect_err(|&err| self.sink.error(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{asm, bus::Bus, rom::Rom, testing::test_rom};
+    use pretty_assertions::assert_eq;
+    use std::{string::String, vec::Vec};
+
+    #[test]
+    fn ring() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.load(&asm!("LDA #1\nLDA #2\nLDA #3\n.byte $02"));
+        cpu.pc = 0x0600;
+
+        let mut tracer = Tracer::new((Ring::<2>::new(), Stream(String::new())));
+        let err = loop {
+            if let Err(err) = tracer.tick(&mut cpu) {
+                break err;
+            }
+        };
+        assert_eq!(
+            err,
+            cpu::Error::InvalidOpcode {
+                opcode: 0x02,
+                offset: 0x0606
+            }
+        );
+
+        let (ring, Stream(all)) = &tracer.sink;
+        let lines: Vec<_> = ring.lines().map(|line| &line[..20]).collect();
+        assert_eq!(lines, ["0602  A9 02     LDA ", "0604  A9 03     LDA "]);
+        assert_eq!(all.lines().count(), 3);
+
+        let mut dump = String::new();
+        ring.dump(&mut dump).unwrap();
+        assert_eq!(dump.lines().count(), 2);
+
+        let mut ring = ring.clone();
+        ring.clear();
+        assert_eq!(ring.lines().count(), 0);
+    }
+
+    #[test]
+    fn long_lines() {
+        let mut line = Line::EMPTY;
+        write!(line, "{}", "é".repeat(LINE_LEN)).unwrap();
+        assert_eq!(line.as_str(), "é".repeat(LINE_LEN / 2));
+    }
+
+    #[test]
+    fn filters() {
+        let raw = test_rom();
+        let mut cpu = Cpu::new(Bus::new(Rom::new(&raw).unwrap()));
+        cpu.pc = 0x8000;
+        assert!(Filter::default().matches(&cpu));
+        assert!(Filter::default().pc(0x8000..0x8001).matches(&cpu));
+        assert!(!Filter::default().pc(0x8001..0xFFFF).matches(&cpu));
+
+        // 16 KiB banks; $C000 is mirrored from the second one in the test ROM
+        assert!(Filter::default().bank(0, 0x4000).matches(&cpu));
+        assert!(!Filter::default().bank(1, 0x4000).matches(&cpu));
+        cpu.pc = 0xC000;
+        assert!(Filter::default().bank(1, 0x4000).matches(&cpu));
+        cpu.pc = 0x0600;
+        assert!(!Filter::default().bank(0, 0x4000).matches(&cpu));
+
+        assert!(Filter::default().frames(0..1).matches(&cpu));
+        // a frame is 29,780 and a bit cycles
+        for _ in 0..120 {
+            cpu.bus.tick(250);
+        }
+        assert!(!Filter::default().frames(0..1).matches(&cpu));
+        assert!(Filter::default()
+            .frames(1..2)
+            .pc(0x0600..0x0700)
+            .matches(&cpu));
+    }
+}
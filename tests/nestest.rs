@@ -8,8 +8,11 @@ use pretty_assertions::assert_eq;
 static NESTEST_ROM: &[u8] = include_bytes!("../tests/nestest/nestest.nes");
 static NESTICLE_LOG: &str = include_str!("../tests/nestest/nestest.log");
 
-#[test]
-fn cpu_test() {
+/// The lines of the log before nestest starts on unofficial opcodes, which aren't implemented
+/// yet.
+const OFFICIAL_LINES: usize = 5003;
+
+fn run(lines: usize) {
     let rom = Rom::new(NESTEST_ROM).unwrap();
     let bus = Bus::new(rom);
 
@@ -17,10 +20,10 @@ fn cpu_test() {
 
     cpu.status = Status::INTERRUPT_DISABLE | Status::BREAK2;
     cpu.pc = 0xC000;
+    // the reset sequence, which nestest's log starts after
+    cpu.bus.tick(7);
 
-    for line in NESTICLE_LOG.lines().map(|l| l.split_at(73).0) {
-        // TODO: CPU cycles.
-
+    for line in NESTICLE_LOG.lines().take(lines) {
         let trace = TraceOp::new(&cpu).unwrap().to_string();
 
         assert_eq!(trace, line);
@@ -29,3 +32,14 @@ fn cpu_test() {
         }
     }
 }
+
+#[test]
+fn cpu_test() {
+    run(OFFICIAL_LINES);
+}
+
+#[test]
+#[ignore = "unofficial opcodes aren't implemented yet"]
+fn cpu_test_unofficial() {
+    run(usize::MAX);
+}
@@ -1,5 +1,8 @@
 use clap::{arg, command, Command};
 use duct::cmd;
+use std::fmt::Write;
+
+mod romdb;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = command!()
@@ -12,6 +15,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .required(false),
                 ),
         )
+        .subcommand(
+            Command::new("romdb")
+                .about("generates fete's ROM database from the NES 2.0 XML database")
+                .arg(arg!(<xml> "path to nes20db.xml"))
+                .arg(
+                    arg!(-o --out <path> "where to write the generated table")
+                        .default_value("src/romdb/entries.rs"),
+                ),
+        )
         .get_matches();
 
     if let Some(sub) = matches.subcommand_matches("test") {
@@ -40,5 +52,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             .run()?;
     }
 
+    if let Some(sub) = matches.subcommand_matches("romdb") {
+        let xml = std::fs::read_to_string(sub.get_one::<String>("xml").unwrap())?;
+        let out = sub.get_one::<String>("out").unwrap();
+
+        let entries = romdb::parse(&xml)?;
+        let mut src = String::from(
+            "// @generated by `cargo xtask romdb`; do not edit.\n\n\
+             #![allow(clippy::unreadable_literal)]\n\n\
+             #[allow(unused_imports)]\n\
+             use crate::rom::{Mirroring, Timing};\n\n\
+             use super::Entry;\n\n\
+             #[rustfmt::skip]\n\
+             pub(super) static ENTRIES: &[Entry] = &[\n",
+        );
+        for entry in &entries {
+            writeln!(src, "    {entry},")?;
+        }
+        src.push_str("];\n");
+
+        std::fs::write(out, src)?;
+        println!("wrote {} entries to {out}", entries.len());
+    }
+
     Ok(())
 }
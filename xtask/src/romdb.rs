@@ -0,0 +1,125 @@
+//! A minimal reader for the NES 2.0 XML database (`nes20db.xml`); just enough to pull out the
+//! fields fete's ROM database corrects.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    crc32: u32,
+    mapper: u16,
+    submapper: u8,
+    mirroring: Option<&'static str>,
+    battery: bool,
+    timing: &'static str,
+}
+
+impl fmt::Display for Entry {
+    /// Formats the entry as a `fete::romdb::Entry` literal.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mirroring = self
+            .mirroring
+            .map_or_else(|| String::from("None"), |m| format!("Some(Mirroring::{m})"));
+        write!(
+            f,
+            "Entry {{ crc32: 0x{:08X}, mapper: {}, submapper: {}, mirroring: {mirroring}, battery: {}, timing: Timing::{} }}",
+            self.crc32, self.mapper, self.submapper, self.battery, self.timing,
+        )
+    }
+}
+
+/// Parses every `<game>` in the database, sorted by CRC32. Duplicate CRCs keep the first entry.
+pub fn parse(xml: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for game in xml.split("<game>").skip(1) {
+        let game = game.split("</game>").next().unwrap_or_default();
+        entries.push(parse_game(game)?);
+    }
+
+    entries.sort_by_key(|e| e.crc32);
+    entries.dedup_by_key(|e| e.crc32);
+    Ok(entries)
+}
+
+fn parse_game(game: &str) -> Result<Entry, String> {
+    let rom = tag(game, "rom").ok_or("game without a <rom>")?;
+    let pcb = tag(game, "pcb").ok_or("game without a <pcb>")?;
+    let console = tag(game, "console");
+
+    let crc32 = attr(rom, "crc32").ok_or("<rom> without a crc32")?;
+    let crc32 = u32::from_str_radix(crc32, 16).map_err(|e| format!("bad crc32 {crc32}: {e}"))?;
+    let number = |tag: &str, name: &str| -> Result<u16, String> {
+        attr(tag, name)
+            .unwrap_or("0")
+            .parse()
+            .map_err(|e| format!("bad {name} for {crc32:08X}: {e}"))
+    };
+
+    Ok(Entry {
+        crc32,
+        mapper: number(pcb, "mapper")?,
+        submapper: u8::try_from(number(pcb, "submapper")?).map_err(|e| e.to_string())?,
+        mirroring: match attr(pcb, "mirroring") {
+            Some("H") => Some("Horizontal"),
+            Some("V") => Some("Vertical"),
+            Some("4") => Some("FourScreen"),
+            _ => None,
+        },
+        battery: attr(pcb, "battery") == Some("1"),
+        timing: match console.map_or(Ok(0), |c| number(c, "region"))? {
+            1 => "Pal",
+            2 => "MultiRegion",
+            3 => "Dendy",
+            _ => "Ntsc",
+        },
+    })
+}
+
+/// The attributes of the first `<name .../>` tag.
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    // keep the leading space, so every attribute is preceded by one
+    let start = xml.find(&format!("<{name} "))? + name.len() + 1;
+    let len = xml[start..].find('>')?;
+    Some(xml[start..start + len].trim_end_matches('/'))
+}
+
+fn attr<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let start = attrs.find(&format!(" {name}=\""))? + name.len() + 3;
+    let len = attrs[start..].find('"')?;
+    Some(&attrs[start..start + len])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+  <game>
+    <!-- B.nes -->
+    <prgrom size="32768" crc32="11111111"/>
+    <rom size="40960" crc32="B0000000" sha1="00"/>
+    <pcb mapper="1" submapper="0" mirroring="H" battery="1"/>
+    <console type="0" region="1"/>
+  </game>
+  <game>
+    <!-- A.nes -->
+    <rom size="40960" crc32="A0000000" sha1="00"/>
+    <pcb mapper="4" submapper="1" mirroring="V" battery="0"/>
+    <console type="0" region="0"/>
+  </game>
+</nes20db>
+"#;
+
+    #[test]
+    fn parses_sorted() {
+        let entries = parse(XML).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].to_string(),
+            "Entry { crc32: 0xA0000000, mapper: 4, submapper: 1, mirroring: Some(Mirroring::Vertical), battery: false, timing: Timing::Ntsc }"
+        );
+        assert_eq!(entries[1].crc32, 0xB000_0000);
+        assert!(entries[1].battery);
+        assert_eq!(entries[1].timing, "Pal");
+    }
+}